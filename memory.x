@@ -1,7 +1,11 @@
 MEMORY
 {
-  /* Leave 8k for the default bootloader on the Feather M0 */
-  FLASH (rx) : ORIGIN = 0x00000000 + 8K, LENGTH = 256K - 8K
+  /* Leave 8k for the default bootloader on the Feather M0, and the last 1k (four rows) for
+     settings */
+  FLASH (rx) : ORIGIN = 0x00000000 + 8K, LENGTH = 256K - 8K - 1K
+  SETTINGS (r) : ORIGIN = 0x00000000 + 256K - 1K, LENGTH = 1K
   RAM (xrw)  : ORIGIN = 0x20000000, LENGTH = 32K
 }
 _stack_start = ORIGIN(RAM) + LENGTH(RAM);
+_settings_start = ORIGIN(SETTINGS);
+_settings_end = ORIGIN(SETTINGS) + LENGTH(SETTINGS);
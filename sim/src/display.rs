@@ -0,0 +1,257 @@
+//! A fake HT16K33 that decodes the I2C traffic the firmware sends it, and renders the
+//! 14-segment digits as ASCII art.
+
+use embedded_hal::blocking::i2c;
+use std::{cell::RefCell, convert::Infallible, rc::Rc};
+
+/// Number of digits on the quad alphanumeric display
+pub const DIGITS: usize = 4;
+
+const SYSTEM_SETUP_CMD: u8 = 0x20;
+const BLINK_CMD: u8 = 0x80;
+const BRIGHTNESS_CMD: u8 = 0xE0;
+/// Display RAM is 16 bytes, 2 per row
+const RAM_SIZE: usize = 16;
+
+/// Segment bits, matching the font in `ht16k33.rs`
+const SEG_A: u16 = 1 << 0;
+const SEG_B: u16 = 1 << 1;
+const SEG_C: u16 = 1 << 2;
+const SEG_D: u16 = 1 << 3;
+const SEG_E: u16 = 1 << 4;
+const SEG_F: u16 = 1 << 5;
+const SEG_G1: u16 = 1 << 6;
+const SEG_G2: u16 = 1 << 7;
+const SEG_H: u16 = 1 << 8;
+const SEG_J: u16 = 1 << 9;
+const SEG_K: u16 = 1 << 10;
+const SEG_L: u16 = 1 << 11;
+const SEG_M: u16 = 1 << 12;
+const SEG_N: u16 = 1 << 13;
+const SEG_DP: u16 = 1 << 14;
+
+/// Where each segment is drawn in a digit's 5x8 cell: `(segment, row, column, character)`
+const SEGMENT_ART: [(u16, usize, usize, char); 25] = [
+    (SEG_A, 0, 1, '-'),
+    (SEG_A, 0, 2, '-'),
+    (SEG_A, 0, 3, '-'),
+    (SEG_A, 0, 4, '-'),
+    (SEG_A, 0, 5, '-'),
+    (SEG_F, 1, 0, '|'),
+    (SEG_H, 1, 1, '\\'),
+    (SEG_J, 1, 3, '|'),
+    (SEG_K, 1, 5, '/'),
+    (SEG_B, 1, 6, '|'),
+    (SEG_G1, 2, 1, '-'),
+    (SEG_G1, 2, 2, '-'),
+    (SEG_G2, 2, 4, '-'),
+    (SEG_G2, 2, 5, '-'),
+    (SEG_E, 3, 0, '|'),
+    (SEG_L, 3, 1, '/'),
+    (SEG_M, 3, 3, '|'),
+    (SEG_N, 3, 5, '\\'),
+    (SEG_C, 3, 6, '|'),
+    (SEG_D, 4, 1, '-'),
+    (SEG_D, 4, 2, '-'),
+    (SEG_D, 4, 3, '-'),
+    (SEG_D, 4, 4, '-'),
+    (SEG_D, 4, 5, '-'),
+    (SEG_DP, 4, 7, '.'),
+];
+const CELL_ROWS: usize = 5;
+const CELL_COLUMNS: usize = 8;
+
+/// Everything the HT16K33 has been told so far
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DisplayState {
+    /// Display RAM, as sent over I2C
+    pub ram: [u8; RAM_SIZE],
+    /// Whether the oscillator is off
+    pub standby: bool,
+    /// Whether the display is turned on
+    pub display_on: bool,
+    /// Blink setting, 0 (off) through 3
+    pub blink: u8,
+    /// Dimming level, 0 through 15
+    pub brightness: u8,
+    /// Number of I2C writes received
+    pub writes: usize,
+}
+
+impl Default for DisplayState {
+    fn default() -> Self {
+        Self {
+            ram: [0; RAM_SIZE],
+            standby: true,
+            display_on: false,
+            blink: 0,
+            brightness: 15,
+            writes: 0,
+        }
+    }
+}
+
+impl DisplayState {
+    /// The 16 bit segment word for the given digit
+    pub fn digit(&self, n: usize) -> u16 {
+        u16::from(self.ram[2 * n]) | (u16::from(self.ram[2 * n + 1]) << 8)
+    }
+
+    /// Whether anything would be lit on the physical display
+    pub fn is_lit(&self) -> bool {
+        !self.standby && self.display_on
+    }
+
+    /// Renders the digits as ASCII art, or blank if the display isn't lit
+    pub fn render(&self) -> String {
+        let mut digits = [0; DIGITS];
+        if self.is_lit() {
+            for (n, digit) in digits.iter_mut().enumerate() {
+                *digit = self.digit(n);
+            }
+        }
+        render_digits(&digits)
+    }
+
+    /// Applies a single I2C write addressed to the display
+    fn apply(&mut self, bytes: &[u8]) {
+        self.writes += 1;
+        let (command, data) = match bytes.split_first() {
+            Some(split) => split,
+            None => return,
+        };
+
+        match command & 0xF0 {
+            SYSTEM_SETUP_CMD => self.standby = command & 0x01 == 0,
+            BLINK_CMD => {
+                self.display_on = command & 0x01 != 0;
+                self.blink = (command >> 1) & 0x03;
+            }
+            BRIGHTNESS_CMD => self.brightness = command & 0x0F,
+            _ if *command < RAM_SIZE as u8 => {
+                // Display data, auto-incrementing from the given address
+                for (offset, byte) in data.iter().enumerate() {
+                    let address = (*command as usize + offset) % RAM_SIZE;
+                    self.ram[address] = *byte;
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Renders 14-segment digit words as lines of ASCII art
+pub fn render_digits(digits: &[u16]) -> String {
+    let mut rows = vec![vec![' '; CELL_COLUMNS * digits.len()]; CELL_ROWS];
+    for (n, digit) in digits.iter().enumerate() {
+        for (segment, row, column, c) in SEGMENT_ART.iter() {
+            if digit & segment != 0 {
+                rows[*row][n * CELL_COLUMNS + column] = *c;
+            }
+        }
+    }
+
+    rows.iter()
+        .map(|row| row.iter().collect::<String>().trim_end().to_string())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// An I2C bus with nothing but a fake HT16K33 on it
+#[derive(Clone)]
+pub struct FakeHt16k33 {
+    address: u8,
+    state: Rc<RefCell<DisplayState>>,
+}
+
+impl FakeHt16k33 {
+    /// Creates a fake display answering on the given address
+    pub fn new(address: u8) -> Self {
+        Self {
+            address,
+            state: Rc::default(),
+        }
+    }
+
+    /// A snapshot of the display's current state
+    pub fn state(&self) -> DisplayState {
+        self.state.borrow().clone()
+    }
+}
+
+impl i2c::Write for FakeHt16k33 {
+    type Error = Infallible;
+
+    fn write(&mut self, address: u8, bytes: &[u8]) -> Result<(), Infallible> {
+        if address == self.address {
+            self.state.borrow_mut().apply(bytes);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use embedded_hal::blocking::i2c::Write;
+    use oven_temp_rs::ht16k33::{BlinkRate, Brightness, DisplayState, HT16K33};
+
+    #[test]
+    fn renders_segments() {
+        let eight = SEG_A | SEG_B | SEG_C | SEG_D | SEG_E | SEG_F | SEG_G1 | SEG_G2;
+        let x = SEG_H | SEG_K | SEG_L | SEG_N;
+        let plus = SEG_G1 | SEG_G2 | SEG_J | SEG_M | SEG_DP;
+        assert_eq!(
+            render_digits(&[eight, x, plus, 0]),
+            [
+                " -----",
+                "|     |  \\   /     |",
+                " -- --           -- --",
+                "|     |  /   \\     |",
+                " -----                 .",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn decodes_driver_traffic() {
+        let mut bus = FakeHt16k33::new(0x70);
+        let mut display = HT16K33::init(0x70, &mut bus).unwrap();
+        let state = bus.state();
+        assert!(state.is_lit());
+        assert_eq!(state.blink, 0);
+        assert_eq!(state.brightness, 15);
+
+        display
+            .set_brightness(Brightness::new(1).unwrap(), &mut bus)
+            .unwrap();
+        display.write_digit_ascii(0, 'I', false);
+        display.write_digit_value(1, 7, true);
+        display.write_display(&mut bus).unwrap();
+        let state = bus.state();
+        assert_eq!(state.brightness, 1);
+        assert_eq!(state.digit(0), SEG_J | SEG_M);
+        assert_eq!(state.digit(1), SEG_A | SEG_B | SEG_C | SEG_DP);
+        assert_eq!(state.digit(2), 0);
+
+        display.set_blink_rate(BlinkRate::OneHz, &mut bus).unwrap();
+        assert_eq!(bus.state().blink, 2);
+
+        display.set_state(DisplayState::Standby, &mut bus).unwrap();
+        assert!(!bus.state().is_lit());
+        assert_eq!(bus.state().render(), render_digits(&[0; DIGITS]));
+    }
+
+    #[test]
+    fn partial_writes_auto_increment() {
+        let mut bus = FakeHt16k33::new(0x70);
+        bus.write(0x70, &[0x04, 0xAA, 0x55]).unwrap();
+        // Other devices on the bus are ignored
+        bus.write(0x71, &[0x00, 0xFF]).unwrap();
+        let state = bus.state();
+        assert_eq!(state.digit(2), 0x55AA);
+        assert_eq!(state.digit(0), 0);
+        assert_eq!(state.writes, 1);
+    }
+}
@@ -0,0 +1,317 @@
+//! Simulated peripherals the firmware's `App` runs against: a thermocouple amplifier, battery and
+//! photoresistor read through the ADC, the status LED, the menu button, and a delay that advances
+//! simulated time.
+
+use embedded_hal::{
+    adc::{Channel, OneShot},
+    blocking::delay::DelayMs,
+    digital::v2::{InputPin, OutputPin},
+};
+use oven_temp_rs::{
+    temperature::Temperature,
+    thermocouple::{ad8495_output, DEFAULT_COLD_JUNCTION},
+};
+use std::{
+    cell::{Cell, RefCell},
+    convert::Infallible,
+    rc::Rc,
+    thread,
+    time::Duration,
+};
+
+/// 12 bit ADC
+const ADC_FULLSCALE: f32 = 4095.;
+/// The ADC reference, VDDA / 2 with a digital gain of 1/2
+const ADC_REF_VOLTAGE: f32 = 3.3;
+/// The photoresistor the firmware expects: a GL5528 from 3.3V to the pin, 10k from the pin to
+/// ground
+const LDR_FIXED_OHMS: f32 = 10_000.;
+const LDR_R10_OHMS: f32 = 10_000.;
+const LDR_GAMMA: f32 = 0.7;
+
+/// Something with a temperature that changes over (simulated) time
+pub trait TemperatureSource {
+    /// The temperature in Celsius at the given time since boot
+    fn celsius_at(&mut self, time_ms: u64) -> f32;
+}
+
+/// A temperature profile linearly interpolated between `(time_ms, celsius)` keyframes
+pub struct Keyframes {
+    frames: Vec<(u64, f32)>,
+}
+
+impl Keyframes {
+    /// Creates a profile from keyframes, which must be sorted by time
+    pub fn new(frames: Vec<(u64, f32)>) -> Self {
+        assert!(!frames.is_empty(), "need at least one keyframe");
+        assert!(
+            frames.windows(2).all(|w| w[0].0 <= w[1].0),
+            "keyframes must be sorted by time"
+        );
+        Self { frames }
+    }
+}
+
+impl TemperatureSource for Keyframes {
+    fn celsius_at(&mut self, time_ms: u64) -> f32 {
+        let next = self.frames.iter().position(|(t, _)| *t > time_ms);
+        match next {
+            Some(0) => self.frames[0].1,
+            None => self.frames[self.frames.len() - 1].1,
+            Some(i) => {
+                let (t0, c0) = self.frames[i - 1];
+                let (t1, c1) = self.frames[i];
+                let fraction = (time_ms - t0) as f32 / (t1 - t0) as f32;
+                c0 + (c1 - c0) * fraction
+            }
+        }
+    }
+}
+
+/// Simulated time since boot, shared between the peripherals
+pub type Clock = Rc<Cell<u64>>;
+
+/// The raw ADC reading of the AD8495's output at the given temperature, with the board at room
+/// temperature
+pub fn thermocouple_reading(celsius: f32) -> u16 {
+    voltage_reading(ad8495_output(
+        Temperature::from_celsius(celsius),
+        DEFAULT_COLD_JUNCTION,
+    ))
+}
+
+/// The raw ADC reading of the given voltage, clipped to the ADC's range
+pub fn voltage_reading(voltage: f32) -> u16 {
+    let reading = (voltage / ADC_REF_VOLTAGE * ADC_FULLSCALE).round();
+    reading.clamp(0., ADC_FULLSCALE) as u16
+}
+
+/// The raw ADC reading of the photoresistor divider in the given light
+pub fn light_reading(lux: f32) -> u16 {
+    if lux <= 0. {
+        return 0;
+    }
+    let ohms = LDR_R10_OHMS * (10. / lux).powf(LDR_GAMMA);
+    voltage_reading(ADC_REF_VOLTAGE * LDR_FIXED_OHMS / (ohms + LDR_FIXED_OHMS))
+}
+
+/// An ADC with a thermocouple amplifier, a battery voltage divider and a photoresistor attached
+pub struct SimAdc {
+    clock: Clock,
+    oven: Rc<RefCell<dyn TemperatureSource>>,
+    battery_voltage: Rc<Cell<f32>>,
+    lux: Rc<Cell<f32>>,
+}
+
+impl SimAdc {
+    /// Creates an ADC reading the given oven at the current simulated time
+    pub fn new(clock: Clock, oven: Rc<RefCell<dyn TemperatureSource>>) -> Self {
+        Self {
+            clock,
+            oven,
+            battery_voltage: Rc::new(Cell::new(4.0)),
+            // A lit kitchen
+            lux: Rc::new(Cell::new(300.)),
+        }
+    }
+
+    /// A handle for changing the simulated battery voltage
+    pub fn battery_voltage(&self) -> Rc<Cell<f32>> {
+        self.battery_voltage.clone()
+    }
+
+    /// A handle for changing the simulated light falling on the photoresistor
+    pub fn lux(&self) -> Rc<Cell<f32>> {
+        self.lux.clone()
+    }
+}
+
+/// The ADC pin the thermocouple amplifier is connected to
+pub struct ThermPin;
+
+impl Channel<SimAdc> for ThermPin {
+    type ID = u8;
+
+    fn channel() -> u8 {
+        4
+    }
+}
+
+/// The ADC pin the battery voltage divider is connected to
+pub struct BattPin;
+
+impl Channel<SimAdc> for BattPin {
+    type ID = u8;
+
+    fn channel() -> u8 {
+        9
+    }
+}
+
+/// The ADC pin the photoresistor is connected to
+pub struct LightPin;
+
+impl Channel<SimAdc> for LightPin {
+    type ID = u8;
+
+    fn channel() -> u8 {
+        1
+    }
+}
+
+impl OneShot<SimAdc, u16, ThermPin> for SimAdc {
+    type Error = Infallible;
+
+    fn read(&mut self, _pin: &mut ThermPin) -> nb::Result<u16, Infallible> {
+        let celsius = self.oven.borrow_mut().celsius_at(self.clock.get());
+        Ok(thermocouple_reading(celsius))
+    }
+}
+
+impl OneShot<SimAdc, u16, BattPin> for SimAdc {
+    type Error = Infallible;
+
+    fn read(&mut self, _pin: &mut BattPin) -> nb::Result<u16, Infallible> {
+        // external HW divides the battery voltage by two
+        Ok(voltage_reading(self.battery_voltage.get() / 2.))
+    }
+}
+
+impl OneShot<SimAdc, u16, LightPin> for SimAdc {
+    type Error = Infallible;
+
+    fn read(&mut self, _pin: &mut LightPin) -> nb::Result<u16, Infallible> {
+        Ok(light_reading(self.lux.get()))
+    }
+}
+
+/// The red status LED
+#[derive(Clone, Default)]
+pub struct SimLed {
+    on: Rc<Cell<bool>>,
+}
+
+impl SimLed {
+    /// Whether the LED is currently lit
+    pub fn is_on(&self) -> bool {
+        self.on.get()
+    }
+}
+
+impl OutputPin for SimLed {
+    type Error = Infallible;
+
+    fn set_low(&mut self) -> Result<(), Infallible> {
+        self.on.set(false);
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Infallible> {
+        self.on.set(true);
+        Ok(())
+    }
+}
+
+/// The menu button, which nobody presses in the simulator
+#[derive(Clone, Copy, Default)]
+pub struct SimButton;
+
+impl InputPin for SimButton {
+    type Error = Infallible;
+
+    fn is_high(&self) -> Result<bool, Infallible> {
+        // Pulled up
+        Ok(true)
+    }
+
+    fn is_low(&self) -> Result<bool, Infallible> {
+        Ok(false)
+    }
+}
+
+/// A delay that advances simulated time, optionally sleeping a scaled-down amount of real time
+pub struct SimDelay {
+    clock: Clock,
+    speed: Option<f64>,
+    on_delay: Box<dyn FnMut(u64)>,
+}
+
+impl SimDelay {
+    /// Creates a delay advancing the given clock
+    ///
+    /// # Arguments
+    /// * `clock`: The simulated time to advance
+    /// * `speed`: How many times faster than real time to run, or `None` to not sleep at all
+    /// * `on_delay`: Called with the current time at the start of every delay, while the
+    ///   firmware is "asleep" and whatever it put on the display is visible
+    pub fn new(clock: Clock, speed: Option<f64>, on_delay: Box<dyn FnMut(u64)>) -> Self {
+        Self {
+            clock,
+            speed,
+            on_delay,
+        }
+    }
+}
+
+impl DelayMs<u32> for SimDelay {
+    fn delay_ms(&mut self, ms: u32) {
+        (self.on_delay)(self.clock.get());
+        if let Some(speed) = self.speed {
+            thread::sleep(Duration::from_secs_f64(f64::from(ms) / 1000. / speed));
+        }
+        self.clock.set(self.clock.get() + u64::from(ms));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn keyframes_interpolate() {
+        let mut profile = Keyframes::new(vec![(1_000, 20.), (3_000, 40.), (3_000, 100.)]);
+        assert_eq!(profile.celsius_at(0), 20.);
+        assert_eq!(profile.celsius_at(2_000), 30.);
+        assert!((profile.celsius_at(2_999) - 39.99).abs() < 0.001);
+        assert_eq!(profile.celsius_at(3_000), 100.);
+        assert_eq!(profile.celsius_at(u64::MAX), 100.);
+    }
+
+    #[test]
+    fn adc_readings() {
+        assert_eq!(thermocouple_reading(0.), 1554);
+        assert_eq!(thermocouple_reading(200.), 2790);
+        // The amplifier output clips at the ADC reference
+        assert_eq!(thermocouple_reading(1_000.), 4095);
+        assert_eq!(voltage_reading(-0.1), 0);
+
+        let clock = Clock::default();
+        let oven = Rc::new(RefCell::new(Keyframes::new(vec![(0, 0.), (1_000, 200.)])));
+        let mut adc = SimAdc::new(clock.clone(), oven);
+        let therm: u16 = adc.read(&mut ThermPin).unwrap();
+        assert_eq!(therm, 1554);
+        clock.set(1_000);
+        let therm: u16 = adc.read(&mut ThermPin).unwrap();
+        assert_eq!(therm, 2790);
+
+        adc.battery_voltage().set(3.3);
+        let batt: u16 = adc.read(&mut BattPin).unwrap();
+        assert_eq!(batt, 2048);
+    }
+
+    #[test]
+    fn delay_advances_clock() {
+        let clock = Clock::default();
+        let calls = Rc::new(Cell::new(0));
+        let calls_in_hook = calls.clone();
+        let mut delay = SimDelay::new(
+            clock.clone(),
+            None,
+            Box::new(move |_| calls_in_hook.set(calls_in_hook.get() + 1)),
+        );
+        delay.delay_ms(1_500);
+        delay.delay_ms(500);
+        assert_eq!(clock.get(), 2_000);
+        assert_eq!(calls.get(), 2);
+    }
+}
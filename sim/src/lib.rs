@@ -0,0 +1,9 @@
+//! Host-side simulation of the oven temperature monitor, for demoing and debugging the firmware's
+//! behavior without a Feather M0.
+
+#![warn(rust_2018_idioms)]
+#![warn(clippy::all)]
+
+pub mod display;
+pub mod hardware;
+pub mod oven;
@@ -0,0 +1,182 @@
+//! Runs the oven temperature monitor's firmware against a simulated oven, drawing the display in
+//! the terminal.
+
+#![warn(rust_2018_idioms)]
+#![warn(clippy::all)]
+
+use oven_temp_rs::{
+    app::{App, AppConfig},
+    temperature::TemperatureUnit,
+};
+use oven_temp_sim::{
+    display::FakeHt16k33,
+    hardware::{BattPin, Clock, LightPin, SimAdc, SimButton, SimDelay, SimLed, ThermPin},
+    oven::{Oven, OvenParams, Scenario},
+};
+use std::{cell::RefCell, env, process, rc::Rc};
+
+const USAGE: &str = "\
+Usage: oven-temp-sim [options]
+
+Options:
+    --speed <x>      Run <x> times faster than real time (default 120)
+    --fast           Don't sleep at all, just print every change
+    --scenario <s>   What the cook does: preheat, bake (default), cool-down or broil
+    --minutes <n>    Simulated minutes to run for (default: the length of the scenario)
+    --celsius        Display temperatures in Celsius
+    --show-unit      Show a trailing C/F on the display
+    --cook-time      Alternate the temperature with how long the oven's been at temperature
+    --lux <n>        Fit a light sensor, in a kitchen this many lux bright
+    --plain          Print each new frame below the last instead of redrawing in place
+";
+
+const MS_PER_MINUTE: u64 = 60_000;
+
+/// Command line options
+struct Options {
+    speed: Option<f64>,
+    scenario: Scenario,
+    minutes: Option<u64>,
+    plain: bool,
+    lux: Option<f32>,
+    config: AppConfig,
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut options = Options {
+        speed: Some(120.),
+        scenario: Scenario::bake(),
+        minutes: None,
+        plain: false,
+        lux: None,
+        config: AppConfig::default(),
+    };
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().ok_or_else(|| format!("{} needs a value", name));
+        match arg.as_str() {
+            "--speed" => {
+                let speed = value("--speed")?;
+                options.speed = Some(
+                    speed
+                        .parse()
+                        .map_err(|_| format!("invalid speed '{}'", speed))?,
+                );
+            }
+            "--fast" => options.speed = None,
+            "--scenario" => {
+                let name = value("--scenario")?;
+                options.scenario = Scenario::by_name(&name)
+                    .ok_or_else(|| format!("unknown scenario '{}'", name))?;
+            }
+            "--minutes" => {
+                let minutes = value("--minutes")?;
+                options.minutes = Some(
+                    minutes
+                        .parse()
+                        .map_err(|_| format!("invalid minutes '{}'", minutes))?,
+                );
+            }
+            "--celsius" => options.config.unit = TemperatureUnit::Celsius,
+            "--show-unit" => options.config.show_unit = true,
+            "--cook-time" => options.config.show_cook_time = true,
+            "--lux" => {
+                let lux = value("--lux")?;
+                options.lux = Some(lux.parse().map_err(|_| format!("invalid lux '{}'", lux))?);
+            }
+            "--plain" => options.plain = true,
+            "-h" | "--help" => {
+                print!("{}", USAGE);
+                process::exit(0);
+            }
+            _ => return Err(format!("unknown option '{}'", arg)),
+        }
+    }
+
+    Ok(options)
+}
+
+fn main() {
+    let options = parse_args().unwrap_or_else(|err| {
+        eprint!("error: {}\n\n{}", err, USAGE);
+        process::exit(1);
+    });
+
+    let clock = Clock::default();
+    let oven = Rc::new(RefCell::new(Oven::new(
+        OvenParams::default(),
+        &options.scenario,
+    )));
+    let display = FakeHt16k33::new(0x70);
+    let led = SimLed::default();
+
+    // Redraw whenever the firmware goes to sleep with something new on the display
+    let delay = {
+        let display = display.clone();
+        let led = led.clone();
+        let oven = oven.clone();
+        let plain = options.plain;
+        let unit = options.config.unit;
+        let mut last_frame = None;
+        SimDelay::new(
+            clock.clone(),
+            options.speed,
+            Box::new(move |time_ms| {
+                let state = display.state();
+                let frame = (state.render(), led.is_on(), state.brightness);
+                if last_frame.as_ref() == Some(&frame) {
+                    return;
+                }
+                let oven = oven.borrow();
+                let celsius = oven.air();
+                let oven_temp = match unit {
+                    TemperatureUnit::Celsius => celsius,
+                    TemperatureUnit::Fahrenheit => celsius * 9. / 5. + 32.,
+                };
+                if !plain {
+                    // clear the terminal and go home
+                    print!("\x1b[2J\x1b[H");
+                }
+                println!(
+                    "t={:02}:{:02}  oven={:.1}{}{}{}  led={}  brightness={}\n{}\n",
+                    time_ms / MS_PER_MINUTE,
+                    time_ms / 1_000 % 60,
+                    oven_temp,
+                    unit.symbol(),
+                    if oven.heating() { " heating" } else { "" },
+                    if oven.door_open() { " door-open" } else { "" },
+                    if frame.1 { "ON" } else { "off" },
+                    frame.2,
+                    frame.0
+                );
+                last_frame = Some(frame);
+            }),
+        )
+    };
+
+    let adc = SimAdc::new(clock.clone(), oven);
+    if let Some(lux) = options.lux {
+        adc.lux().set(lux);
+    }
+    let mut app = App::init(
+        display,
+        adc,
+        ThermPin,
+        BattPin,
+        options.lux.map(|_| LightPin),
+        led,
+        SimButton,
+        delay,
+        options.config,
+    );
+
+    let duration_ms = options
+        .minutes
+        .map_or(options.scenario.duration_ms, |minutes| {
+            minutes * MS_PER_MINUTE
+        });
+    while clock.get() < duration_ms {
+        app.step();
+    }
+}
@@ -0,0 +1,490 @@
+//! A lumped thermal model of an electric oven with a bang-bang thermostat, for producing
+//! realistic temperature traces.
+//!
+//! The oven is two thermal masses: the air (plus racks and elements) the thermocouple sits in, and
+//! the walls. The elements heat the air, the air exchanges heat with the walls, and the walls lose
+//! heat to the kitchen. Opening the door dumps heat from the air straight into the kitchen.
+
+use crate::hardware::TemperatureSource;
+
+/// Simulation time step
+const STEP_MS: u64 = 100;
+const MS_PER_MINUTE: u64 = 60_000;
+
+/// Converts Fahrenheit to Celsius, since scenarios are easiest to read in oven dial units
+pub fn fahrenheit(f: f32) -> f32 {
+    (f - 32.) * 5. / 9.
+}
+
+/// Physical properties of the oven
+#[derive(Copy, Clone, Debug)]
+pub struct OvenParams {
+    /// Kitchen temperature, C
+    pub ambient: f32,
+    /// Bake element power, W
+    pub bake_power: f32,
+    /// Broil element power, W
+    pub broil_power: f32,
+    /// Heat capacity of the air, racks and elements, J/C
+    pub air_capacity: f32,
+    /// Heat capacity of the walls, J/C
+    pub wall_capacity: f32,
+    /// Heat transfer between the air and walls, W/C
+    pub air_to_wall: f32,
+    /// Heat lost from the walls to the kitchen, W/C
+    pub wall_to_ambient: f32,
+    /// Extra heat lost from the air to the kitchen with the door open, W/C
+    pub door_open_loss: f32,
+    /// Total swing of the thermostat around its set point, C
+    pub thermostat_swing: f32,
+}
+
+impl Default for OvenParams {
+    fn default() -> Self {
+        Self {
+            ambient: 21.,
+            bake_power: 2_400.,
+            broil_power: 3_400.,
+            air_capacity: 4_000.,
+            wall_capacity: 9_000.,
+            air_to_wall: 60.,
+            wall_to_ambient: 8.,
+            door_open_loss: 120.,
+            thermostat_swing: 20.,
+        }
+    }
+}
+
+/// Something the cook does to the oven
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Action {
+    /// Turn the dial to bake at the given temperature (C)
+    Bake(f32),
+    /// Turn the dial to broil, which runs the top element up to the given temperature (C)
+    Broil(f32),
+    /// Turn the oven off
+    Off,
+    /// Open the door
+    OpenDoor,
+    /// Close the door
+    CloseDoor,
+}
+
+/// A scripted cooking session
+#[derive(Clone, Debug)]
+pub struct Scenario {
+    /// Short name, as used on the command line
+    pub name: &'static str,
+    /// Oven temperature (air and walls) at the start, C
+    pub start: f32,
+    /// What happens when, in ms since the start
+    pub actions: Vec<(u64, Action)>,
+    /// How long the scenario lasts, ms
+    pub duration_ms: u64,
+}
+
+impl Scenario {
+    /// Preheat from cold to 350F
+    pub fn preheat() -> Self {
+        Self {
+            name: "preheat",
+            start: OvenParams::default().ambient,
+            actions: vec![(MS_PER_MINUTE, Action::Bake(fahrenheit(350.)))],
+            duration_ms: 20 * MS_PER_MINUTE,
+        }
+    }
+
+    /// Preheat to 350F, bake for 40 minutes checking on the food once, then turn the oven off
+    pub fn bake() -> Self {
+        Self {
+            name: "bake",
+            start: OvenParams::default().ambient,
+            actions: vec![
+                (MS_PER_MINUTE, Action::Bake(fahrenheit(350.))),
+                (35 * MS_PER_MINUTE, Action::OpenDoor),
+                (35 * MS_PER_MINUTE + 20_000, Action::CloseDoor),
+                (55 * MS_PER_MINUTE, Action::Off),
+            ],
+            duration_ms: 150 * MS_PER_MINUTE,
+        }
+    }
+
+    /// Turn off an oven that's been holding 350F, and let it cool
+    pub fn cool_down() -> Self {
+        Self {
+            name: "cool-down",
+            start: fahrenheit(350.),
+            actions: vec![(0, Action::Off)],
+            duration_ms: 120 * MS_PER_MINUTE,
+        }
+    }
+
+    /// Broil at 550F for 15 minutes
+    pub fn broil() -> Self {
+        Self {
+            name: "broil",
+            start: OvenParams::default().ambient,
+            actions: vec![
+                (MS_PER_MINUTE, Action::Broil(fahrenheit(550.))),
+                (16 * MS_PER_MINUTE, Action::Off),
+            ],
+            duration_ms: 30 * MS_PER_MINUTE,
+        }
+    }
+
+    /// All of the canned scenarios
+    pub fn all() -> Vec<Self> {
+        vec![
+            Self::preheat(),
+            Self::bake(),
+            Self::cool_down(),
+            Self::broil(),
+        ]
+    }
+
+    /// Looks up a canned scenario by name
+    pub fn by_name(name: &str) -> Option<Self> {
+        Self::all().into_iter().find(|s| s.name == name)
+    }
+}
+
+/// The oven's thermostat setting
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Mode {
+    Off,
+    Bake(f32),
+    Broil(f32),
+}
+
+/// The simulated oven
+pub struct Oven {
+    params: OvenParams,
+    actions: Vec<(u64, Action)>,
+    next_action: usize,
+    time_ms: u64,
+    air: f32,
+    wall: f32,
+    mode: Mode,
+    heating: bool,
+    door_open: bool,
+}
+
+impl Oven {
+    /// Creates an oven that will play out the given scenario
+    pub fn new(params: OvenParams, scenario: &Scenario) -> Self {
+        Self {
+            params,
+            actions: scenario.actions.clone(),
+            next_action: 0,
+            time_ms: 0,
+            air: scenario.start,
+            wall: scenario.start,
+            mode: Mode::Off,
+            heating: false,
+            door_open: false,
+        }
+    }
+
+    /// The air temperature the thermocouple sees, C
+    pub fn air(&self) -> f32 {
+        self.air
+    }
+
+    /// Whether an element is currently on
+    pub fn heating(&self) -> bool {
+        self.heating
+    }
+
+    /// Whether the door is open
+    pub fn door_open(&self) -> bool {
+        self.door_open
+    }
+
+    /// Advances the simulation to the given time. Time never goes backwards.
+    pub fn advance_to(&mut self, time_ms: u64) {
+        while self.time_ms + STEP_MS <= time_ms {
+            self.apply_actions();
+            self.step();
+        }
+    }
+
+    fn apply_actions(&mut self) {
+        while let Some((at, action)) = self.actions.get(self.next_action) {
+            if *at > self.time_ms {
+                break;
+            }
+            match action {
+                Action::Bake(set_point) => self.mode = Mode::Bake(*set_point),
+                Action::Broil(set_point) => self.mode = Mode::Broil(*set_point),
+                Action::Off => self.mode = Mode::Off,
+                Action::OpenDoor => self.door_open = true,
+                Action::CloseDoor => self.door_open = false,
+            }
+            self.next_action += 1;
+        }
+    }
+
+    fn step(&mut self) {
+        let p = &self.params;
+        let (set_point, power) = match self.mode {
+            Mode::Off => (None, 0.),
+            Mode::Bake(set_point) => (Some(set_point), p.bake_power),
+            Mode::Broil(set_point) => (Some(set_point), p.broil_power),
+        };
+
+        // Bang-bang thermostat with hysteresis
+        self.heating = match set_point {
+            None => false,
+            Some(set_point) if self.air <= set_point - p.thermostat_swing / 2. => true,
+            Some(set_point) if self.air >= set_point + p.thermostat_swing / 2. => false,
+            Some(_) => self.heating,
+        };
+
+        let heater = if self.heating { power } else { 0. };
+        let air_to_wall = p.air_to_wall * (self.air - self.wall);
+        let wall_loss = p.wall_to_ambient * (self.wall - p.ambient);
+        let door_loss = if self.door_open {
+            p.door_open_loss * (self.air - p.ambient)
+        } else {
+            0.
+        };
+
+        let dt = STEP_MS as f32 / 1000.;
+        self.air += (heater - air_to_wall - door_loss) / p.air_capacity * dt;
+        self.wall += (air_to_wall - wall_loss) / p.wall_capacity * dt;
+        self.time_ms += STEP_MS;
+    }
+}
+
+impl TemperatureSource for Oven {
+    fn celsius_at(&mut self, time_ms: u64) -> f32 {
+        self.advance_to(time_ms);
+        self.air
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use oven_temp_rs::{
+        oventemp::{OvenTemp, OvenTempEvent, OvenTempState},
+        temperature::Temperature,
+    };
+
+    /// How often the firmware samples the thermocouple
+    const SAMPLE_MS: u64 = 1_000;
+
+    /// Runs the state machine over a scenario, returning each transition and the minute it
+    /// happened in
+    fn transitions(scenario: &Scenario) -> Vec<(u64, OvenTempState)> {
+        let mut oven = Oven::new(OvenParams::default(), scenario);
+        let mut state = OvenTemp::default();
+        let mut transitions = Vec::new();
+        for time_ms in (0..scenario.duration_ms).step_by(SAMPLE_MS as usize) {
+            let temp = Temperature::from_celsius(oven.celsius_at(time_ms));
+            if let Some(transition) = state.check_transition(temp, time_ms) {
+                transitions.push((transition.at / MS_PER_MINUTE, transition.to));
+            }
+        }
+        transitions
+    }
+
+    /// The minutes the oven was reported done preheating in
+    fn preheat_completions(scenario: &Scenario) -> Vec<u64> {
+        let mut oven = Oven::new(OvenParams::default(), scenario);
+        let mut state = OvenTemp::default();
+        let mut completions = Vec::new();
+        for time_ms in (0..scenario.duration_ms).step_by(SAMPLE_MS as usize) {
+            state.check_transition(Temperature::from_celsius(oven.celsius_at(time_ms)), time_ms);
+            while let Some(event) = state.poll_event() {
+                if event == OvenTempEvent::PreheatComplete {
+                    completions.push(time_ms / MS_PER_MINUTE);
+                }
+            }
+        }
+        completions
+    }
+
+    /// (min, max) of the air temperature over the given window of the scenario
+    fn air_range(scenario: &Scenario, from_ms: u64, to_ms: u64) -> (f32, f32) {
+        let mut oven = Oven::new(OvenParams::default(), scenario);
+        let mut range = (f32::MAX, f32::MIN);
+        for time_ms in (from_ms..to_ms).step_by(SAMPLE_MS as usize) {
+            let air = oven.celsius_at(time_ms);
+            range = (range.0.min(air), range.1.max(air));
+        }
+        range
+    }
+
+    #[test]
+    fn thermostat_holds_set_point() {
+        let (low, high) = air_range(&Scenario::preheat(), 15 * MS_PER_MINUTE, 20 * MS_PER_MINUTE);
+        let set_point = fahrenheit(350.);
+        assert!(
+            low > set_point - 12. && high < set_point + 12.,
+            "{} {}",
+            low,
+            high
+        );
+        assert!(
+            high - low > 15.,
+            "thermostat should swing: {} {}",
+            low,
+            high
+        );
+    }
+
+    #[test]
+    fn door_drops_temperature() {
+        let mut oven = Oven::new(OvenParams::default(), &Scenario::bake());
+        oven.advance_to(35 * MS_PER_MINUTE);
+        let before = oven.air();
+        oven.advance_to(35 * MS_PER_MINUTE + 20_000);
+        assert!(oven.door_open());
+        assert!(before - oven.air() > 20., "{} -> {}", before, oven.air());
+    }
+
+    #[test]
+    fn preheat_transitions() {
+        // We boot assuming a hot oven, realize it's cold, then follow it up to temperature
+        assert_eq!(
+            transitions(&Scenario::preheat()),
+            [
+                (0, OvenTempState::CoolingDown),
+                (0, OvenTempState::Off),
+                (1, OvenTempState::HeatingUp),
+                (13, OvenTempState::AtTemp),
+            ]
+        );
+    }
+
+    #[test]
+    fn bake_transitions() {
+        // Opening the door at 35 minutes is recognized as the door, so we stay AtTemp until the
+        // oven's turned off
+        assert_eq!(
+            transitions(&Scenario::bake()),
+            [
+                (0, OvenTempState::CoolingDown),
+                (0, OvenTempState::Off),
+                (1, OvenTempState::HeatingUp),
+                (13, OvenTempState::AtTemp),
+                (59, OvenTempState::CoolingDown),
+                (125, OvenTempState::Off),
+            ]
+        );
+    }
+
+    #[test]
+    fn cool_down_transitions() {
+        assert_eq!(
+            transitions(&Scenario::cool_down()),
+            [(7, OvenTempState::CoolingDown), (72, OvenTempState::Off)]
+        );
+    }
+
+    #[test]
+    fn broil_transitions() {
+        assert_eq!(
+            transitions(&Scenario::broil()),
+            [
+                (0, OvenTempState::CoolingDown),
+                (0, OvenTempState::Off),
+                (1, OvenTempState::HeatingUp),
+                (8, OvenTempState::AtTemp),
+                (28, OvenTempState::CoolingDown),
+            ]
+        );
+    }
+
+    #[test]
+    fn preheat_completes_once() {
+        // The thermostat first cuts out a few minutes after we start showing AtTemp, and opening
+        // the door later doesn't count as preheating again
+        assert_eq!(preheat_completions(&Scenario::preheat()), [17]);
+        assert_eq!(preheat_completions(&Scenario::bake()), [17]);
+        assert!(preheat_completions(&Scenario::cool_down()).is_empty());
+    }
+
+    #[test]
+    fn measures_thermostat_cycles() {
+        // Up until the door opens
+        let scenario = Scenario::bake();
+        let mut oven = Oven::new(OvenParams::default(), &scenario);
+        let mut state = OvenTemp::default();
+        for time_ms in (0..35 * MS_PER_MINUTE).step_by(SAMPLE_MS as usize) {
+            state.check_transition(Temperature::from_celsius(oven.celsius_at(time_ms)), time_ms);
+        }
+        // The thermostat swings evenly around the set point, so we should see exactly that
+        let stats = state.cycle().unwrap();
+        let set_point = fahrenheit(350.);
+        assert!((stats.mean.celsius() - set_point).abs() < 1., "{:?}", stats);
+        assert!(
+            (stats.swing - OvenParams::default().thermostat_swing).abs() < 1.,
+            "{:?}",
+            stats
+        );
+        assert!(
+            (2 * MS_PER_MINUTE..3 * MS_PER_MINUTE).contains(&u64::from(stats.period_ms)),
+            "{:?}",
+            stats
+        );
+        assert!(
+            stats.duty_cycle > 0.3 && stats.duty_cycle < 0.6,
+            "{:?}",
+            stats
+        );
+
+        // Which tells us what the dial is set to
+        let estimate = state.set_point().unwrap();
+        assert!(
+            (estimate.set_point.celsius() - set_point).abs() < 0.5,
+            "{:?}",
+            estimate
+        );
+        assert!(estimate.confidence > 0.9, "{:?}", estimate);
+    }
+
+    #[test]
+    fn door_events() {
+        let scenario = Scenario::bake();
+        let mut oven = Oven::new(OvenParams::default(), &scenario);
+        let mut state = OvenTemp::default();
+        let mut events = Vec::new();
+        for time_ms in (0..scenario.duration_ms).step_by(SAMPLE_MS as usize) {
+            state.check_transition(Temperature::from_celsius(oven.celsius_at(time_ms)), time_ms);
+            while let Some(event) = state.poll_event() {
+                match event {
+                    OvenTempEvent::PreheatComplete | OvenTempEvent::CycleComplete(_) => {}
+                    _ => events.push((time_ms / 1_000, event)),
+                }
+            }
+        }
+        // The door's open from 35:00 to 35:20, and the oven takes a couple of minutes to get back
+        // to where it was
+        assert_eq!(
+            events,
+            [
+                (2_101, OvenTempEvent::DoorOpened),
+                (2_122, OvenTempEvent::DoorClosed { open_ms: 21_000 }),
+                (
+                    2_257,
+                    OvenTempEvent::DoorRecovered {
+                        recovery_ms: 135_000
+                    }
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn scenarios_by_name() {
+        for scenario in Scenario::all() {
+            assert_eq!(
+                Scenario::by_name(scenario.name).unwrap().name,
+                scenario.name
+            );
+        }
+        assert!(Scenario::by_name("sous-vide").is_none());
+    }
+}
@@ -0,0 +1,248 @@
+//! Automatic display brightness from an ambient light sensor.
+//!
+//! The eye judges brightness on a log scale, so lux are mapped to the display's 16 levels
+//! logarithmically. Readings are smoothed so a passing shadow doesn't flicker the display, and the
+//! level only moves once the light has clearly left the current one, so it doesn't hunt back and
+//! forth when the light sits on a boundary.
+
+use crate::menu::MAX_BRIGHTNESS;
+
+/// How lux map to brightness levels, and how quickly the level follows the light
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct AutoBrightnessConfig {
+    /// At or below this many lux the display is at its dimmest
+    pub dark_lux: f32,
+    /// At or above this many lux the display is at its brightest
+    pub bright_lux: f32,
+    /// How far each reading moves the smoothed light level towards it, from `(0, 1]`. Smaller is
+    /// smoother but slower to respond.
+    pub smoothing: f32,
+    /// How far past the edge of the current level the light has to go before the level changes,
+    /// in levels
+    pub hysteresis: f32,
+}
+
+impl Default for AutoBrightnessConfig {
+    fn default() -> Self {
+        Self {
+            // A kitchen lit only by the hall light
+            dark_lux: 1.,
+            // A kitchen with the lights on and the sun coming in
+            bright_lux: 1_000.,
+            smoothing: 0.2,
+            hysteresis: 0.25,
+        }
+    }
+}
+
+impl AutoBrightnessConfig {
+    /// The brightness level for the given light, before rounding
+    #[must_use]
+    pub fn level_for(&self, lux: f32) -> f32 {
+        self.level_for_log(self.log_lux(lux))
+    }
+
+    /// `lux` on a log scale, limited to the range we map
+    fn log_lux(&self, lux: f32) -> f32 {
+        libm::log10f(lux.clamp(self.dark_lux, self.bright_lux))
+    }
+
+    fn level_for_log(&self, log_lux: f32) -> f32 {
+        let dark = libm::log10f(self.dark_lux);
+        let bright = libm::log10f(self.bright_lux);
+        if bright <= dark {
+            return f32::from(MAX_BRIGHTNESS);
+        }
+        (log_lux - dark) / (bright - dark) * f32::from(MAX_BRIGHTNESS)
+    }
+}
+
+/// Picks the display brightness from a stream of light readings
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct AutoBrightness {
+    config: AutoBrightnessConfig,
+    /// The smoothed light, on a log scale
+    log_lux: Option<f32>,
+    level: u8,
+}
+
+impl AutoBrightness {
+    /// Creates a controller that hasn't seen any light yet
+    #[must_use]
+    pub fn new(config: AutoBrightnessConfig) -> Self {
+        Self {
+            config,
+            log_lux: None,
+            level: 0,
+        }
+    }
+
+    /// The level picked, once there's been a reading
+    #[must_use]
+    pub fn level(&self) -> Option<u8> {
+        self.log_lux.map(|_| self.level)
+    }
+
+    /// Adds a light reading
+    ///
+    /// # Returns
+    /// the new brightness level, if it changed (the first reading always does)
+    pub fn update(&mut self, lux: f32) -> Option<u8> {
+        let sample = self.config.log_lux(lux);
+        let first = self.log_lux.is_none();
+        let log_lux = match self.log_lux {
+            Some(log_lux) => log_lux + self.config.smoothing.clamp(0., 1.) * (sample - log_lux),
+            None => sample,
+        };
+        self.log_lux = Some(log_lux);
+
+        let target = self.config.level_for_log(log_lux);
+        let threshold = 0.5 + self.config.hysteresis.max(0.);
+        if first || libm::fabsf(target - f32::from(self.level)) >= threshold {
+            let level = (libm::roundf(target) as u8).min(MAX_BRIGHTNESS);
+            if first || level != self.level {
+                self.level = level;
+                return Some(level);
+            }
+        }
+        None
+    }
+
+    /// Forgets the light seen so far, so the next reading is taken as is
+    pub fn reset(&mut self) {
+        self.log_lux = None;
+    }
+}
+
+/// A photoresistor (LDR) between the supply and an ADC pin, with a fixed resistor from the pin to
+/// ground. More light means less resistance, and a higher voltage at the pin.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Photoresistor {
+    /// The fixed resistor to ground, ohms
+    pub fixed_ohms: f32,
+    /// The photoresistor's resistance at 10 lux, from its datasheet
+    pub r10_ohms: f32,
+    /// How steeply its resistance falls with light, from its datasheet: the slope of log
+    /// resistance against log lux
+    pub gamma: f32,
+}
+
+impl Photoresistor {
+    /// The light falling on the photoresistor
+    ///
+    /// # Arguments
+    /// * `voltage`: The voltage at the pin
+    /// * `supply`: The voltage across the divider
+    ///
+    /// # Returns
+    /// the light in lux. Readings at the rails come out as 0 or infinite.
+    #[must_use]
+    pub fn lux(&self, voltage: f32, supply: f32) -> f32 {
+        if voltage <= 0. {
+            return 0.;
+        }
+        let ohms = self.fixed_ohms * (supply - voltage) / voltage;
+        if ohms <= 0. {
+            return f32::INFINITY;
+        }
+        10. * libm::powf(self.r10_ohms / ohms, 1. / self.gamma)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+
+    use super::*;
+    use std::vec::Vec;
+
+    const LDR: Photoresistor = Photoresistor {
+        fixed_ohms: 10_000.,
+        r10_ohms: 10_000.,
+        gamma: 0.7,
+    };
+
+    fn settle(auto: &mut AutoBrightness, lux: f32) -> Option<u8> {
+        let mut level = None;
+        for _ in 0..50 {
+            level = auto.update(lux).or(level);
+        }
+        level
+    }
+
+    #[test]
+    fn maps_lux_logarithmically() {
+        let config = AutoBrightnessConfig::default();
+        assert_eq!(config.level_for(1.), 0.);
+        assert_eq!(config.level_for(1_000.), 15.);
+        assert!((config.level_for(31.62) - 7.5).abs() < 0.01);
+        // Each tenfold step is worth the same
+        assert!((config.level_for(10.) - 5.).abs() < 0.01);
+        assert!((config.level_for(100.) - 10.).abs() < 0.01);
+        // Limited to the display's range
+        assert_eq!(config.level_for(0.), 0.);
+        assert_eq!(config.level_for(f32::INFINITY), 15.);
+    }
+
+    #[test]
+    fn first_reading_sets_the_level() {
+        let mut auto = AutoBrightness::new(AutoBrightnessConfig::default());
+        assert_eq!(auto.level(), None);
+        assert_eq!(auto.update(100.), Some(10));
+        assert_eq!(auto.level(), Some(10));
+        assert_eq!(auto.update(100.), None);
+
+        auto.reset();
+        assert_eq!(auto.level(), None);
+        assert_eq!(auto.update(1.), Some(0));
+    }
+
+    #[test]
+    fn smooths_changes() {
+        let mut auto = AutoBrightness::new(AutoBrightnessConfig::default());
+        auto.update(1.);
+        // The lights coming on takes a few readings to show
+        let levels: Vec<_> = (0..30).map(|_| auto.update(1_000.)).collect();
+        assert_eq!(levels[0], Some(3));
+        assert!(levels.contains(&Some(15)));
+        assert_eq!(auto.level(), Some(15));
+
+        // A single dark reading, like a hand passing over the sensor, only dims it a little
+        auto.update(1.);
+        assert_eq!(auto.level(), Some(12));
+        assert_eq!(settle(&mut auto, 1_000.), Some(15));
+    }
+
+    #[test]
+    fn holds_the_level_near_a_boundary() {
+        let config = AutoBrightnessConfig::default();
+        let mut auto = AutoBrightness::new(config);
+        // 10 lux is right on level 5
+        assert_eq!(auto.update(10.), Some(5));
+
+        // Halfway to level 6 is past rounding, but not past the hysteresis
+        let lux_for = |level: f32| libm::powf(10., level / 5.);
+        assert_eq!(settle(&mut auto, lux_for(5.6)), None);
+        assert_eq!(settle(&mut auto, lux_for(5.4)), None);
+        assert_eq!(settle(&mut auto, lux_for(5.7)), None);
+        assert_eq!(auto.level(), Some(5));
+
+        // Clearly into level 6
+        assert_eq!(settle(&mut auto, lux_for(5.9)), Some(6));
+        // And back down takes as much
+        assert_eq!(settle(&mut auto, lux_for(5.3)), None);
+        assert_eq!(settle(&mut auto, lux_for(5.1)), Some(5));
+    }
+
+    #[test]
+    fn photoresistor_to_lux() {
+        // Half the supply means the photoresistor matches the fixed resistor, which is its
+        // resistance at 10 lux
+        assert!((LDR.lux(1.65, 3.3) - 10.).abs() < 0.01);
+        // Brighter pulls the pin up
+        assert!(LDR.lux(3., 3.3) > 100.);
+        assert!(LDR.lux(0.3, 3.3) < 1.);
+        assert_eq!(LDR.lux(0., 3.3), 0.);
+        assert_eq!(LDR.lux(3.3, 3.3), f32::INFINITY);
+    }
+}
@@ -0,0 +1,1950 @@
+//! The firmware's behavior, generic over the hardware it runs on so it can be exercised on a host.
+
+use crate::{
+    ambient::{AutoBrightness, AutoBrightnessConfig, Photoresistor},
+    battery,
+    buttons::{Button, ButtonConfig, Gesture, PressLatch},
+    calibration::{Calibration, CalibrationError},
+    command::{self, Command},
+    cycle::CycleStats,
+    filter::{Filter, FilterConfig, TemperatureFilter},
+    ht16k33::{self, Align, BlinkRate, Brightness, DisplayState, DisplayText},
+    marquee::Marquee,
+    menu::{Menu, MenuChange, MenuSettings},
+    oventemp::{OvenTemp, OvenTempConfig, OvenTempEvent, OvenTempState, PreheatStatus},
+    setpoint::SetPointEstimate,
+    temperature::{Temperature, TemperatureUnit},
+    thermocouple::{self, FaultDetector, ProbeStatus, ThermocoupleFault},
+    timer::{Timer, TimerState},
+};
+use core::convert::TryFrom;
+use core::fmt::Write as _;
+use core::marker::PhantomData;
+use embedded_hal::{
+    adc::{Channel, OneShot},
+    blocking::{delay::DelayMs, i2c},
+    digital::v2::{InputPin, OutputPin},
+};
+
+#[cfg(feature = "usbserial")]
+use crate::{serial_write, usbserial};
+
+#[cfg(not(feature = "usbserial"))]
+macro_rules! serial_write {
+    ($($tt:tt)*) => {{}};
+}
+
+/// I2C address of the HT16K33 display backpack
+const DISPLAY_I2C_ADDR: u8 = 0x70;
+
+/// 12 bit ADC
+const ADC_FULLSCALE: u32 = 4095;
+/// Using VDDA / 2 with digital gain 1/2, our reference is ~3.3v
+const ADC_REF_VOLTAGE: f32 = 3.3;
+/// The threshold for showing a low battery indication.
+/// We can't descern much below this voltage due to drop out
+const LOW_BATTERY_VOLTAGE: f32 = 3.7;
+
+const DELAY_OFF_MS: u32 = 1_000;
+const DELAY_COOLDOWN_MS: u32 = 1_000;
+const DELAY_RUNNING_MS: u32 = 1_000;
+const SECS_BETWEEN_BLINK: u32 = 5;
+/// How many times `REDY` flashes when the oven finishes preheating
+const READY_FLASHES: u32 = 3;
+const READY_FLASH_MS: u32 = 250;
+/// While the display's on, every `ALTERNATE_PERIOD` readings we show the timer or cook time
+/// instead of the temperature for the last `ALTERNATE_READINGS` of them
+const ALTERNATE_PERIOD: u32 = 5;
+const ALTERNATE_READINGS: u32 = 2;
+/// How many times we alternate between `SET ` and the value when showing the set point
+const SET_POINT_FLASHES: u32 = 2;
+const SET_POINT_FLASH_MS: u32 = 1_000;
+/// How long scrolling text stays in each position
+const MARQUEE_STEP_MS: u32 = 200;
+/// How often the button is polled while it's in use
+const BUTTON_POLL_MS: u32 = 10;
+/// The menu closes itself after this long without the button being touched
+const MENU_TIMEOUT_MS: u64 = 15_000;
+/// Dim enough for a dark kitchen, and easy on the battery
+const DEFAULT_BRIGHTNESS: u8 = 1;
+/// A GL5528 from 3.3V to the light sensor pin, with 10k from the pin to ground
+const PHOTORESISTOR: Photoresistor = Photoresistor {
+    fixed_ohms: 10_000.,
+    r10_ohms: 10_000.,
+    gamma: 0.7,
+};
+
+/// Wraps the delay to keep track of time. We spend nearly all of ours asleep, so how long we've
+/// slept is a good enough monotonic clock without needing another timer.
+struct Clock<DELAY> {
+    delay: DELAY,
+    now_ms: u64,
+}
+
+impl<DELAY: DelayMs<u32>> DelayMs<u32> for Clock<DELAY> {
+    fn delay_ms(&mut self, ms: u32) {
+        self.delay.delay_ms(ms);
+        self.now_ms += u64::from(ms);
+    }
+}
+
+/// User-facing configuration of the application
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct AppConfig {
+    /// The unit temperatures are displayed and reported in
+    pub unit: TemperatureUnit,
+    /// Whether to give up the last digit of the display for a trailing `C`/`F`
+    pub show_unit: bool,
+    /// The thresholds for turning the display on and off
+    pub oven: OvenTempConfig,
+    /// Correction applied to thermocouple readings
+    pub calibration: Calibration,
+    /// How thermocouple readings are smoothed
+    pub filter: FilterConfig,
+    /// Whether to alternate between the temperature and how long the oven's been at temperature
+    pub show_cook_time: bool,
+    /// Display brightness, from 0 to `menu::MAX_BRIGHTNESS`
+    pub brightness: u8,
+    /// Whether the brightness follows the ambient light instead, if there's a light sensor
+    pub auto_brightness: bool,
+}
+
+impl Default for AppConfig {
+    fn default() -> Self {
+        Self {
+            unit: TemperatureUnit::default(),
+            show_unit: false,
+            oven: OvenTempConfig::default(),
+            calibration: Calibration::default(),
+            filter: FilterConfig::default(),
+            show_cook_time: false,
+            brightness: DEFAULT_BRIGHTNESS,
+            auto_brightness: true,
+        }
+    }
+}
+
+/// The oven temperature monitor application
+///
+/// # Type Parameters
+/// * `I2C`: The bus the HT16K33 display is on
+/// * `ADC`: The ADC the thermocouple amplifier and battery divider are read with
+/// * `A`: The ADC peripheral type the pins are channels of
+/// * `THERM`: The ADC pin connected to the thermocouple amplifier
+/// * `BATT`: The ADC pin connected to the battery voltage divider
+/// * `LIGHT`: The ADC pin connected to the photoresistor, if there is one
+/// * `LED`: The red status LED
+/// * `BUTTON`: The pin the menu button is on
+/// * `DELAY`: The delay used to wait between samples
+pub struct App<I2C, ADC, A, THERM, BATT, LIGHT, LED, BUTTON, DELAY> {
+    i2c: I2C,
+    adc: ADC,
+    therm_pin: THERM,
+    batt_pin: BATT,
+    light_pin: Option<LIGHT>,
+    red_led: LED,
+    button: Button<BUTTON>,
+    delay: Clock<DELAY>,
+    display: ht16k33::HT16K33,
+    auto_brightness: AutoBrightness,
+    oven_state: OvenTemp,
+    filter: Filter,
+    fault_detector: FaultDetector,
+    timer: Timer,
+    /// What the timer was doing as of the last reading
+    last_timer: TimerState,
+    menu: Menu,
+    /// When the button was last used in the menu
+    menu_input_ms: u64,
+    /// A message scrolling across the display, a step each time we're stepped
+    marquee: Option<Marquee>,
+    /// Whether the low battery message has scrolled by since we last slept on it
+    low_battery_shown: bool,
+    iteration: u32,
+    config: AppConfig,
+    _adc: PhantomData<A>,
+}
+
+impl<I2C, CommE, ADC, A, THERM, BATT, LIGHT, LED, BUTTON, DELAY>
+    App<I2C, ADC, A, THERM, BATT, LIGHT, LED, BUTTON, DELAY>
+where
+    I2C: i2c::Write<Error = CommE>,
+    ADC: OneShot<A, u16, THERM> + OneShot<A, u16, BATT> + OneShot<A, u16, LIGHT>,
+    THERM: Channel<A>,
+    BATT: Channel<A>,
+    LIGHT: Channel<A>,
+    LED: OutputPin<Error = core::convert::Infallible>,
+    BUTTON: InputPin<Error = core::convert::Infallible>,
+    DELAY: DelayMs<u32>,
+{
+    /// Initializes the application, waiting for the display to start communicating
+    ///
+    /// # Arguments
+    /// * `i2c`: The bus the display is on
+    /// * `adc`: The configured ADC
+    /// * `therm_pin`: The ADC pin connected to the thermocouple amplifier
+    /// * `batt_pin`: The ADC pin connected to the battery voltage divider
+    /// * `light_pin`: The ADC pin connected to the photoresistor, if there is one
+    /// * `red_led`: The red status LED
+    /// * `button_pin`: The pin the menu button is on, pulled up and shorted to ground when pressed
+    /// * `delay`: The delay used to wait between samples
+    /// * `config`: User-facing configuration
+    #[allow(clippy::too_many_arguments)] // One per peripheral
+    pub fn init(
+        mut i2c: I2C,
+        adc: ADC,
+        therm_pin: THERM,
+        batt_pin: BATT,
+        light_pin: Option<LIGHT>,
+        mut red_led: LED,
+        button_pin: BUTTON,
+        delay: DELAY,
+        config: AppConfig,
+    ) -> Self {
+        red_led.set_high().unwrap();
+        let mut delay = Clock { delay, now_ms: 0 };
+
+        // wait here until the display is plugged in and communicating
+        let display = loop {
+            match ht16k33::HT16K33::init(DISPLAY_I2C_ADDR, &mut i2c) {
+                Ok(disp) => break disp,
+                _ => delay.delay_ms(1_000_u32),
+            };
+        };
+
+        let mut app = Self {
+            i2c,
+            adc,
+            therm_pin,
+            batt_pin,
+            light_pin,
+            red_led,
+            button: Button::new(button_pin, ButtonConfig::default()),
+            delay,
+            display,
+            auto_brightness: AutoBrightness::new(AutoBrightnessConfig::default()),
+            oven_state: OvenTemp::new(config.oven),
+            filter: Filter::new(config.filter),
+            fault_detector: FaultDetector::new(),
+            timer: Timer::new(),
+            last_timer: TimerState::Idle,
+            menu: Menu::new(),
+            menu_input_ms: 0,
+            marquee: None,
+            low_battery_shown: false,
+            iteration: 0,
+            config,
+            _adc: PhantomData,
+        };
+
+        if app.greet().is_err() {
+            app.error();
+        }
+
+        app.red_led.set_low().unwrap();
+        app
+    }
+
+    /// Say hi on the display so we know it's working
+    fn greet(&mut self) -> Result<(), ht16k33::Error<CommE>> {
+        self.display.clear();
+        self.display
+            .set_brightness(Brightness::clamped(self.config.brightness), &mut self.i2c)?;
+        self.display.write_str_aligned("HI", Align::Center);
+        self.display.write_display(&mut self.i2c)?;
+        self.delay.delay_ms(500_u32);
+        self.display.clear();
+        self.display.write_display(&mut self.i2c)
+    }
+
+    /// The current state of the oven
+    #[must_use]
+    pub fn oven_state(&self) -> OvenTempState {
+        self.oven_state.state
+    }
+
+    /// Milliseconds since the app started
+    #[must_use]
+    pub fn now_ms(&self) -> u64 {
+        self.delay.now_ms
+    }
+
+    /// How long the oven has been in its current state, as of the last reading, ms
+    #[must_use]
+    pub fn time_in_state(&self) -> u64 {
+        self.oven_state.time_in_state()
+    }
+
+    /// Whether the oven has finished preheating
+    #[must_use]
+    pub fn preheat(&self) -> PreheatStatus {
+        self.oven_state.preheat()
+    }
+
+    /// How the oven's thermostat is cycling, once it's been at temperature for a full cycle
+    #[must_use]
+    pub fn cycle(&self) -> Option<CycleStats> {
+        self.oven_state.cycle()
+    }
+
+    /// What the oven's dial is really set to, once we've seen its thermostat cycle
+    #[must_use]
+    pub fn set_point(&self) -> Option<SetPointEstimate> {
+        self.oven_state.set_point()
+    }
+
+    /// What the countdown timer is doing
+    #[must_use]
+    pub fn timer(&self) -> TimerState {
+        self.timer.state(self.delay.now_ms)
+    }
+
+    /// Counts button presses the interrupt handler latched, even if they're over before the
+    /// button's next polled
+    pub fn set_button_latch(&mut self, latch: &'static PressLatch) {
+        self.button.set_latch(latch);
+    }
+
+    /// The user-facing configuration of the application
+    #[must_use]
+    pub fn config(&self) -> &AppConfig {
+        &self.config
+    }
+
+    /// Parses and runs a line of text received over serial, reporting the outcome back
+    #[cfg_attr(not(feature = "usbserial"), allow(unused_variables))]
+    pub fn handle_line(&mut self, line: &str) {
+        let result = match command::parse(line) {
+            Ok(command) => self.handle_command(command).map_err(|err| err.message()),
+            Err(command::ParseError::Empty) => Ok(()),
+            Err(err) => Err(err.message()),
+        };
+        if let Err(message) = result {
+            serial_write!("{}\r\n", message);
+        }
+    }
+
+    /// Runs a command
+    pub fn handle_command(&mut self, command: Command) -> Result<(), CalibrationError> {
+        let unit = self.config.unit;
+        let temp = |value| Temperature::from_unit(value, unit);
+        // Readings are what the display showed, with the current calibration applied
+        let current = self.config.calibration;
+        let reading = |value| current.unapply(temp(value));
+        let calibration = match command {
+            Command::ShowSetPoint => {
+                if self.show_set_point().is_err() {
+                    self.error();
+                }
+                return Ok(());
+            }
+            Command::ShowTimer | Command::StartTimer(_) | Command::StopTimer => {
+                self.handle_timer(command);
+                return Ok(());
+            }
+            Command::ShowUnit | Command::SetUnit(_) | Command::SetUnitShown(_) => {
+                self.handle_unit(command);
+                return Ok(());
+            }
+            Command::ShowCalibration => self.config.calibration,
+            Command::ResetCalibration => Calibration::IDENTITY,
+            // Like the menu, this keeps any gain from a two-point calibration
+            Command::CalibrateOffset(offset) => current.with_offset(unit.delta_to_celsius(offset)),
+            Command::CalibrateIceBoil { ice, boil } => {
+                Calibration::ice_boil(reading(ice), reading(boil))?
+            }
+            Command::CalibrateTwoPoint { low, high } => Calibration::two_point(
+                (reading(low.0), temp(low.1)),
+                (reading(high.0), temp(high.1)),
+            )?,
+        };
+        self.config.calibration = calibration;
+
+        serial_write!(
+            "cal: gain {} offset {}{}\r\n",
+            usbserial::Decimal(calibration.gain(), 3),
+            usbserial::Decimal(unit.delta_from_celsius(calibration.offset()), 1),
+            unit.symbol()
+        );
+        Ok(())
+    }
+
+    /// Starts, stops or reports on the countdown timer
+    fn handle_timer(&mut self, command: Command) {
+        let now_ms = self.delay.now_ms;
+        match command {
+            Command::StartTimer(minutes) => self.timer.start((minutes * 60_000.) as u64, now_ms),
+            Command::StopTimer => self.timer.cancel(),
+            _ => {}
+        }
+        match self.timer.state(now_ms) {
+            #[cfg_attr(not(feature = "usbserial"), allow(unused_variables))]
+            TimerState::Running { remaining_ms } => {
+                let seconds = remaining_ms.div_ceil(1_000);
+                serial_write!("timer: {}m{}s left\r\n", seconds / 60, seconds % 60);
+            }
+            TimerState::Expired => serial_write!("timer: done\r\n"),
+            TimerState::Idle => serial_write!("timer: off\r\n"),
+        }
+    }
+
+    /// Changes or reports on how temperatures are shown
+    fn handle_unit(&mut self, command: Command) {
+        match command {
+            Command::SetUnit(unit) => self.config.unit = unit,
+            Command::SetUnitShown(show_unit) => self.config.show_unit = show_unit,
+            _ => {}
+        }
+        serial_write!(
+            "unit: {} ({})\r\n",
+            self.config.unit.symbol(),
+            if self.config.show_unit {
+                "shown"
+            } else {
+                "hidden"
+            }
+        );
+    }
+
+    /// Polls the button, acting on any gesture it completes
+    ///
+    /// # Returns
+    /// whether the button or menu is in use, in which case the reading should be skipped and this
+    /// called again soon
+    fn poll_button(&mut self) -> bool {
+        let now_ms = self.delay.now_ms;
+        if let Some(gesture) = self.button.poll(now_ms).unwrap() {
+            self.menu_input_ms = now_ms;
+            if self.handle_gesture(gesture).is_err() {
+                self.error();
+            }
+        } else if self.menu.is_open()
+            && self.button.is_idle()
+            && now_ms - self.menu_input_ms >= MENU_TIMEOUT_MS
+        {
+            serial_write!("menu: timed out\r\n");
+            if self.close_menu().is_err() {
+                self.error();
+            }
+        }
+
+        if self.menu.is_open() || !self.button.is_idle() {
+            self.delay.delay_ms(BUTTON_POLL_MS);
+            return true;
+        }
+        false
+    }
+
+    /// Opens the menu, or passes the gesture on to it if it's open
+    fn handle_gesture(&mut self, gesture: Gesture) -> Result<(), ht16k33::Error<CommE>> {
+        if !self.menu.is_open() {
+            // A double press is how you leave the menu, so it shouldn't open it
+            if gesture == Gesture::DoublePress {
+                return Ok(());
+            }
+            self.menu.open(self.menu_settings());
+        } else if let Some(change) = self.menu.handle(gesture.into()) {
+            self.apply_change(change)?;
+        }
+
+        if !self.menu.is_open() {
+            return self.close_menu();
+        }
+        self.display.set_state(DisplayState::On, &mut self.i2c)?;
+        self.menu.render(&mut self.display);
+        self.display.write_display(&mut self.i2c)
+    }
+
+    /// The settings the menu starts editing from
+    fn menu_settings(&self) -> MenuSettings {
+        let unit = self.config.unit;
+        MenuSettings {
+            unit,
+            brightness: self.config.brightness,
+            auto_brightness: self.light_pin.as_ref().map(|_| self.config.auto_brightness),
+            offset: unit.delta_from_celsius(self.config.calibration.offset()),
+            timer_minutes: match self.timer.state(self.delay.now_ms) {
+                TimerState::Running { remaining_ms } => {
+                    u32::try_from(remaining_ms.div_ceil(60_000)).unwrap_or(u32::MAX)
+                }
+                _ => 0,
+            },
+        }
+    }
+
+    /// Applies a setting saved in the menu
+    fn apply_change(&mut self, change: MenuChange) -> Result<(), ht16k33::Error<CommE>> {
+        match change {
+            MenuChange::Unit(unit) => self.config.unit = unit,
+            MenuChange::Brightness(level) => {
+                self.config.brightness = level;
+                self.config.auto_brightness = false;
+                self.display
+                    .set_brightness(Brightness::clamped(level), &mut self.i2c)?;
+            }
+            MenuChange::AutoBrightness => {
+                self.config.auto_brightness = true;
+                if let Some(level) = self.auto_brightness.level() {
+                    self.display
+                        .set_brightness(Brightness::clamped(level), &mut self.i2c)?;
+                }
+            }
+            MenuChange::Offset(offset) => {
+                self.config.calibration = self
+                    .config
+                    .calibration
+                    .with_offset(self.config.unit.delta_to_celsius(offset));
+            }
+            MenuChange::Timer(0) => self.timer.cancel(),
+            MenuChange::Timer(minutes) => self
+                .timer
+                .start(u64::from(minutes) * 60_000, self.delay.now_ms),
+        }
+        Ok(())
+    }
+
+    /// Closes the menu, handing the display back to the oven
+    fn close_menu(&mut self) -> Result<(), ht16k33::Error<CommE>> {
+        self.menu.close();
+        self.display.clear();
+        let state = self.oven_state.state;
+        if self.last_timer == TimerState::Idle
+            && (state == OvenTempState::Off || state == OvenTempState::CoolingDown)
+        {
+            // Nothing else wants the display
+            self.enter_state(state)
+        } else {
+            self.display.write_display(&mut self.i2c)
+        }
+    }
+
+    /// Runs one iteration of the main loop: check the battery, read the thermocouple,
+    /// update the display and sleep until the next sample.
+    pub fn step(&mut self) {
+        #[cfg(feature = "usbserial")]
+        while let Some(line) = usbserial::USBSerial::read_line() {
+            if let Some(text) = line.as_str() {
+                self.handle_line(text);
+            }
+        }
+
+        // Keep up with the button while it's in use. The menu has the display to itself while it's
+        // open.
+        if self.poll_button() {
+            return;
+        }
+
+        if self.follow_ambient_light().is_err() {
+            self.error();
+        }
+
+        // A message has the display until it's scrolled by. Readings wait for it, which they can
+        // for the few seconds it takes.
+        match self.scroll_message() {
+            Ok(true) => {
+                self.delay.delay_ms(MARQUEE_STEP_MS);
+                return;
+            }
+            Ok(false) => {}
+            Err(_) => {
+                self.marquee = None;
+                self.error();
+            }
+        }
+
+        // Check to make sure our battery is in good shape
+        let battery_voltage = match read_voltage(&mut self.adc, &mut self.batt_pin) {
+            // external HW divides the reading by two
+            Some(voltage) => 2.0 * voltage,
+            None => {
+                self.error();
+                return;
+            }
+        };
+
+        if battery_voltage <= LOW_BATTERY_VOLTAGE {
+            // inform user of low battery. Thermocouple readings are not accurate
+            if self.show_low_battery().is_err() {
+                self.error();
+            }
+            return; // Do not run the typical thermocouple routine
+        }
+        // So the message is shown first if it runs low again
+        self.low_battery_shown = false;
+
+        // Check the thermocouple
+        let voltage = match read_voltage(&mut self.adc, &mut self.therm_pin) {
+            Some(voltage) => voltage,
+            None => {
+                self.error();
+                return;
+            }
+        };
+        let now_ms = self.delay.now_ms;
+        match self.fault_detector.check(voltage) {
+            ProbeStatus::Good => {}
+            ProbeStatus::Suspect => {
+                // Might be a glitch, so skip the sample rather than act on it
+                self.delay.delay_ms(DELAY_RUNNING_MS);
+                return;
+            }
+            ProbeStatus::Faulted(fault) => {
+                if self.show_fault(fault, now_ms).is_err() {
+                    self.error();
+                }
+                return;
+            }
+        }
+        if let OvenTempState::Fault(_) = self.oven_state.state {
+            // What we filtered before the fault is stale
+            self.filter.reset();
+            serial_write!("fault cleared\r\n");
+        }
+
+        let temp = self
+            .filter
+            .update(thermocouple::ad8495_temperature(voltage));
+        let temp = self.config.calibration.apply(temp);
+        self.iteration += 1;
+
+        serial_write!(
+            "reading: {}{}\r\n",
+            usbserial::Decimal(temp.in_unit(self.config.unit), 1),
+            self.config.unit.symbol()
+        );
+
+        if self.run(temp).is_err() {
+            self.error();
+        }
+
+        if let Some(transition) = self.oven_state.check_transition(temp, now_ms) {
+            if self.enter_state(transition.to).is_err() {
+                self.error();
+            }
+        }
+
+        while let Some(event) = self.oven_state.poll_event() {
+            if self.handle_event(event).is_err() {
+                self.error();
+            }
+        }
+
+        // blink a dot to show we're alive, and show battery percentage
+        if (self.oven_state.state == OvenTempState::Off
+            || self.oven_state.state == OvenTempState::CoolingDown)
+            && self.last_timer == TimerState::Idle
+            && (self.iteration % SECS_BETWEEN_BLINK == SECS_BETWEEN_BLINK - 1)
+            && self.blink_battery_dot(battery_voltage).is_err()
+        {
+            self.error();
+        }
+    }
+
+    /// Reads the light sensor, if there is one, and adjusts the display's brightness to match if
+    /// it's meant to
+    fn follow_ambient_light(&mut self) -> Result<(), ht16k33::Error<CommE>> {
+        let pin = match self.light_pin.as_mut() {
+            Some(pin) => pin,
+            None => return Ok(()),
+        };
+        let voltage = match read_voltage(&mut self.adc, pin) {
+            Some(voltage) => voltage,
+            None => {
+                self.error();
+                return Ok(());
+            }
+        };
+        // Keep following the light while it's not wanted, so it's ready if it's picked
+        let changed = self
+            .auto_brightness
+            .update(PHOTORESISTOR.lux(voltage, ADC_REF_VOLTAGE));
+        match changed {
+            Some(level) if self.config.auto_brightness => {
+                serial_write!("brightness: {}\r\n", level);
+                self.display
+                    .set_brightness(Brightness::clamped(level), &mut self.i2c)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Starts scrolling `text` across the display once. Each step moves it along until it's gone.
+    fn show_message(&mut self, text: &str) -> Result<(), ht16k33::Error<CommE>> {
+        let mut marquee = Marquee::new(text, MARQUEE_STEP_MS);
+        marquee.tick(self.delay.now_ms);
+        self.marquee = Some(marquee);
+        self.display.set_state(DisplayState::On, &mut self.i2c)?;
+        self.scroll_message().map(|_| ())
+    }
+
+    /// Shows the scrolling message as of now, if there is one
+    ///
+    /// # Returns
+    /// whether it's still scrolling, rather than gone (leaving the display blank)
+    fn scroll_message(&mut self) -> Result<bool, ht16k33::Error<CommE>> {
+        let marquee = match self.marquee.as_mut() {
+            Some(marquee) => marquee,
+            None => return Ok(false),
+        };
+        marquee.tick(self.delay.now_ms);
+        let scrolling = marquee.passes() == 0;
+        marquee.render(&mut self.display);
+        if !scrolling {
+            self.marquee = None;
+        }
+        self.display.write_display(&mut self.i2c)?;
+        Ok(scrolling)
+    }
+
+    /// Tell the user the battery is low, then sleep for a long while with the display in standby
+    fn show_low_battery(&mut self) -> Result<(), ht16k33::Error<CommE>> {
+        self.low_battery_shown = !self.low_battery_shown;
+        if self.low_battery_shown {
+            // Back here to sleep once it's scrolled by
+            self.show_message("LOW BATTERY")?;
+            self.delay.delay_ms(MARQUEE_STEP_MS);
+            return Ok(());
+        }
+
+        // Delay for a long while with display in standby to save some power
+        self.display
+            .set_state(DisplayState::Standby, &mut self.i2c)?;
+        self.delay.delay_ms(5_000_u32);
+        self.display.set_state(DisplayState::On, &mut self.i2c)?;
+        self.delay.delay_ms(100_u32);
+        Ok(())
+    }
+
+    /// Show what's wrong with the thermocouple until it's fixed
+    fn show_fault(
+        &mut self,
+        fault: ThermocoupleFault,
+        now_ms: u64,
+    ) -> Result<(), ht16k33::Error<CommE>> {
+        if let Some(transition) = self.oven_state.set_fault(fault, now_ms) {
+            serial_write!("fault: {}\r\n", fault.code());
+            self.enter_state(transition.to)?;
+        }
+        self.display.clear();
+        self.display.write_str(fault.code());
+        self.display.write_display(&mut self.i2c)?;
+        self.delay.delay_ms(DELAY_RUNNING_MS);
+        Ok(())
+    }
+
+    /// Alternate between `SET ` and the estimated set point on the display, or `----` if we
+    /// don't have an estimate yet
+    pub fn show_set_point(&mut self) -> Result<(), ht16k33::Error<CommE>> {
+        let estimate = self.oven_state.set_point();
+        #[cfg(feature = "usbserial")]
+        match estimate {
+            Some(estimate) => serial_write!(
+                "set point: {}{} confidence {}%\r\n",
+                usbserial::Decimal(estimate.set_point.in_unit(self.config.unit), 1),
+                self.config.unit.symbol(),
+                usbserial::Decimal(estimate.confidence * 100., 0)
+            ),
+            None => serial_write!("set point: unknown\r\n"),
+        }
+
+        self.display.set_state(DisplayState::On, &mut self.i2c)?;
+        for _ in 0..SET_POINT_FLASHES {
+            self.display.clear();
+            self.display.write_str("SET ");
+            self.display.write_display(&mut self.i2c)?;
+            self.delay.delay_ms(SET_POINT_FLASH_MS);
+            match estimate {
+                Some(estimate) => self.display_temp(estimate.set_point)?,
+                None => {
+                    self.display.write_str("----");
+                    self.display.write_display(&mut self.i2c)?;
+                }
+            }
+            self.delay.delay_ms(SET_POINT_FLASH_MS);
+        }
+
+        // Put the display back the way we found it
+        self.display.clear();
+        self.display.write_display(&mut self.i2c)?;
+        if matches!(
+            self.oven_state.state,
+            OvenTempState::Off | OvenTempState::CoolingDown
+        ) {
+            self.display
+                .set_state(DisplayState::Standby, &mut self.i2c)?;
+        }
+        Ok(())
+    }
+
+    /// Let the user know something happened
+    fn handle_event(&mut self, event: OvenTempEvent) -> Result<(), ht16k33::Error<CommE>> {
+        match event {
+            OvenTempEvent::PreheatComplete => {
+                serial_write!("preheat complete\r\n");
+                // Flash the message, then leave the temperature up for next time around
+                for _ in 0..READY_FLASHES {
+                    self.display.clear();
+                    self.display.write_str("REDY");
+                    self.display.write_display(&mut self.i2c)?;
+                    self.delay.delay_ms(READY_FLASH_MS);
+                    self.display.clear();
+                    self.display.write_display(&mut self.i2c)?;
+                    self.delay.delay_ms(READY_FLASH_MS);
+                }
+                Ok(())
+            }
+            #[cfg_attr(not(feature = "usbserial"), allow(unused_variables))]
+            OvenTempEvent::CycleComplete(stats) => {
+                let unit = self.config.unit;
+                serial_write!(
+                    "cycle: mean {}{} swing {}{} period {}s duty {}%\r\n",
+                    usbserial::Decimal(stats.mean.in_unit(unit), 1),
+                    unit.symbol(),
+                    usbserial::Decimal(unit.delta_from_celsius(stats.swing), 1),
+                    unit.symbol(),
+                    stats.period_ms / 1_000,
+                    usbserial::Decimal(stats.duty_cycle * 100., 0)
+                );
+                Ok(())
+            }
+            OvenTempEvent::DoorOpened => {
+                serial_write!("door opened\r\n");
+                Ok(())
+            }
+            #[cfg_attr(not(feature = "usbserial"), allow(unused_variables))]
+            OvenTempEvent::DoorClosed { open_ms } => {
+                serial_write!("door closed after {}s\r\n", open_ms / 1_000);
+                Ok(())
+            }
+            #[cfg_attr(not(feature = "usbserial"), allow(unused_variables))]
+            OvenTempEvent::DoorRecovered { recovery_ms } => {
+                serial_write!(
+                    "recovered {}s after the door closed\r\n",
+                    recovery_ms / 1_000
+                );
+                Ok(())
+            }
+        }
+    }
+
+    /// Turn the display on or off as we move into a new oven state
+    fn enter_state(&mut self, new_state: OvenTempState) -> Result<(), ht16k33::Error<CommE>> {
+        match new_state {
+            OvenTempState::Off | OvenTempState::CoolingDown => {
+                // clear and turn off the display
+                self.display.clear();
+                self.display.write_display(&mut self.i2c)?;
+                self.display.set_state(DisplayState::Standby, &mut self.i2c)
+            }
+            _ => {
+                // take the display out of standby mode
+                self.display.set_state(DisplayState::On, &mut self.i2c)
+            }
+        }
+    }
+
+    /// Blink a dot to show we're alive, with its position showing the battery percentage
+    fn blink_battery_dot(&mut self, battery_voltage: f32) -> Result<(), ht16k33::Error<CommE>> {
+        let battery_percentage = battery::voltage_to_percentage(battery_voltage);
+        let mut blink_index: u8 = 0;
+        if battery_percentage >= 75 {
+            blink_index = 3;
+        } else if battery_percentage >= 50 {
+            blink_index = 2;
+        } else if battery_percentage >= 25 {
+            blink_index = 1;
+        }
+
+        // Turn display on
+        self.display.set_state(DisplayState::On, &mut self.i2c)?;
+
+        // Blink dot
+        self.display.clear();
+        self.display.write_digit_ascii(blink_index, ' ', true);
+        self.display.write_display(&mut self.i2c)?;
+        self.delay.delay_ms(50_u32);
+        self.display.clear();
+        self.display.write_display(&mut self.i2c)?;
+
+        // turn display back off
+        self.display.set_state(DisplayState::Standby, &mut self.i2c)
+    }
+
+    /// Run the main state display/sleep logic
+    fn run(&mut self, temp: Temperature) -> Result<(), ht16k33::Error<CommE>> {
+        let timer = self.timer.state(self.delay.now_ms);
+        let last_timer = core::mem::replace(&mut self.last_timer, timer);
+        self.alert(timer, last_timer)?;
+
+        let alternate = self.iteration % ALTERNATE_PERIOD >= ALTERNATE_PERIOD - ALTERNATE_READINGS;
+        match self.oven_state.state {
+            state @ (OvenTempState::Off | OvenTempState::CoolingDown) => {
+                let ret = match timer {
+                    // The timer needs the display even though the oven doesn't
+                    TimerState::Running { .. } | TimerState::Expired => {
+                        self.display.set_state(DisplayState::On, &mut self.i2c)?;
+                        self.display_timer(timer)
+                    }
+                    // Done with it, so turn it back off
+                    TimerState::Idle if last_timer != TimerState::Idle => self.enter_state(state),
+                    TimerState::Idle => Ok(()),
+                };
+                if state == OvenTempState::Off {
+                    serial_write!("Off\r\n");
+                    self.delay.delay_ms(DELAY_OFF_MS);
+                } else {
+                    serial_write!("CoolingDown\r\n");
+                    self.delay.delay_ms(DELAY_COOLDOWN_MS);
+                }
+                ret
+            }
+            state => {
+                serial_write!("WarmingUp or AtTemp\r\n");
+                let ret = match timer {
+                    TimerState::Expired => self.display_timer(timer),
+                    TimerState::Running { .. } if alternate => self.display_timer(timer),
+                    _ if alternate
+                        && self.config.show_cook_time
+                        && state == OvenTempState::AtTemp =>
+                    {
+                        self.display_cook_time()
+                    }
+                    _ => self.display_temp(temp),
+                };
+                self.delay.delay_ms(DELAY_RUNNING_MS);
+                ret
+            }
+        }
+    }
+
+    /// Blink the display and LED while the timer's expired
+    fn alert(
+        &mut self,
+        timer: TimerState,
+        last_timer: TimerState,
+    ) -> Result<(), ht16k33::Error<CommE>> {
+        let alerting = timer == TimerState::Expired;
+        if alerting != (last_timer == TimerState::Expired) {
+            let rate = if alerting {
+                serial_write!("timer: done\r\n");
+                BlinkRate::TwoHz
+            } else {
+                self.red_led.set_low().unwrap();
+                BlinkRate::Off
+            };
+            self.display.set_blink_rate(rate, &mut self.i2c)?;
+        }
+        if alerting {
+            // Every other reading, so it's a slow blink
+            if self.iteration.is_multiple_of(2) {
+                self.red_led.set_high().unwrap();
+            } else {
+                self.red_led.set_low().unwrap();
+            }
+        }
+        Ok(())
+    }
+
+    /// Display how long is left on the timer, or `DONE` once it's run out
+    fn display_timer(&mut self, timer: TimerState) -> Result<(), ht16k33::Error<CommE>> {
+        let frame = match timer {
+            // Round up, so we show 00.00 as it runs out rather than a second early
+            TimerState::Running { remaining_ms } => format_duration(remaining_ms + 999),
+            _ => Frame::from("DONE"),
+        };
+        self.display_frame(&frame)
+    }
+
+    /// Display the given temperature on the display
+    fn display_temp(&mut self, temp: Temperature) -> Result<(), ht16k33::Error<CommE>> {
+        let frame = format_temperature(temp.in_unit(self.config.unit), self.config);
+        self.display_frame(&frame)
+    }
+
+    /// Display how long the oven has been at temperature
+    fn display_cook_time(&mut self) -> Result<(), ht16k33::Error<CommE>> {
+        let elapsed_ms = self
+            .delay
+            .now_ms
+            .saturating_sub(self.oven_state.entered_at());
+        let frame = format_duration(elapsed_ms);
+        self.display_frame(&frame)
+    }
+
+    fn display_frame(&mut self, frame: &Frame) -> Result<(), ht16k33::Error<CommE>> {
+        self.display.clear();
+        self.display.write_text(frame, Align::Left);
+        self.display.write_display(&mut self.i2c)
+    }
+
+    /// Blinks an SOS pattern on the red LED indicating an error
+    fn error(&mut self) {
+        error(&mut self.red_led, &mut self.delay);
+        // Whatever went wrong may have reset the display or left a frame half sent, so it can't be
+        // trusted to still hold what we last sent it
+        self.display.force_refresh(&mut self.i2c).ok();
+    }
+}
+
+/// What to show in each of the display's four positions
+type Frame = DisplayText;
+
+/// `value` cut down to `places` decimal places. Formatting rounds, and we'd rather not show a
+/// temperature that hasn't been reached yet.
+fn truncate(value: f32, places: i32) -> f32 {
+    let scale = libm::powf(10., places as f32);
+    libm::truncf(value * scale) / scale
+}
+
+/// Formats a temperature for the display, with as many decimal places as fit
+fn format_temperature(value: f32, config: AppConfig) -> Frame {
+    let unit = config.unit.symbol();
+    let mut frame = Frame::new();
+    // Writing to a frame can't fail, it just drops what doesn't fit
+    let _ = if !(0. ..1000.).contains(&value) {
+        // Too many digits to show. Broken thermocouples are caught before we get here, so
+        // this takes a wild calibration.
+        write!(frame, "ERR!")
+    } else if value < 100. {
+        if config.show_unit {
+            write!(frame, "{:04.1}{}", truncate(value, 1), unit)
+        } else {
+            write!(frame, "{:05.2}", truncate(value, 2))
+        }
+    } else if config.show_unit {
+        write!(frame, "{:.0}{}", truncate(value, 0), unit)
+    } else {
+        write!(frame, "{:.1}", truncate(value, 1))
+    };
+    frame
+}
+
+/// Formats a duration as `MM.SS`, or `H.MMh` from an hour up. The point stands in for the colon
+/// the alphanumeric display doesn't have.
+fn format_duration(duration_ms: u64) -> Frame {
+    let seconds = duration_ms / 1_000;
+    let (minutes, seconds) = (seconds / 60, seconds % 60);
+    let (hours, hour_minutes) = (minutes / 60, minutes % 60);
+    let mut frame = Frame::new();
+    // Writing to a frame can't fail, it just drops what doesn't fit
+    let _ = if minutes < 60 {
+        write!(frame, "{:02}.{:02}", minutes, seconds)
+    } else if hours < 10 {
+        write!(frame, "{}.{:02}h", hours, hour_minutes)
+    } else {
+        // Something's been forgotten about: just the hours
+        write!(frame, "{:02}h", hours.min(99))
+    };
+    frame
+}
+
+/// Reads the given ADC pin, returning the voltage on it
+fn read_voltage<ADC, A, PIN>(adc: &mut ADC, pin: &mut PIN) -> Option<f32>
+where
+    ADC: OneShot<A, u16, PIN>,
+    PIN: Channel<A>,
+{
+    let reading: u16 = nb::block!(adc.read(pin)).ok()?;
+    Some((f32::from(reading) / ADC_FULLSCALE as f32) * ADC_REF_VOLTAGE)
+}
+
+/// Blinks an SOS pattern indicating an error
+///
+/// # Parameters
+/// * `red_led`: The LED pin to blink
+/// * `delay`: The `Delay` instance to wait
+fn error<PIN, T>(red_led: &mut PIN, delay: &mut T)
+where
+    PIN: OutputPin<Error = core::convert::Infallible>,
+    T: DelayMs<u32>,
+{
+    const SHORT_BLIP_MS: u32 = 250;
+    const LONG_BLIP_MS: u32 = 500;
+
+    // S
+    red_led.set_high().unwrap();
+    delay.delay_ms(SHORT_BLIP_MS);
+    red_led.set_low().unwrap();
+    delay.delay_ms(SHORT_BLIP_MS);
+    red_led.set_high().unwrap();
+    delay.delay_ms(SHORT_BLIP_MS);
+    red_led.set_low().unwrap();
+    delay.delay_ms(SHORT_BLIP_MS);
+    red_led.set_high().unwrap();
+    delay.delay_ms(SHORT_BLIP_MS);
+    red_led.set_low().unwrap();
+    delay.delay_ms(SHORT_BLIP_MS);
+
+    // O
+    red_led.set_high().unwrap();
+    delay.delay_ms(LONG_BLIP_MS);
+    red_led.set_low().unwrap();
+    delay.delay_ms(LONG_BLIP_MS);
+    red_led.set_high().unwrap();
+    delay.delay_ms(LONG_BLIP_MS);
+    red_led.set_low().unwrap();
+    delay.delay_ms(LONG_BLIP_MS);
+    red_led.set_high().unwrap();
+    delay.delay_ms(LONG_BLIP_MS);
+    red_led.set_low().unwrap();
+    delay.delay_ms(LONG_BLIP_MS);
+
+    // S
+    red_led.set_high().unwrap();
+    delay.delay_ms(SHORT_BLIP_MS);
+    red_led.set_low().unwrap();
+    delay.delay_ms(SHORT_BLIP_MS);
+    red_led.set_high().unwrap();
+    delay.delay_ms(SHORT_BLIP_MS);
+    red_led.set_low().unwrap();
+    delay.delay_ms(SHORT_BLIP_MS);
+    red_led.set_high().unwrap();
+    delay.delay_ms(SHORT_BLIP_MS);
+    red_led.set_low().unwrap();
+
+    delay.delay_ms(2 * LONG_BLIP_MS);
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+
+    use super::*;
+    use crate::menu::MAX_BRIGHTNESS;
+    use crate::mock::{
+        shown_as, BattPin, LightPin, MockAdc, MockDelay, MockI2c, MockPin, ThermPin,
+    };
+    use std::{string::String, vec::Vec};
+
+    type TestApp =
+        App<MockI2c, MockAdc, MockAdc, ThermPin, BattPin, LightPin, MockPin, MockPin, MockDelay>;
+
+    /// A healthy, 4v battery
+    const BATT_OK: u16 = 2482;
+    /// A 3.6v battery, below our low battery threshold
+    const BATT_LOW: u16 = 2234;
+    /// 201.5C / 394.7F
+    const THERM_HOT: u16 = 2800;
+    /// 19.9C / 67.8F
+    const THERM_ROOM: u16 = 1675;
+
+    struct Harness {
+        app: TestApp,
+        i2c: MockI2c,
+        adc: MockAdc,
+        led: MockPin,
+        button: MockPin,
+        delay: MockDelay,
+    }
+
+    fn harness(config: AppConfig) -> Harness {
+        build(config, None)
+    }
+
+    fn harness_with_light(config: AppConfig) -> Harness {
+        build(config, Some(LightPin))
+    }
+
+    fn build(config: AppConfig, light_pin: Option<LightPin>) -> Harness {
+        let i2c = MockI2c::default();
+        let adc = MockAdc::default();
+        let led = MockPin::default();
+        let button = MockPin::default();
+        button.set(true);
+        let delay = MockDelay::default();
+        adc.set_batt(BATT_OK);
+        adc.set_therm(THERM_ROOM);
+
+        let app = App::init(
+            i2c.clone(),
+            adc.clone(),
+            ThermPin,
+            BattPin,
+            light_pin,
+            led.clone(),
+            button.clone(),
+            delay.clone(),
+            config,
+        );
+        Harness {
+            app,
+            i2c,
+            adc,
+            led,
+            button,
+            delay,
+        }
+    }
+
+    /// Every brightness level set on the display
+    fn brightness_levels(i2c: &MockI2c) -> Vec<u8> {
+        i2c.writes()
+            .iter()
+            .filter(|(_, bytes)| bytes.len() == 1 && bytes[0] & 0xF0 == 0xE0)
+            .map(|(_, bytes)| bytes[0] & 0x0F)
+            .collect()
+    }
+
+    #[test]
+    fn init_greets() {
+        let h = harness(AppConfig::default());
+        assert_eq!(h.i2c.frames(), [" HI ", "    "]);
+        assert!(h.i2c.writes().iter().all(|(addr, _)| *addr == 0x70));
+        assert!(!h.led.is_high());
+        assert_eq!(h.app.oven_state(), OvenTempState::AtTemp);
+    }
+
+    #[test]
+    fn displays_temperature() {
+        let mut h = harness(AppConfig::default());
+        h.adc.set_therm(THERM_HOT);
+        h.app.step();
+        assert_eq!(h.i2c.displayed_text().unwrap(), "394.7");
+        assert_eq!(h.delay.elapsed_ms(), 500 + DELAY_RUNNING_MS);
+    }
+
+    #[test]
+    fn displays_temperature_with_unit() {
+        let mut h = harness(AppConfig {
+            unit: TemperatureUnit::Celsius,
+            show_unit: true,
+            ..AppConfig::default()
+        });
+        h.adc.set_therm(THERM_HOT);
+        h.app.step();
+        assert_eq!(h.i2c.displayed_text().unwrap(), "201C");
+
+        let mut h = harness(AppConfig {
+            show_unit: true,
+            ..AppConfig::default()
+        });
+        h.adc.set_therm(THERM_HOT);
+        h.app.step();
+        assert_eq!(h.i2c.displayed_text().unwrap(), "394F");
+    }
+
+    #[test]
+    fn serial_changes_the_unit() {
+        let mut h = harness(AppConfig::default());
+        h.adc.set_therm(THERM_HOT);
+        h.app.handle_line("unit c");
+        h.app.step();
+        assert_eq!(h.i2c.displayed_text().unwrap(), "201.5");
+        h.app.handle_line("unit show");
+        h.app.step();
+        assert_eq!(h.i2c.displayed_text().unwrap(), "201C");
+        h.app.handle_line("unit f");
+        h.app.handle_line("unit hide");
+        h.app.step();
+        assert_eq!(h.i2c.displayed_text().unwrap(), "394.7");
+        assert_eq!(
+            (h.app.config().unit, h.app.config().show_unit),
+            (TemperatureUnit::Fahrenheit, false)
+        );
+    }
+
+    #[test]
+    fn calibration_corrects_readings() {
+        let mut h = harness(AppConfig::default());
+        h.adc.set_therm(THERM_HOT);
+        h.app.handle_line("cal offset -4.7");
+        h.app.step();
+        assert_eq!(h.i2c.displayed_text().unwrap(), "390.0");
+
+        // With that offset, ice water read 36F and boiling water 216F, so we still read 4F high
+        h.app.handle_line("cal iceboil 36 216");
+        assert!((h.app.config().calibration.offset() + 8.7 * 5. / 9.).abs() < 0.01);
+        h.app.step();
+        assert_eq!(h.i2c.displayed_text().unwrap(), "386.0");
+
+        // An offset keeps the gain, as it does in the menu
+        h.app.handle_line("cal 2pt 70 70 390 380");
+        let gain = h.app.config().calibration.gain();
+        assert!(gain < 1.);
+        h.app.handle_line("cal offset 2");
+        assert_eq!(h.app.config().calibration.gain(), gain);
+        assert!((h.app.config().calibration.offset() - 2. * 5. / 9.).abs() < 0.01);
+
+        h.app.handle_line("cal reset");
+        assert_eq!(h.app.config().calibration, Calibration::IDENTITY);
+    }
+
+    #[test]
+    fn calibrating_again_starts_from_what_was_shown() {
+        let mut h = harness(AppConfig {
+            unit: TemperatureUnit::Celsius,
+            ..AppConfig::default()
+        });
+        h.adc.set_therm(THERM_HOT);
+        h.app.step();
+        assert_eq!(h.i2c.displayed_text().unwrap(), "201.5");
+
+        // A reference thermometer reads 20C in the kitchen, where we show 23C, and 191.5C in the
+        // oven
+        h.app.handle_line("cal 2pt 23 20 201.5 191.5");
+        h.app.step();
+        assert_eq!(h.i2c.displayed_text().unwrap(), "191.5");
+
+        // Calibrating against the same thermometer again changes nothing
+        h.app.handle_line("cal 2pt 20 20 191.5 191.5");
+        h.app.step();
+        assert_eq!(h.i2c.displayed_text().unwrap(), "191.5");
+
+        // A better thermometer says the oven's really at 189.5C, and ice water is 0C where we show
+        // 1C
+        h.app.handle_line("cal 2pt 1 0 191.5 189.5");
+        h.app.step();
+        assert_eq!(h.i2c.displayed_text().unwrap(), "189.5");
+
+        // Water boiling shows as 100.5C, ice water still 1C
+        h.app.handle_line("cal iceboil 1 100.5");
+        h.app.step();
+        let expected = (189.5 - 1.) * 100. / 99.5;
+        let shown: f32 = h.i2c.displayed_text().unwrap().parse().unwrap();
+        assert!((shown - expected).abs() < 0.1, "{} != {}", shown, expected);
+    }
+
+    #[test]
+    fn bad_calibration_commands_are_ignored() {
+        let mut h = harness(AppConfig::default());
+        h.app.handle_line("cal offset 5");
+        let calibration = h.app.config().calibration;
+        for line in [
+            "cal offset",
+            "cal offset abc",
+            "cal iceboil 40 41",
+            "cal 2pt 1 2 3",
+            "cal 2pt 20 20 100 140",
+            "cal 2pt 75 350 360 70",
+        ] {
+            h.app.handle_line(line);
+            assert_eq!(h.app.config().calibration, calibration, "{}", line);
+        }
+        assert_eq!(
+            h.app.handle_command(Command::CalibrateTwoPoint {
+                low: (100., 100.),
+                high: (101., 300.)
+            }),
+            Err(CalibrationError::PointsTooClose)
+        );
+    }
+
+    #[test]
+    fn displays_error_for_disconnected_thermocouple() {
+        let mut h = harness(AppConfig::default());
+        h.adc.set_therm(THERM_HOT);
+        h.app.step();
+        h.adc.set_therm(4095);
+        h.app.step();
+        h.app.step();
+        // A couple of bad samples could be a glitch, so they're skipped
+        assert_eq!(h.i2c.displayed_text().unwrap(), "394.7");
+        assert_eq!(h.app.oven_state(), OvenTempState::AtTemp);
+        h.app.step();
+        assert_eq!(h.i2c.displayed_text().unwrap(), "OPEN");
+        assert!(!h.i2c.in_standby());
+        assert_eq!(
+            h.app.oven_state(),
+            OvenTempState::Fault(ThermocoupleFault::Open)
+        );
+
+        // Plugged back in
+        h.adc.set_therm(THERM_HOT);
+        for _ in 0..3 {
+            h.app.step();
+        }
+        assert_eq!(h.app.oven_state(), OvenTempState::AtTemp);
+        assert_eq!(h.i2c.displayed_text().unwrap(), "394.7");
+    }
+
+    #[test]
+    fn distinct_fault_codes() {
+        for (reading, code) in [(0, "SHRT"), (1200, "LO  "), (3700, "HI  ")] {
+            let mut h = harness(AppConfig::default());
+            h.adc.set_therm(reading);
+            for _ in 0..3 {
+                h.app.step();
+            }
+            assert_eq!(
+                h.i2c.displayed_text().unwrap(),
+                shown_as(code),
+                "{}",
+                reading
+            );
+        }
+    }
+
+    #[test]
+    fn cold_kitchen_is_not_a_fault() {
+        // 2C, which used to show ERR! in Celsius
+        let mut h = harness(AppConfig {
+            unit: TemperatureUnit::Celsius,
+            ..AppConfig::default()
+        });
+        h.adc.set_therm(1560);
+        for _ in 0..5 {
+            h.app.step();
+        }
+        assert_eq!(h.app.oven_state(), OvenTempState::Off);
+    }
+
+    #[test]
+    fn cold_oven_turns_display_off() {
+        let mut h = harness(AppConfig {
+            filter: FilterConfig::None,
+            ..AppConfig::default()
+        });
+        h.app.step();
+        assert_eq!(h.app.oven_state(), OvenTempState::CoolingDown);
+        assert!(h.i2c.in_standby());
+        h.app.step();
+        assert_eq!(h.app.oven_state(), OvenTempState::Off);
+
+        // Heat back up and the display turns back on. Jumping straight there is faster than an
+        // oven can heat, so the first hot sample is skipped.
+        h.i2c.clear();
+        h.adc.set_therm(THERM_HOT);
+        h.app.step();
+        assert_eq!(h.app.oven_state(), OvenTempState::Off);
+        h.app.step();
+        assert_eq!(h.app.oven_state(), OvenTempState::HeatingUp);
+        assert!(!h.i2c.in_standby());
+        h.app.step();
+        assert_eq!(h.i2c.displayed_text().unwrap(), "394.7");
+        assert_eq!(h.app.oven_state(), OvenTempState::AtTemp);
+    }
+
+    #[test]
+    fn tracks_time_in_state() {
+        let mut h = harness(AppConfig::default());
+        // The greeting took half a second
+        assert_eq!(h.app.now_ms(), 500);
+        h.app.step();
+        h.app.step();
+        assert_eq!(h.app.oven_state(), OvenTempState::Off);
+        assert_eq!(h.app.time_in_state(), 0);
+        for _ in 0..3 {
+            h.app.step();
+        }
+        // Including the battery dot's blink
+        assert_eq!(h.app.time_in_state(), 3 * u64::from(DELAY_OFF_MS) + 50);
+        assert_eq!(h.app.now_ms(), u64::from(h.delay.elapsed_ms()));
+    }
+
+    #[test]
+    fn formats_temperatures() {
+        let text = |value, show_unit| {
+            let config = AppConfig {
+                show_unit,
+                ..AppConfig::default()
+            };
+            let frame = format_temperature(value, config);
+            frame
+                .cells()
+                .iter()
+                .flat_map(|&(c, point)| core::iter::once(c).chain(point.then_some('.')))
+                .collect::<String>()
+        };
+        assert_eq!(text(394.75, false), "394.7");
+        assert_eq!(text(394.75, true), "394F");
+        assert_eq!(text(67.89, false), "67.89");
+        assert_eq!(text(67.89, true), "67.8F");
+        assert_eq!(text(5.5, false), "05.50");
+        assert_eq!(text(0., false), "00.00");
+        assert_eq!(text(1000., false), "ERR!");
+        assert_eq!(text(-1., false), "ERR!");
+    }
+
+    #[test]
+    fn formats_durations() {
+        let text = |duration_ms| {
+            format_duration(duration_ms)
+                .cells()
+                .iter()
+                .flat_map(|&(c, point)| core::iter::once(c).chain(point.then_some('.')))
+                .collect::<String>()
+        };
+        assert_eq!(text(0), "00.00");
+        assert_eq!(text(999), "00.00");
+        assert_eq!(text(65_000), "01.05");
+        assert_eq!(text(59 * 60_000 + 59_999), "59.59");
+        assert_eq!(text(60 * 60_000), "1.00h");
+        assert_eq!(text((2 * 60 + 7) * 60_000 + 30_000), "2.07h");
+        assert_eq!(text((9 * 60 + 59) * 60_000), "9.59h");
+        assert_eq!(text(10 * 3_600_000), "10h");
+        assert_eq!(text(1_000 * 3_600_000), "99h");
+    }
+
+    #[test]
+    fn alternates_with_cook_time() {
+        let mut h = harness(AppConfig {
+            show_cook_time: true,
+            ..AppConfig::default()
+        });
+        h.adc.set_therm(THERM_HOT);
+        let shown: Vec<String> = (0..10)
+            .map(|_| {
+                h.app.step();
+                h.i2c.displayed_text().unwrap()
+            })
+            .collect();
+        // At temperature since boot, which was half a second before the first reading
+        assert_eq!(
+            shown,
+            [
+                "394.7", "394.7", "00.02", "00.03", "394.7", "394.7", "394.7", "00.07", "00.08",
+                "394.7"
+            ]
+        );
+    }
+
+    #[test]
+    fn filters_readings() {
+        let mut h = harness(AppConfig::default());
+        h.adc.set_therm(THERM_HOT);
+        for _ in 0..5 {
+            h.app.step();
+        }
+        assert_eq!(h.i2c.displayed_text().unwrap(), "394.7");
+
+        // Oven door opened: we follow the drop, but not all at once
+        h.adc.set_therm(2650);
+        h.app.step();
+        let shown: f32 = h.i2c.displayed_text().unwrap().parse().unwrap();
+        assert!(shown > 355. && shown < 390., "{}", shown);
+        for _ in 0..20 {
+            h.app.step();
+        }
+        assert_eq!(h.i2c.displayed_text().unwrap(), "350.3");
+    }
+
+    /// ADC reading for the given temperature
+    fn therm_reading(celsius: f32) -> u16 {
+        let voltage = thermocouple::ad8495_output(
+            Temperature::from_celsius(celsius),
+            thermocouple::DEFAULT_COLD_JUNCTION,
+        );
+        (voltage / ADC_REF_VOLTAGE * ADC_FULLSCALE as f32 + 0.5) as u16
+    }
+
+    #[test]
+    fn shows_set_point_on_demand() {
+        let mut h = harness(AppConfig {
+            filter: FilterConfig::None,
+            ..AppConfig::default()
+        });
+        h.i2c.clear();
+        h.app.handle_line("set");
+        assert_eq!(h.i2c.frames(), ["SET ", "----", "SET ", "----", "    "]);
+
+        // Thermostat holding 356F: heats from 338F to 374F over a minute, then coasts back
+        // down over two
+        for n in 0..15 * 60 {
+            let t = n % 180;
+            let celsius = if t < 60 {
+                170. + t as f32 / 3.
+            } else {
+                190. - (t - 60) as f32 / 6.
+            };
+            h.adc.set_therm(therm_reading(celsius));
+            h.app.step();
+        }
+        assert_eq!(h.app.oven_state(), OvenTempState::AtTemp);
+        let estimate = h.app.set_point().unwrap();
+        assert!((estimate.set_point.celsius() - 180.).abs() < 0.3);
+        assert!((estimate.confidence - 0.75).abs() < 0.05);
+
+        h.i2c.clear();
+        h.app.handle_line("set");
+        let shown = h.i2c.frames();
+        assert_eq!(shown.len(), 5);
+        assert_eq!([&shown[0], &shown[2]], ["SET ", "SET "]);
+        for value in [&shown[1], &shown[3]] {
+            let value: f32 = value.parse().unwrap();
+            assert!((value - 356.).abs() < 0.5, "{}", value);
+        }
+        assert!(!h.i2c.in_standby());
+    }
+
+    #[test]
+    fn flashes_ready_once_preheated() {
+        let mut h = harness(AppConfig::default());
+        for _ in 0..3 {
+            h.app.step();
+        }
+        assert_eq!(h.app.oven_state(), OvenTempState::Off);
+        assert_eq!(h.app.preheat(), PreheatStatus::Idle);
+
+        // Heat up 1F per reading (a fast oven) past the on threshold
+        h.i2c.clear();
+        let mut reading = THERM_ROOM;
+        while h.app.preheat() != PreheatStatus::Complete {
+            reading += 1;
+            h.adc.set_therm(reading.min(THERM_HOT));
+            h.app.step();
+            assert!(reading < THERM_HOT + 100, "never finished preheating");
+        }
+        let ready = |h: &Harness| h.i2c.frames().iter().filter(|f| *f == "REDY").count();
+        assert_eq!(ready(&h), READY_FLASHES as usize);
+        assert!(reading > THERM_HOT);
+
+        // Holding temperature doesn't flash it again
+        for _ in 0..50 {
+            h.app.step();
+        }
+        assert_eq!(ready(&h), READY_FLASHES as usize);
+        assert_eq!(h.i2c.displayed_text().unwrap(), "394.7");
+    }
+
+    #[test]
+    fn counts_down_and_alerts() {
+        let mut h = harness(AppConfig::default());
+        for _ in 0..3 {
+            h.app.step();
+        }
+        assert_eq!(h.app.oven_state(), OvenTempState::Off);
+        assert!(h.i2c.in_standby());
+
+        // Wakes the display even though the oven's off
+        h.app.handle_line("timer 0.05");
+        let shown: Vec<String> = (0..4)
+            .map(|_| {
+                h.app.step();
+                h.i2c.displayed_text().unwrap()
+            })
+            .collect();
+        assert_eq!(shown, ["00.03", "00.02", "00.01", "DONE"]);
+        assert!(!h.i2c.in_standby());
+        assert_eq!(h.app.timer(), TimerState::Expired);
+        // Blinking at 2Hz
+        assert!(h.i2c.writes().iter().any(|(_, bytes)| bytes == &[0x83]));
+
+        // The LED blinks along with it
+        let mut led = Vec::new();
+        for _ in 0..4 {
+            h.app.step();
+            led.push(h.led.is_high());
+        }
+        assert!(led.contains(&true) && led.contains(&false));
+
+        // Stopping it puts everything back
+        h.i2c.clear();
+        h.app.handle_line("timer stop");
+        h.app.step();
+        assert_eq!(h.app.timer(), TimerState::Idle);
+        assert!(!h.led.is_high());
+        assert!(h.i2c.writes().iter().any(|(_, bytes)| bytes == &[0x81]));
+        assert!(h.i2c.in_standby());
+    }
+
+    #[test]
+    fn alternates_with_timer() {
+        let mut h = harness(AppConfig {
+            show_cook_time: true,
+            ..AppConfig::default()
+        });
+        h.adc.set_therm(THERM_HOT);
+        h.app.handle_line("timer 10");
+        let shown: Vec<String> = (0..5)
+            .map(|_| {
+                h.app.step();
+                h.i2c.displayed_text().unwrap()
+            })
+            .collect();
+        // The timer takes the cook time's place
+        assert_eq!(shown, ["394.7", "394.7", "09.58", "09.57", "394.7"]);
+    }
+
+    /// Holds the button down (or leaves it alone) for a while, stepping the app all along
+    fn hold(h: &mut Harness, pressed: bool, ms: u64) {
+        h.button.set(!pressed);
+        let until_ms = h.app.now_ms() + ms;
+        while h.app.now_ms() < until_ms {
+            h.app.step();
+        }
+    }
+
+    /// Presses the button quickly, then waits long enough for it not to be a double press
+    fn tap(h: &mut Harness) {
+        hold(h, true, 100);
+        hold(h, false, 400);
+    }
+
+    /// Holds the button down long enough to be a long press
+    fn long_press(h: &mut Harness) {
+        hold(h, true, 1_000);
+        hold(h, false, 100);
+    }
+
+    #[test]
+    fn taps_too_quick_to_poll_open_the_menu() {
+        static LATCH: PressLatch = PressLatch::new();
+        let mut h = harness(AppConfig::default());
+        h.app.set_button_latch(&LATCH);
+        hold(&mut h, false, 3_000);
+        assert!(h.i2c.in_standby());
+
+        // Tapped while we slept, and released before we woke up to poll it
+        LATCH.set();
+        hold(&mut h, false, 400);
+        assert_eq!(h.i2c.displayed_text().unwrap(), "UNIT");
+        assert!(!h.i2c.in_standby());
+
+        // The first tap of a double press can be as quick
+        LATCH.set();
+        hold(&mut h, false, 100);
+        hold(&mut h, true, 100);
+        assert!(h.i2c.in_standby());
+    }
+
+    #[test]
+    fn menu_changes_brightness() {
+        let mut h = harness(AppConfig::default());
+        hold(&mut h, false, 3_000);
+        assert_eq!(h.app.oven_state(), OvenTempState::Off);
+        assert!(h.i2c.in_standby());
+
+        long_press(&mut h);
+        assert_eq!(h.i2c.displayed_text().unwrap(), "UNIT");
+        assert!(!h.i2c.in_standby());
+        tap(&mut h);
+        assert_eq!(h.i2c.displayed_text().unwrap(), "BRIT");
+        long_press(&mut h);
+        assert_eq!(h.i2c.displayed_text().unwrap(), "   1");
+        tap(&mut h);
+        assert_eq!(h.i2c.displayed_text().unwrap(), "   2");
+        h.i2c.clear();
+        long_press(&mut h);
+        assert_eq!(h.i2c.displayed_text().unwrap(), "BRIT");
+        assert!(h.i2c.writes().iter().any(|(_, bytes)| bytes == &[0xE2]));
+        assert_eq!(h.app.config().brightness, 2);
+
+        // A double press closes the menu, and the oven's off so the display goes back off
+        hold(&mut h, true, 100);
+        hold(&mut h, false, 100);
+        hold(&mut h, true, 100);
+        assert!(h.i2c.in_standby());
+        assert_eq!(h.i2c.displayed_text().unwrap(), "    ");
+    }
+
+    #[test]
+    fn menu_starts_the_timer() {
+        let mut h = harness(AppConfig::default());
+        long_press(&mut h);
+        for _ in 0..3 {
+            tap(&mut h);
+        }
+        assert_eq!(h.i2c.displayed_text().unwrap(), "TIMR");
+        long_press(&mut h);
+        tap(&mut h);
+        assert_eq!(h.i2c.displayed_text().unwrap(), "  1M");
+        long_press(&mut h);
+        assert!(matches!(h.app.timer(), TimerState::Running { .. }));
+
+        // Left alone, the menu closes itself and the display shows the timer
+        hold(&mut h, false, MENU_TIMEOUT_MS);
+        hold(&mut h, false, 2_000);
+        assert_eq!(h.i2c.displayed_text().unwrap(), "00.43");
+    }
+
+    #[test]
+    fn blinks_battery_dot_while_off() {
+        let mut h = harness(AppConfig::default());
+        for _ in 0..SECS_BETWEEN_BLINK - 2 {
+            h.app.step();
+        }
+        assert_eq!(h.app.oven_state(), OvenTempState::Off);
+        h.i2c.clear();
+        h.app.step();
+        // A full battery blinks the last dot
+        assert_eq!(h.i2c.frames(), ["    .", "    "]);
+    }
+
+    #[test]
+    fn low_battery_skips_thermocouple() {
+        let mut h = harness(AppConfig::default());
+        h.adc.set_batt(BATT_LOW);
+        h.i2c.clear();
+        // A step at a time, so the button and serial port are seen to in between
+        let before = h.delay.elapsed_ms();
+        let mut steps = 0;
+        while h.i2c.frames().last().map(String::as_str) != Some("    ") {
+            h.app.step();
+            steps += 1;
+            assert!(steps < 20);
+        }
+        assert_eq!(steps, 12);
+        assert_eq!(
+            h.i2c.frames(),
+            [
+                "LOW ", "OW B", "W BA", " BAT", "BATT", "ATTE", "TTER", "TERY", "ERY ", "RY  ",
+                "Y   ", "    "
+            ]
+        );
+
+        // The step it's gone in, we sleep with the display in standby. Then it's shown again.
+        assert_eq!(h.delay.elapsed_ms() - before, 11 * MARQUEE_STEP_MS + 5_100);
+        assert!(h.i2c.writes().iter().any(|(_, bytes)| bytes == &[0x20]));
+        h.app.step();
+        assert_eq!(h.i2c.displayed_text().unwrap(), "LOW ");
+        // We never looked at the (cold) thermocouple
+        assert_eq!(h.app.oven_state(), OvenTempState::AtTemp);
+    }
+
+    #[test]
+    fn battery_dot_blinks_then_sleeps() {
+        let mut h = harness(AppConfig::default());
+        h.i2c.clear();
+        for _ in 0..SECS_BETWEEN_BLINK {
+            h.app.step();
+        }
+        // A full battery's dot is on the last digit
+        let frames = h.i2c.frames();
+        assert_eq!(frames[frames.len() - 2..], ["    .", "    "]);
+        let setup: Vec<u8> = h
+            .i2c
+            .writes()
+            .iter()
+            .filter(|(_, bytes)| bytes.len() == 1 && bytes[0] & 0xF0 == 0x20)
+            .map(|(_, bytes)| bytes[0])
+            .collect();
+        assert_eq!(setup.last(), Some(&0x20));
+    }
+
+    #[test]
+    fn low_battery_is_shown_each_time_it_runs_low() {
+        let mut h = harness(AppConfig::default());
+        h.adc.set_batt(BATT_LOW);
+        for _ in 0..3 {
+            h.app.step();
+        }
+
+        // Recovers while the message is scrolling, then runs low again
+        h.adc.set_batt(BATT_OK);
+        while h.i2c.frames().last().map(String::as_str) != Some("    ") {
+            h.app.step();
+        }
+        h.app.step();
+        h.adc.set_batt(BATT_LOW);
+        h.i2c.clear();
+        let before = h.delay.elapsed_ms();
+        h.app.step();
+        assert_eq!(h.i2c.displayed_text().unwrap(), "LOW ");
+        assert_eq!(h.delay.elapsed_ms() - before, MARQUEE_STEP_MS);
+    }
+
+    #[test]
+    fn button_works_while_a_message_scrolls() {
+        let mut h = harness(AppConfig::default());
+        h.adc.set_batt(BATT_LOW);
+        for _ in 0..3 {
+            h.app.step();
+        }
+        assert_eq!(h.i2c.displayed_text().unwrap(), "W BA");
+        long_press(&mut h);
+        assert_eq!(h.i2c.displayed_text().unwrap(), "UNIT");
+    }
+
+    #[test]
+    fn bus_errors_blink_sos() {
+        let mut h = harness(AppConfig::default());
+        h.adc.set_therm(THERM_HOT);
+        h.i2c.set_fail(true);
+        let before = h.delay.elapsed_ms();
+        h.app.step();
+        // 3 short, 3 long, 3 short blips and a pause, on top of our normal delay
+        assert_eq!(
+            h.delay.elapsed_ms() - before,
+            DELAY_RUNNING_MS + 6 * 250 + 6 * 500 + 5 * 250 + 2 * 500
+        );
+        assert!(!h.led.is_high());
+
+        // Once the bus is back, the whole frame is sent again rather than what changed
+        h.i2c.set_fail(false);
+        h.i2c.clear();
+        h.app.step();
+        assert!(h.i2c.writes().iter().any(|(_, bytes)| bytes.len() == 17));
+        assert_eq!(h.i2c.displayed_text().unwrap(), "394.7");
+    }
+
+    #[test]
+    fn follows_the_ambient_light() {
+        let mut h = harness_with_light(AppConfig::default());
+        // Half the supply is about 10 lux
+        h.adc.set_light(2048);
+        h.i2c.clear();
+        h.app.step();
+        assert_eq!(brightness_levels(&h.i2c), [5]);
+
+        // The lights come on, and the display brightens over a few readings
+        h.adc.set_light(4000);
+        for _ in 0..30 {
+            h.app.step();
+        }
+        let levels = brightness_levels(&h.i2c);
+        assert!(levels.len() > 2);
+        assert!(levels.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(levels.last(), Some(&15));
+
+        // Holding steady doesn't touch the display
+        h.i2c.clear();
+        for _ in 0..10 {
+            h.app.step();
+        }
+        assert!(brightness_levels(&h.i2c).is_empty());
+    }
+
+    #[test]
+    fn menu_switches_between_manual_and_automatic_brightness() {
+        let mut h = harness_with_light(AppConfig::default());
+        h.adc.set_light(2048);
+        hold(&mut h, false, 3_000);
+
+        long_press(&mut h);
+        tap(&mut h);
+        long_press(&mut h);
+        assert_eq!(h.i2c.displayed_text().unwrap(), "AUTO");
+        tap(&mut h);
+        assert_eq!(h.i2c.displayed_text().unwrap(), "   0");
+        h.i2c.clear();
+        long_press(&mut h);
+        assert_eq!(brightness_levels(&h.i2c), [0]);
+        assert!(!h.app.config().auto_brightness);
+        assert_eq!(h.app.config().brightness, 0);
+
+        // The light's still followed, but left alone
+        hold(&mut h, true, 100);
+        hold(&mut h, false, 100);
+        hold(&mut h, true, 100);
+        h.adc.set_light(4000);
+        hold(&mut h, false, 30_000);
+        assert_eq!(brightness_levels(&h.i2c), [0]);
+
+        // So going back to automatic picks up where it is now
+        long_press(&mut h);
+        tap(&mut h);
+        long_press(&mut h);
+        assert_eq!(h.i2c.displayed_text().unwrap(), "   0");
+        for _ in 0..MAX_BRIGHTNESS + 1 {
+            tap(&mut h);
+        }
+        assert_eq!(h.i2c.displayed_text().unwrap(), "AUTO");
+        long_press(&mut h);
+        assert_eq!(brightness_levels(&h.i2c), [0, 15]);
+        assert!(h.app.config().auto_brightness);
+    }
+
+    #[test]
+    fn menu_has_no_automatic_brightness_without_a_light_sensor() {
+        let mut h = harness(AppConfig::default());
+        hold(&mut h, false, 3_000);
+        long_press(&mut h);
+        tap(&mut h);
+        long_press(&mut h);
+        assert_eq!(h.i2c.displayed_text().unwrap(), "   1");
+        for _ in 0..MAX_BRIGHTNESS {
+            tap(&mut h);
+        }
+        assert_eq!(h.i2c.displayed_text().unwrap(), "   0");
+    }
+}
@@ -0,0 +1,529 @@
+//! Push buttons: debouncing, and telling short, long and double presses apart.
+//!
+//! Buttons are polled rather than interrupt driven. Polling is only needed while a gesture is in
+//! progress though: once a button [`is_idle`](Button::is_idle), nothing happens until its pin
+//! changes, so the caller can sleep until a pin change interrupt wakes it. Every decision is made
+//! from the times we're given rather than how often we're polled, so a late poll after waking up
+//! still sees the gesture the user made.
+//!
+//! A quick tap can be over before we wake up and poll, so the interrupt handler also sets a
+//! [`PressLatch`]. A button given the latch counts a press it missed as a tap, and wrapping the
+//! delay in [`WakeOnPress`] cuts the sleep short so that tap is seen straight away.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+use embedded_hal::blocking::delay::DelayMs;
+use embedded_hal::digital::v2::InputPin;
+
+/// Default for how long (ms) a pin must hold steady before we believe it
+pub const DEFAULT_DEBOUNCE_MS: u32 = 20;
+/// Default for how long (ms) a button must be held to count as a long press
+pub const DEFAULT_LONG_PRESS_MS: u32 = 800;
+/// Default for how soon (ms) after releasing a button a second press makes it a double press
+pub const DEFAULT_DOUBLE_PRESS_MS: u32 = 300;
+
+/// The longest we sleep at a time while waiting for a press. `SleepingDelay` splits a second or
+/// more into parts, and an interrupt only ends the part it comes in.
+pub const MAX_SLEEP_MS: u32 = 500;
+
+/// Timing and wiring of a button
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct ButtonConfig {
+    /// How long (ms) a pin must hold steady before we believe it
+    pub debounce_ms: u32,
+    /// How long (ms) a button must be held to count as a long press
+    pub long_press_ms: u32,
+    /// How soon (ms) after releasing a button a second press makes it a double press
+    pub double_press_ms: u32,
+    /// Whether pressing the button pulls the pin low (a button to ground, with a pull-up)
+    pub active_low: bool,
+}
+
+impl Default for ButtonConfig {
+    fn default() -> Self {
+        Self {
+            debounce_ms: DEFAULT_DEBOUNCE_MS,
+            long_press_ms: DEFAULT_LONG_PRESS_MS,
+            double_press_ms: DEFAULT_DOUBLE_PRESS_MS,
+            active_low: true,
+        }
+    }
+}
+
+/// Something the user did with a button
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Gesture {
+    /// Pressed and released, and not pressed again soon after
+    Press,
+    /// Held down. Reported as soon as it's been held long enough, not on release.
+    LongPress,
+    /// Pressed twice in quick succession. Reported on the second press.
+    DoublePress,
+}
+
+/// Where we are in recognizing a gesture
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Phase {
+    Idle,
+    /// Pressed at the given time
+    Down {
+        since_ms: u64,
+    },
+    /// Released a short press at the given time, waiting to see if a second press follows
+    Up {
+        released_ms: u64,
+    },
+    /// Already reported this press's gesture, waiting for the button to be released
+    Reported,
+}
+
+/// Turns debounced button states into gestures
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct GestureRecognizer {
+    config: ButtonConfig,
+    /// Latest raw reading, and when it changed to that
+    raw: bool,
+    raw_since_ms: u64,
+    /// Debounced state
+    pressed: bool,
+    phase: Phase,
+}
+
+impl GestureRecognizer {
+    /// Creates a recognizer for a button that starts out released
+    #[must_use]
+    pub const fn new(config: ButtonConfig) -> Self {
+        Self {
+            config,
+            raw: false,
+            raw_since_ms: 0,
+            pressed: false,
+            phase: Phase::Idle,
+        }
+    }
+
+    /// Whether the button is debounced and pressed
+    #[must_use]
+    pub fn is_pressed(&self) -> bool {
+        self.pressed
+    }
+
+    /// Whether nothing will happen until the button is pressed, so the caller can stop polling
+    #[must_use]
+    pub fn is_idle(&self) -> bool {
+        self.phase == Phase::Idle && !self.raw && !self.pressed
+    }
+
+    /// Adds a raw reading of the button taken at the given time (ms)
+    ///
+    /// # Returns
+    /// the gesture the reading completed, if it did
+    pub fn update(&mut self, raw: bool, now_ms: u64) -> Option<Gesture> {
+        if raw != self.raw {
+            self.raw = raw;
+            self.raw_since_ms = now_ms;
+        }
+        let settled =
+            now_ms.saturating_sub(self.raw_since_ms) >= u64::from(self.config.debounce_ms);
+        if settled && self.raw != self.pressed {
+            self.pressed = self.raw;
+            // Date the change from when the pin changed, rather than when we believed it
+            if let Some(gesture) = self.transition(self.raw_since_ms, now_ms) {
+                return Some(gesture);
+            }
+        }
+        self.transition(now_ms, now_ms)
+    }
+
+    /// Adds a press that was over before it could be read, as if it was released at `now_ms`
+    ///
+    /// # Returns
+    /// the gesture the press completed, if it did
+    pub fn tap(&mut self, now_ms: u64) -> Option<Gesture> {
+        if self.raw || self.pressed {
+            // It's still being held (or bouncing), and the readings will see it
+            return None;
+        }
+        let (phase, gesture) = match self.phase {
+            Phase::Idle => (
+                Phase::Up {
+                    released_ms: now_ms,
+                },
+                None,
+            ),
+            Phase::Up { .. } => (Phase::Reported, Some(Gesture::DoublePress)),
+            phase => (phase, None),
+        };
+        self.phase = phase;
+        gesture
+    }
+
+    /// Moves the gesture along, given the debounced state changed at `changed_ms` (or didn't)
+    fn transition(&mut self, changed_ms: u64, now_ms: u64) -> Option<Gesture> {
+        let elapsed = |since_ms: u64, limit_ms: u32| now_ms - since_ms >= u64::from(limit_ms);
+        let (phase, gesture) = match (self.phase, self.pressed) {
+            (Phase::Idle, true) => (
+                Phase::Down {
+                    since_ms: changed_ms,
+                },
+                None,
+            ),
+            (Phase::Down { since_ms }, true) if elapsed(since_ms, self.config.long_press_ms) => {
+                (Phase::Reported, Some(Gesture::LongPress))
+            }
+            (Phase::Down { .. }, false) => (
+                Phase::Up {
+                    released_ms: changed_ms,
+                },
+                None,
+            ),
+            (Phase::Up { .. }, true) => (Phase::Reported, Some(Gesture::DoublePress)),
+            (Phase::Up { released_ms }, false)
+                if elapsed(released_ms, self.config.double_press_ms) =>
+            {
+                (Phase::Idle, Some(Gesture::Press))
+            }
+            (Phase::Reported, false) => (Phase::Idle, None),
+            (phase, _) => (phase, None),
+        };
+        self.phase = phase;
+        gesture
+    }
+}
+
+impl Default for GestureRecognizer {
+    fn default() -> Self {
+        Self::new(ButtonConfig::default())
+    }
+}
+
+/// Set from a button's interrupt handler when it's pressed, and cleared when the button's polled
+pub struct PressLatch(AtomicBool);
+
+impl PressLatch {
+    /// Creates a latch that hasn't seen a press
+    #[must_use]
+    pub const fn new() -> Self {
+        Self(AtomicBool::new(false))
+    }
+
+    /// Records a press. Call this from the interrupt handler.
+    pub fn set(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    /// Whether there's been a press since the button was last polled
+    #[must_use]
+    pub fn is_set(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Clears the latch, returning whether it was set
+    fn take(&self) -> bool {
+        // Thumbv6 has no atomic swap. A press coming in between the two is a bounce of the one
+        // just taken.
+        let set = self.is_set();
+        self.0.store(false, Ordering::Relaxed);
+        set
+    }
+}
+
+impl Default for PressLatch {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A delay that gives up the rest of its sleep once the button's pressed, so the app can poll it
+///
+/// The clock the app keeps counts the whole delay as having passed, so it runs a little fast for
+/// every press. That's fine for cook times and timers measured in minutes.
+pub struct WakeOnPress<DELAY> {
+    delay: DELAY,
+    latch: &'static PressLatch,
+}
+
+impl<DELAY: DelayMs<u32>> WakeOnPress<DELAY> {
+    /// Wraps `delay`, which should itself be woken by the button's interrupt
+    pub fn new(delay: DELAY, latch: &'static PressLatch) -> Self {
+        Self { delay, latch }
+    }
+}
+
+impl<DELAY: DelayMs<u32>> DelayMs<u32> for WakeOnPress<DELAY> {
+    fn delay_ms(&mut self, ms: u32) {
+        let mut left = ms;
+        while left > 0 && !self.latch.is_set() {
+            let part = left.min(MAX_SLEEP_MS);
+            self.delay.delay_ms(part);
+            left -= part;
+        }
+    }
+}
+
+/// A push button on an input pin
+pub struct Button<PIN> {
+    pin: PIN,
+    recognizer: GestureRecognizer,
+    latch: Option<&'static PressLatch>,
+}
+
+impl<PIN, E> Button<PIN>
+where
+    PIN: InputPin<Error = E>,
+{
+    /// Creates a button on the given pin, which should already have any pull-up it needs
+    pub fn new(pin: PIN, config: ButtonConfig) -> Self {
+        Self {
+            pin,
+            recognizer: GestureRecognizer::new(config),
+            latch: None,
+        }
+    }
+
+    /// Counts presses the interrupt handler saw, but that were over before the next poll
+    pub fn set_latch(&mut self, latch: &'static PressLatch) {
+        self.latch = Some(latch);
+    }
+
+    /// Whether the button is debounced and pressed
+    #[must_use]
+    pub fn is_pressed(&self) -> bool {
+        self.recognizer.is_pressed()
+    }
+
+    /// Whether nothing will happen until the pin changes, so the caller can sleep until it does
+    #[must_use]
+    pub fn is_idle(&self) -> bool {
+        self.recognizer.is_idle()
+    }
+
+    /// Reads the pin at the given time (ms)
+    ///
+    /// # Returns
+    /// the gesture the reading completed, if it did, or the pin's error if it couldn't be read
+    pub fn poll(&mut self, now_ms: u64) -> Result<Option<Gesture>, E> {
+        let pressed = if self.recognizer.config.active_low {
+            self.pin.is_low()?
+        } else {
+            self.pin.is_high()?
+        };
+        let tapped = self.latch.is_some_and(PressLatch::take);
+        if tapped && !pressed {
+            if let Some(gesture) = self.recognizer.tap(now_ms) {
+                return Ok(Some(gesture));
+            }
+        }
+        Ok(self.recognizer.update(pressed, now_ms))
+    }
+
+    /// Gives the pin back
+    pub fn free(self) -> PIN {
+        self.pin
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+
+    use super::*;
+    use crate::mock::{MockDelay, MockPin};
+    use std::vec::Vec;
+
+    /// How often the scripts are polled, ms
+    const POLL_MS: u64 = 5;
+
+    /// Drives an active low button through `script`, a list of `(until_ms, pressed)` steps, and
+    /// returns every gesture with the time it was reported
+    fn run(script: &[(u64, bool)]) -> Vec<(u64, Gesture)> {
+        let pin = MockPin::default();
+        pin.set(true);
+        let mut button = Button::new(pin.clone(), ButtonConfig::default());
+
+        let mut gestures = Vec::new();
+        let mut now_ms = 0;
+        for &(until_ms, pressed) in script {
+            pin.set(!pressed);
+            while now_ms < until_ms {
+                if let Some(gesture) = button.poll(now_ms).unwrap() {
+                    gestures.push((now_ms, gesture));
+                }
+                now_ms += POLL_MS;
+            }
+        }
+        gestures
+    }
+
+    /// A contact that chatters for 10ms before settling
+    fn bouncy(at_ms: u64, pressed: bool) -> [(u64, bool); 3] {
+        [
+            (at_ms + 5, pressed),
+            (at_ms + 10, !pressed),
+            (at_ms + 15, pressed),
+        ]
+    }
+
+    #[test]
+    fn short_press() {
+        let gestures = run(&[(100, false), (250, true), (1_000, false)]);
+        // Reported once it's too late to be a double press
+        assert_eq!(gestures, [(550, Gesture::Press)]);
+    }
+
+    #[test]
+    fn long_press() {
+        let gestures = run(&[(100, false), (2_000, true), (3_000, false)]);
+        // Reported while still held
+        assert_eq!(gestures, [(900, Gesture::LongPress)]);
+    }
+
+    #[test]
+    fn double_press() {
+        let gestures = run(&[
+            (100, false),
+            (200, true),
+            (350, false),
+            // Holding the second press doesn't make it a long press too
+            (2_000, true),
+            (3_000, false),
+        ]);
+        assert_eq!(gestures, [(370, Gesture::DoublePress)]);
+    }
+
+    #[test]
+    fn slow_presses_are_separate() {
+        let gestures = run(&[
+            (100, false),
+            (200, true),
+            (600, false),
+            (700, true),
+            (1_500, false),
+        ]);
+        assert_eq!(gestures, [(500, Gesture::Press), (1_000, Gesture::Press)]);
+    }
+
+    #[test]
+    fn debounces() {
+        let mut script = Vec::from([(100, false)]);
+        script.extend(bouncy(100, true));
+        script.push((200, true));
+        script.extend(bouncy(200, false));
+        script.push((1_000, false));
+        // The chatter is one press, not a double press, and it's timed from when it settled
+        assert_eq!(run(&script), [(510, Gesture::Press)]);
+
+        // And a glitch shorter than the debounce time isn't a press at all
+        assert_eq!(run(&[(100, false), (110, true), (1_000, false)]), []);
+    }
+
+    #[test]
+    fn late_polls_after_sleeping() {
+        let mut recognizer = GestureRecognizer::default();
+        assert!(recognizer.is_idle());
+        // Woken by the pin change, then not polled again for a while
+        assert_eq!(recognizer.update(true, 1_000), None);
+        assert!(!recognizer.is_idle());
+        assert_eq!(recognizer.update(true, 1_050), None);
+        assert!(recognizer.is_pressed());
+        assert_eq!(recognizer.update(false, 1_100), None);
+        assert_eq!(recognizer.update(false, 2_000), Some(Gesture::Press));
+        assert!(recognizer.is_idle());
+    }
+
+    #[test]
+    fn active_high() {
+        let pin = MockPin::default();
+        let mut button = Button::new(
+            pin.clone(),
+            ButtonConfig {
+                active_low: false,
+                ..ButtonConfig::default()
+            },
+        );
+        assert_eq!(button.poll(0).unwrap(), None);
+        pin.set(true);
+        assert_eq!(button.poll(10).unwrap(), None);
+        assert_eq!(button.poll(1_000).unwrap(), Some(Gesture::LongPress));
+        assert!(button.is_pressed());
+    }
+
+    #[test]
+    fn taps_between_polls() {
+        static LATCH: PressLatch = PressLatch::new();
+        let pin = MockPin::default();
+        pin.set(true);
+        let mut button = Button::new(pin.clone(), ButtonConfig::default());
+        button.set_latch(&LATCH);
+
+        // Pressed and released while we were asleep
+        LATCH.set();
+        assert_eq!(button.poll(1_000).unwrap(), None);
+        assert!(!LATCH.is_set());
+        assert!(!button.is_idle());
+        assert_eq!(button.poll(1_200).unwrap(), None);
+        assert_eq!(button.poll(1_300).unwrap(), Some(Gesture::Press));
+        assert!(button.is_idle());
+
+        // Twice
+        LATCH.set();
+        assert_eq!(button.poll(2_000).unwrap(), None);
+        LATCH.set();
+        assert_eq!(button.poll(2_100).unwrap(), Some(Gesture::DoublePress));
+        assert_eq!(button.poll(2_110).unwrap(), None);
+        assert!(button.is_idle());
+
+        // Still held when we poll, so it's timed from the pin
+        LATCH.set();
+        pin.set(false);
+        assert_eq!(button.poll(3_000).unwrap(), None);
+        assert!(!LATCH.is_set());
+        assert_eq!(button.poll(3_800).unwrap(), Some(Gesture::LongPress));
+    }
+
+    #[test]
+    fn bounces_on_release_are_not_taps() {
+        let mut recognizer = GestureRecognizer::default();
+        recognizer.update(true, 0);
+        recognizer.update(true, 50);
+        // Released, and the contact chattering set the latch
+        recognizer.update(false, 100);
+        assert_eq!(recognizer.tap(105), None);
+        assert_eq!(recognizer.update(false, 150), None);
+        assert_eq!(recognizer.update(false, 500), Some(Gesture::Press));
+    }
+
+    #[test]
+    fn presses_cut_sleeps_short() {
+        static LATCH: PressLatch = PressLatch::new();
+        let delay = MockDelay::default();
+        let mut waking = WakeOnPress::new(delay.clone(), &LATCH);
+        waking.delay_ms(1_250);
+        assert_eq!(delay.elapsed_ms(), 1_250);
+
+        // Already pressed, so there's no sleeping until it's been polled
+        LATCH.set();
+        waking.delay_ms(5_000);
+        assert_eq!(delay.elapsed_ms(), 1_250);
+    }
+
+    #[test]
+    fn presses_end_long_sleeps_early() {
+        static LATCH: PressLatch = PressLatch::new();
+        /// Pressed once the sleep's part way through
+        struct PressedAt(MockDelay, u32);
+        impl DelayMs<u32> for PressedAt {
+            fn delay_ms(&mut self, ms: u32) {
+                // An interrupt would end this part early too
+                let ms = ms.min(self.1.saturating_sub(self.0.elapsed_ms()));
+                self.0.delay_ms(ms);
+                if self.0.elapsed_ms() >= self.1 {
+                    LATCH.set();
+                }
+            }
+        }
+        let delay = MockDelay::default();
+        let mut waking = WakeOnPress::new(PressedAt(delay.clone(), 700), &LATCH);
+        waking.delay_ms(5_000);
+        assert_eq!(delay.elapsed_ms(), 700);
+    }
+}
@@ -0,0 +1,337 @@
+//! User calibration of thermocouple readings against a reference thermometer.
+//!
+//! Readings are corrected as `gain * reading + offset`, in Celsius. Every oven and probe placement
+//! reads a little differently, so this is either a plain offset or a two-point correction from a
+//! pair of readings taken next to a trusted thermometer (or in ice water and boiling water).
+
+use crate::temperature::Temperature;
+
+/// Offsets beyond this (C) are a typo, not a miscalibrated probe
+pub const MAX_OFFSET: f32 = 30.;
+/// Smallest gain we'll accept
+pub const MIN_GAIN: f32 = 0.8;
+/// Largest gain we'll accept
+pub const MAX_GAIN: f32 = 1.2;
+/// Two-point readings closer together than this (C) give a meaningless gain
+pub const MIN_SPAN: f32 = 10.;
+
+/// Water freezes at 0C
+const ICE_POINT: Temperature = Temperature::from_celsius(0.);
+/// Water boils at 100C (at sea level)
+const BOILING_POINT: Temperature = Temperature::from_celsius(100.);
+
+/// Marks persisted calibration words as ours
+const PERSIST_MAGIC: u32 = 0xCA1B_0001;
+
+/// Reasons a calibration can't be computed
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum CalibrationError {
+    /// The two readings are too close together to compute a gain from
+    PointsTooClose,
+    /// A reading isn't a real number
+    InvalidReading,
+    /// The readings are too far off to correct with a gain in `MIN_GAIN..=MAX_GAIN`, or the
+    /// pairs are crossed
+    GainOutOfRange,
+}
+
+impl CalibrationError {
+    /// A short description of the error, for reporting back to the user
+    #[must_use]
+    pub fn message(&self) -> &'static str {
+        match self {
+            CalibrationError::PointsTooClose => "points too close together",
+            CalibrationError::InvalidReading => "invalid reading",
+            CalibrationError::GainOutOfRange => "gain out of range",
+        }
+    }
+}
+
+/// A linear correction applied to thermocouple readings
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Calibration {
+    gain: f32,
+    /// Celsius
+    offset: f32,
+}
+
+impl Calibration {
+    /// The calibration that leaves readings alone
+    pub const IDENTITY: Self = Self {
+        gain: 1.,
+        offset: 0.,
+    };
+
+    /// Creates a calibration, clamping the gain and offset to sane values
+    ///
+    /// # Arguments
+    /// * `gain`: Multiplier applied to readings, clamped to `MIN_GAIN..=MAX_GAIN`
+    /// * `offset`: Celsius added to readings after the gain, clamped to `±MAX_OFFSET`
+    #[must_use]
+    pub fn new(gain: f32, offset: f32) -> Self {
+        // NaN would survive clamping, so fall back to no correction
+        let gain = if gain.is_finite() {
+            gain.clamp(MIN_GAIN, MAX_GAIN)
+        } else {
+            Self::IDENTITY.gain
+        };
+        let offset = if offset.is_finite() {
+            offset.clamp(-MAX_OFFSET, MAX_OFFSET)
+        } else {
+            Self::IDENTITY.offset
+        };
+        Self { gain, offset }
+    }
+
+    /// A calibration that shifts every reading by the given number of degrees Celsius
+    #[must_use]
+    pub fn from_offset(offset: f32) -> Self {
+        Self::new(1., offset)
+    }
+
+    /// This calibration with its offset replaced, keeping the gain, so a two-point calibration can
+    /// be nudged without losing it
+    #[must_use]
+    pub fn with_offset(self, offset: f32) -> Self {
+        Self::new(self.gain, offset)
+    }
+
+    /// Computes the calibration that maps two measured readings onto their actual temperatures
+    ///
+    /// # Arguments
+    /// * `low`: `(measured, actual)` for the first reference
+    /// * `high`: `(measured, actual)` for the second reference
+    pub fn two_point(
+        low: (Temperature, Temperature),
+        high: (Temperature, Temperature),
+    ) -> Result<Self, CalibrationError> {
+        let (measured_low, actual_low) = (low.0.celsius(), low.1.celsius());
+        let (measured_high, actual_high) = (high.0.celsius(), high.1.celsius());
+        if ![measured_low, actual_low, measured_high, actual_high]
+            .iter()
+            .all(|t| t.is_finite())
+        {
+            return Err(CalibrationError::InvalidReading);
+        }
+        if (measured_high - measured_low).abs() < MIN_SPAN
+            || (actual_high - actual_low).abs() < MIN_SPAN
+        {
+            return Err(CalibrationError::PointsTooClose);
+        }
+
+        let gain = (actual_high - actual_low) / (measured_high - measured_low);
+        // Clamping it would miss both references
+        if !(MIN_GAIN..=MAX_GAIN).contains(&gain) {
+            return Err(CalibrationError::GainOutOfRange);
+        }
+        Ok(Self::new(gain, actual_low - gain * measured_low))
+    }
+
+    /// Computes the calibration from readings taken in ice water and boiling water
+    pub fn ice_boil(
+        measured_ice: Temperature,
+        measured_boil: Temperature,
+    ) -> Result<Self, CalibrationError> {
+        Self::two_point((measured_ice, ICE_POINT), (measured_boil, BOILING_POINT))
+    }
+
+    /// Multiplier applied to readings
+    #[must_use]
+    pub fn gain(&self) -> f32 {
+        self.gain
+    }
+
+    /// Degrees Celsius added to readings after the gain
+    #[must_use]
+    pub fn offset(&self) -> f32 {
+        self.offset
+    }
+
+    /// Corrects a reading
+    #[must_use]
+    pub fn apply(&self, temp: Temperature) -> Temperature {
+        Temperature::from_celsius(self.gain * temp.celsius() + self.offset)
+    }
+
+    /// Undoes `apply`, giving back the reading a corrected temperature came from
+    #[must_use]
+    pub fn unapply(&self, temp: Temperature) -> Temperature {
+        // The gain is clamped well away from zero
+        Temperature::from_celsius((temp.celsius() - self.offset) / self.gain)
+    }
+
+    /// Packs the calibration into words for persisting, with a magic number and checksum
+    #[must_use]
+    pub fn to_words(&self) -> [u32; 4] {
+        let gain = self.gain.to_bits();
+        let offset = self.offset.to_bits();
+        [PERSIST_MAGIC, gain, offset, checksum(gain, offset)]
+    }
+
+    /// Unpacks a calibration persisted with `to_words`
+    ///
+    /// # Returns
+    /// the calibration, or `None` if the words are garbage (never written, or corrupted)
+    #[must_use]
+    pub fn from_words(words: &[u32; 4]) -> Option<Self> {
+        let [magic, gain, offset, sum] = *words;
+        if magic != PERSIST_MAGIC || sum != checksum(gain, offset) {
+            return None;
+        }
+        Some(Self::new(f32::from_bits(gain), f32::from_bits(offset)))
+    }
+}
+
+impl Default for Calibration {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+fn checksum(gain: u32, offset: u32) -> u32 {
+    !(PERSIST_MAGIC ^ gain.rotate_left(7) ^ offset.rotate_left(19))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn c(celsius: f32) -> Temperature {
+        Temperature::from_celsius(celsius)
+    }
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!(
+            (actual - expected).abs() < 0.001,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn identity_leaves_readings_alone() {
+        let cal = Calibration::default();
+        assert_eq!(cal.apply(c(176.5)), c(176.5));
+    }
+
+    #[test]
+    fn offset() {
+        let cal = Calibration::from_offset(-4.5);
+        assert_close(cal.apply(c(200.)).celsius(), 195.5);
+        assert_close(cal.apply(c(20.)).celsius(), 15.5);
+    }
+
+    #[test]
+    fn unapply_undoes_apply() {
+        let cal = Calibration::new(1.05, -2.25);
+        assert_close(cal.unapply(cal.apply(c(180.))).celsius(), 180.);
+        assert_close(cal.apply(cal.unapply(c(-12.))).celsius(), -12.);
+        assert_eq!(Calibration::IDENTITY.unapply(c(176.5)), c(176.5));
+    }
+
+    #[test]
+    fn with_offset_keeps_the_gain() {
+        let cal = Calibration::new(1.05, -2.25).with_offset(3.);
+        assert_eq!((cal.gain(), cal.offset()), (1.05, 3.));
+        assert_eq!(
+            Calibration::IDENTITY.with_offset(-4.5),
+            Calibration::from_offset(-4.5)
+        );
+        assert_eq!(cal.with_offset(500.).offset(), MAX_OFFSET);
+    }
+
+    #[test]
+    fn two_point_maps_references() {
+        // Probe reads 3C high at room temperature and 12C high at 350F
+        let low = (c(23.), c(20.));
+        let high = (c(188.7), c(176.7));
+        let cal = Calibration::two_point(low, high).unwrap();
+        assert_close(cal.apply(low.0).celsius(), 20.);
+        assert_close(cal.apply(high.0).celsius(), 176.7);
+        assert!(cal.gain() < 1.);
+
+        // Order doesn't matter
+        let swapped = Calibration::two_point(high, low).unwrap();
+        assert_close(swapped.gain(), cal.gain());
+        assert_close(swapped.offset(), cal.offset());
+    }
+
+    #[test]
+    fn ice_boil() {
+        let cal = Calibration::ice_boil(c(1.), c(98.)).unwrap();
+        assert_close(cal.apply(c(1.)).celsius(), 0.);
+        assert_close(cal.apply(c(98.)).celsius(), 100.);
+        assert_close(cal.gain(), 100. / 97.);
+    }
+
+    #[test]
+    fn rejects_degenerate_points() {
+        assert_eq!(
+            Calibration::two_point((c(100.), c(100.)), (c(105.), c(110.))),
+            Err(CalibrationError::PointsTooClose)
+        );
+        assert_eq!(
+            Calibration::two_point((c(20.), c(100.)), (c(200.), c(100.))),
+            Err(CalibrationError::PointsTooClose)
+        );
+        assert_eq!(
+            Calibration::ice_boil(c(f32::NAN), c(100.)),
+            Err(CalibrationError::InvalidReading)
+        );
+    }
+
+    #[test]
+    fn clamps_absurd_values() {
+        assert_eq!(Calibration::from_offset(500.).offset(), MAX_OFFSET);
+        assert_eq!(Calibration::from_offset(-500.).offset(), -MAX_OFFSET);
+        assert_eq!(Calibration::new(3., 0.).gain(), MAX_GAIN);
+        assert_eq!(Calibration::new(-1., 0.).gain(), MIN_GAIN);
+        assert_eq!(
+            Calibration::new(f32::NAN, f32::INFINITY),
+            Calibration::IDENTITY
+        );
+    }
+
+    #[test]
+    fn rejects_gains_out_of_range() {
+        // Readings that are wildly off, rather than a correction that misses both
+        assert_eq!(
+            Calibration::ice_boil(c(0.), c(20.)),
+            Err(CalibrationError::GainOutOfRange)
+        );
+        assert_eq!(
+            Calibration::two_point((c(20.), c(20.)), (c(100.), c(140.))),
+            Err(CalibrationError::GainOutOfRange)
+        );
+        // Pairs typed crossed give a negative gain
+        assert_eq!(
+            Calibration::two_point((c(75.), c(350.)), (c(360.), c(70.))),
+            Err(CalibrationError::GainOutOfRange)
+        );
+
+        // Right at the limits, both references still map
+        let low = (c(20.), c(20.));
+        let high = (c(120.), c(140.));
+        let cal = Calibration::two_point(low, high).unwrap();
+        assert_close(cal.gain(), MAX_GAIN);
+        assert_close(cal.apply(low.0).celsius(), 20.);
+        assert_close(cal.apply(high.0).celsius(), 140.);
+        let cal = Calibration::two_point((c(20.), c(20.)), (c(120.), c(100.))).unwrap();
+        assert_close(cal.gain(), MIN_GAIN);
+        assert_close(cal.apply(c(120.)).celsius(), 100.);
+    }
+
+    #[test]
+    fn persists() {
+        let cal = Calibration::new(1.05, -2.25);
+        assert_eq!(Calibration::from_words(&cal.to_words()), Some(cal));
+
+        // Never written, or corrupted
+        assert_eq!(Calibration::from_words(&[0; 4]), None);
+        assert_eq!(Calibration::from_words(&[0xFFFF_FFFF; 4]), None);
+        let mut words = cal.to_words();
+        words[2] ^= 0x10;
+        assert_eq!(Calibration::from_words(&words), None);
+    }
+}
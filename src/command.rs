@@ -0,0 +1,335 @@
+//! Parsing of the text commands accepted over USB serial.
+//!
+//! Commands are a line of whitespace separated words. Temperatures are in the unit the app is
+//! configured to display.
+//!
+//! * `cal`: report the current calibration
+//! * `cal reset`: remove any calibration
+//! * `cal offset <degrees>`: shift every reading by the given amount, keeping any gain from a
+//!   two-point calibration
+//! * `cal iceboil <ice reading> <boiling reading>`: two-point calibration from what the probe
+//!   read in ice water and in boiling water
+//! * `cal 2pt <reading> <actual> <reading> <actual>`: two-point calibration from what the probe
+//!   read next to a reference thermometer at two different temperatures
+//! * `set`: show what the oven's dial is really set to, judging by how its thermostat cycles
+//! * `timer`: report how long is left on the countdown timer
+//! * `timer <minutes>`: start the countdown timer
+//! * `timer stop`: stop the countdown timer, or its alert
+//! * `unit`: report the unit temperatures are shown in
+//! * `unit c` / `unit f`: show temperatures in Celsius or Fahrenheit
+//! * `unit show` / `unit hide`: whether to give up the display's last digit for the unit
+
+use crate::temperature::TemperatureUnit;
+use core::str::{self, SplitWhitespace};
+
+/// A parsed command
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Command {
+    /// Report the current calibration
+    ShowCalibration,
+    /// Remove any calibration
+    ResetCalibration,
+    /// Shift every reading by the given number of degrees, keeping the calibration's gain
+    CalibrateOffset(f32),
+    /// Two-point calibration from readings taken in ice water and boiling water
+    CalibrateIceBoil { ice: f32, boil: f32 },
+    /// Two-point calibration from `(reading, actual)` pairs
+    CalibrateTwoPoint { low: (f32, f32), high: (f32, f32) },
+    /// Show the estimated set point
+    ShowSetPoint,
+    /// Report how long is left on the countdown timer
+    ShowTimer,
+    /// Start the countdown timer for the given number of minutes
+    StartTimer(f32),
+    /// Stop the countdown timer, or its alert
+    StopTimer,
+    /// Report the unit temperatures are shown in
+    ShowUnit,
+    /// Show temperatures in the given unit
+    SetUnit(TemperatureUnit),
+    /// Whether to show the unit after temperatures on the display
+    SetUnitShown(bool),
+}
+
+/// Reasons a line couldn't be parsed into a command
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ParseError {
+    /// The line was blank
+    Empty,
+    /// The command (or subcommand) isn't one we know
+    UnknownCommand,
+    /// The command needs more arguments
+    MissingArgument,
+    /// The command was given more arguments than it takes
+    UnexpectedArgument,
+    /// An argument that should be a number isn't
+    InvalidNumber,
+}
+
+impl ParseError {
+    /// A short description of the error, for reporting back to the user
+    #[must_use]
+    pub fn message(&self) -> &'static str {
+        match self {
+            ParseError::Empty => "empty command",
+            ParseError::UnknownCommand => "unknown command",
+            ParseError::MissingArgument => "missing argument",
+            ParseError::UnexpectedArgument => "too many arguments",
+            ParseError::InvalidNumber => "invalid number",
+        }
+    }
+}
+
+/// Parses a line of input into a command
+pub fn parse(line: &str) -> Result<Command, ParseError> {
+    let mut words = line.split_whitespace();
+    let command = match words.next().ok_or(ParseError::Empty)? {
+        "cal" => parse_calibration(&mut words)?,
+        "set" => Command::ShowSetPoint,
+        "timer" => parse_timer(&mut words)?,
+        "unit" => parse_unit(&mut words)?,
+        _ => return Err(ParseError::UnknownCommand),
+    };
+    match words.next() {
+        Some(_) => Err(ParseError::UnexpectedArgument),
+        None => Ok(command),
+    }
+}
+
+fn parse_calibration(words: &mut SplitWhitespace<'_>) -> Result<Command, ParseError> {
+    let subcommand = match words.next() {
+        Some(subcommand) => subcommand,
+        None => return Ok(Command::ShowCalibration),
+    };
+    match subcommand {
+        "reset" => Ok(Command::ResetCalibration),
+        "offset" => Ok(Command::CalibrateOffset(number(words)?)),
+        "iceboil" => Ok(Command::CalibrateIceBoil {
+            ice: number(words)?,
+            boil: number(words)?,
+        }),
+        "2pt" => Ok(Command::CalibrateTwoPoint {
+            low: (number(words)?, number(words)?),
+            high: (number(words)?, number(words)?),
+        }),
+        _ => Err(ParseError::UnknownCommand),
+    }
+}
+
+fn parse_timer(words: &mut SplitWhitespace<'_>) -> Result<Command, ParseError> {
+    let argument = match words.next() {
+        Some(argument) => argument,
+        None => return Ok(Command::ShowTimer),
+    };
+    if argument == "stop" {
+        return Ok(Command::StopTimer);
+    }
+    match argument.parse::<f32>() {
+        Ok(minutes) if minutes.is_finite() && minutes > 0. => Ok(Command::StartTimer(minutes)),
+        _ => Err(ParseError::InvalidNumber),
+    }
+}
+
+fn parse_unit(words: &mut SplitWhitespace<'_>) -> Result<Command, ParseError> {
+    let argument = match words.next() {
+        Some(argument) => argument,
+        None => return Ok(Command::ShowUnit),
+    };
+    match argument {
+        "c" | "C" => Ok(Command::SetUnit(TemperatureUnit::Celsius)),
+        "f" | "F" => Ok(Command::SetUnit(TemperatureUnit::Fahrenheit)),
+        "show" => Ok(Command::SetUnitShown(true)),
+        "hide" => Ok(Command::SetUnitShown(false)),
+        _ => Err(ParseError::UnknownCommand),
+    }
+}
+
+/// Parses the next word as a (finite) number
+fn number(words: &mut SplitWhitespace<'_>) -> Result<f32, ParseError> {
+    let word = words.next().ok_or(ParseError::MissingArgument)?;
+    match word.parse::<f32>() {
+        Ok(value) if value.is_finite() => Ok(value),
+        _ => Err(ParseError::InvalidNumber),
+    }
+}
+
+/// Assembles bytes trickling in over serial into lines of up to `N` bytes
+pub struct LineBuffer<const N: usize> {
+    partial: [u8; N],
+    partial_len: usize,
+    /// The current line is too long, so drop it
+    overflowed: bool,
+    line: [u8; N],
+    line_len: Option<usize>,
+}
+
+impl<const N: usize> LineBuffer<N> {
+    /// Creates an empty buffer
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            partial: [0; N],
+            partial_len: 0,
+            overflowed: false,
+            line: [0; N],
+            line_len: None,
+        }
+    }
+
+    /// Adds received bytes. A completed line replaces any line that hasn't been taken yet.
+    pub fn push(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            match byte {
+                b'\r' | b'\n' => {
+                    if !self.overflowed && self.partial_len > 0 {
+                        self.line[..self.partial_len]
+                            .copy_from_slice(&self.partial[..self.partial_len]);
+                        self.line_len = Some(self.partial_len);
+                    }
+                    self.partial_len = 0;
+                    self.overflowed = false;
+                }
+                // Backspace and delete, for people typing into a terminal
+                0x08 | 0x7F => self.partial_len = self.partial_len.saturating_sub(1),
+                _ if self.partial_len == N => self.overflowed = true,
+                _ => {
+                    self.partial[self.partial_len] = *byte;
+                    self.partial_len += 1;
+                }
+            }
+        }
+    }
+
+    /// Takes the most recently completed line, if there is one
+    pub fn take_line(&mut self) -> Option<Line<N>> {
+        self.line_len.take().map(|len| Line {
+            bytes: self.line,
+            len,
+        })
+    }
+}
+
+impl<const N: usize> Default for LineBuffer<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A complete line taken from a [`LineBuffer`]
+pub struct Line<const N: usize> {
+    bytes: [u8; N],
+    len: usize,
+}
+
+impl<const N: usize> Line<N> {
+    /// The line's text, or `None` if it isn't valid UTF-8
+    #[must_use]
+    pub fn as_str(&self) -> Option<&str> {
+        str::from_utf8(&self.bytes[..self.len]).ok()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parses_calibration() {
+        assert_eq!(parse("cal"), Ok(Command::ShowCalibration));
+        assert_eq!(parse("  cal  reset "), Ok(Command::ResetCalibration));
+        assert_eq!(parse("cal offset -4.5"), Ok(Command::CalibrateOffset(-4.5)));
+        assert_eq!(
+            parse("cal iceboil 33 210.5"),
+            Ok(Command::CalibrateIceBoil {
+                ice: 33.,
+                boil: 210.5
+            })
+        );
+        assert_eq!(
+            parse("cal 2pt 75 70 360 350"),
+            Ok(Command::CalibrateTwoPoint {
+                low: (75., 70.),
+                high: (360., 350.)
+            })
+        );
+    }
+
+    #[test]
+    fn parses_set_point() {
+        assert_eq!(parse("set"), Ok(Command::ShowSetPoint));
+        assert_eq!(parse("set 350"), Err(ParseError::UnexpectedArgument));
+    }
+
+    #[test]
+    fn parses_timer() {
+        assert_eq!(parse("timer"), Ok(Command::ShowTimer));
+        assert_eq!(parse("timer 12"), Ok(Command::StartTimer(12.)));
+        assert_eq!(parse("timer 0.5"), Ok(Command::StartTimer(0.5)));
+        assert_eq!(parse("timer stop"), Ok(Command::StopTimer));
+        assert_eq!(parse("timer 0"), Err(ParseError::InvalidNumber));
+        assert_eq!(parse("timer -5"), Err(ParseError::InvalidNumber));
+        assert_eq!(parse("timer soon"), Err(ParseError::InvalidNumber));
+        assert_eq!(parse("timer 5 10"), Err(ParseError::UnexpectedArgument));
+    }
+
+    #[test]
+    fn parses_unit() {
+        assert_eq!(parse("unit"), Ok(Command::ShowUnit));
+        assert_eq!(
+            parse("unit c"),
+            Ok(Command::SetUnit(TemperatureUnit::Celsius))
+        );
+        assert_eq!(
+            parse("unit F"),
+            Ok(Command::SetUnit(TemperatureUnit::Fahrenheit))
+        );
+        assert_eq!(parse("unit show"), Ok(Command::SetUnitShown(true)));
+        assert_eq!(parse("unit hide"), Ok(Command::SetUnitShown(false)));
+        assert_eq!(parse("unit k"), Err(ParseError::UnknownCommand));
+        assert_eq!(parse("unit c show"), Err(ParseError::UnexpectedArgument));
+    }
+
+    #[test]
+    fn rejects_bad_commands() {
+        assert_eq!(parse(""), Err(ParseError::Empty));
+        assert_eq!(parse("   "), Err(ParseError::Empty));
+        assert_eq!(parse("bake 350"), Err(ParseError::UnknownCommand));
+        assert_eq!(parse("cal gain 2"), Err(ParseError::UnknownCommand));
+        assert_eq!(parse("cal offset"), Err(ParseError::MissingArgument));
+        assert_eq!(parse("cal 2pt 75 70 360"), Err(ParseError::MissingArgument));
+        assert_eq!(parse("cal offset 5 6"), Err(ParseError::UnexpectedArgument));
+        assert_eq!(parse("cal offset five"), Err(ParseError::InvalidNumber));
+        assert_eq!(parse("cal offset nan"), Err(ParseError::InvalidNumber));
+        assert_eq!(parse("cal offset inf"), Err(ParseError::InvalidNumber));
+    }
+
+    #[test]
+    fn assembles_lines() {
+        let mut buffer = LineBuffer::<16>::new();
+        buffer.push(b"cal ");
+        assert!(buffer.take_line().is_none());
+        buffer.push(b"reset\r\n");
+        assert_eq!(buffer.take_line().unwrap().as_str(), Some("cal reset"));
+        assert!(buffer.take_line().is_none());
+
+        // Blank lines are ignored, and only the latest line is kept
+        buffer.push(b"\r\n\r\none\rtwo\n");
+        assert_eq!(buffer.take_line().unwrap().as_str(), Some("two"));
+
+        // Backspace
+        buffer.push(b"cak\x08l\x7f\x7f\x7f\x7fcal\n");
+        assert_eq!(buffer.take_line().unwrap().as_str(), Some("cal"));
+    }
+
+    #[test]
+    fn drops_overlong_lines() {
+        let mut buffer = LineBuffer::<4>::new();
+        buffer.push(b"cal offset 5\ncal\n");
+        assert_eq!(buffer.take_line().unwrap().as_str(), Some("cal"));
+        buffer.push(b"cal offset 5\n");
+        assert!(buffer.take_line().is_none());
+
+        buffer.push(&[b'c', 0xFF, b'\n']);
+        assert_eq!(buffer.take_line().unwrap().as_str(), None);
+    }
+}
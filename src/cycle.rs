@@ -0,0 +1,290 @@
+//! Measures how the oven's thermostat cycles the element around its set point.
+//!
+//! Dumb ovens run the element flat out until they're a bit over the set point, then let the oven
+//! coast until it's a bit under. We pick the resulting peaks and troughs out of the readings, and
+//! from them how far the temperature swings, how long a cycle takes and how much of it the element
+//! spends on. The temperature rises while the element is on, so the rising part of a cycle stands
+//! in for the element's on time.
+
+use crate::temperature::Temperature;
+use core::convert::TryFrom;
+
+/// Default for how far (C) the temperature must turn around before we believe it's a peak or
+/// trough, rather than noise
+pub const DEFAULT_CYCLE_HYSTERESIS: f32 = 2.;
+
+/// What one full thermostat cycle (trough to trough) looked like
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct CycleStats {
+    /// Average temperature over the cycle
+    pub mean: Temperature,
+    /// Middle of the thermostat's band, halfway between the peak and troughs
+    pub center: Temperature,
+    /// Peak to trough difference (averaging the troughs either side), C
+    pub swing: f32,
+    /// Time from one trough to the next, ms
+    pub period_ms: u32,
+    /// Fraction of the cycle the element was on, from 0 to 1
+    pub duty_cycle: f32,
+}
+
+/// A reading we've decided was a local peak or trough
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct Extremum {
+    time_ms: u64,
+    celsius: f32,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Direction {
+    /// Haven't moved far enough from the first reading to tell
+    Unknown,
+    Rising,
+    Falling,
+}
+
+/// Picks thermostat cycles out of a stream of readings
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct CycleAnalyzer {
+    hysteresis: f32,
+    direction: Direction,
+    /// First reading while `Unknown`, otherwise the most extreme reading in our direction
+    extreme: Option<Extremum>,
+    /// Last confirmed trough, which the cycle in progress started at
+    trough: Option<Extremum>,
+    /// Confirmed peak of the cycle in progress
+    peak: Option<Extremum>,
+    /// Sum and count of readings since `trough` was confirmed
+    sum: f32,
+    count: u32,
+    stats: Option<CycleStats>,
+}
+
+impl CycleAnalyzer {
+    /// Creates an analyzer that hasn't seen any readings
+    ///
+    /// # Arguments
+    /// * `hysteresis`: How far (C) the temperature must turn around to count as a peak or trough
+    #[must_use]
+    pub const fn new(hysteresis: f32) -> Self {
+        Self {
+            hysteresis,
+            direction: Direction::Unknown,
+            extreme: None,
+            trough: None,
+            peak: None,
+            sum: 0.,
+            count: 0,
+            stats: None,
+        }
+    }
+
+    /// The most recently completed cycle, if we've seen one since the last reset
+    #[must_use]
+    pub fn stats(&self) -> Option<CycleStats> {
+        self.stats
+    }
+
+    /// Forgets every reading and completed cycle
+    pub fn reset(&mut self) {
+        *self = Self::new(self.hysteresis);
+    }
+
+    /// Adds a reading taken at the given time (ms)
+    ///
+    /// # Returns
+    /// the stats of the cycle the reading completed, if it did
+    pub fn push(&mut self, time_ms: u64, temp: Temperature) -> Option<CycleStats> {
+        let reading = Extremum {
+            time_ms,
+            celsius: temp.celsius(),
+        };
+        if self.trough.is_some() {
+            self.sum += reading.celsius;
+            self.count += 1;
+        }
+
+        let extreme = match self.extreme {
+            Some(extreme) => extreme,
+            None => {
+                self.extreme = Some(reading);
+                return None;
+            }
+        };
+        let change = reading.celsius - extreme.celsius;
+        match self.direction {
+            Direction::Unknown => {
+                if change >= self.hysteresis {
+                    self.direction = Direction::Rising;
+                    self.extreme = Some(reading);
+                } else if change <= -self.hysteresis {
+                    self.direction = Direction::Falling;
+                    self.extreme = Some(reading);
+                }
+                None
+            }
+            Direction::Rising => {
+                if change > 0. {
+                    self.extreme = Some(reading);
+                } else if change <= -self.hysteresis {
+                    // Only count peaks that follow the trough the cycle started at
+                    if self.trough.is_some() {
+                        self.peak = Some(extreme);
+                    }
+                    self.direction = Direction::Falling;
+                    self.extreme = Some(reading);
+                }
+                None
+            }
+            Direction::Falling => {
+                if change < 0. {
+                    self.extreme = Some(reading);
+                    None
+                } else if change >= self.hysteresis {
+                    self.direction = Direction::Rising;
+                    self.extreme = Some(reading);
+                    self.end_cycle(extreme)
+                } else {
+                    None
+                }
+            }
+        }
+    }
+
+    /// Closes out the cycle in progress at the given trough, starting the next one
+    fn end_cycle(&mut self, trough: Extremum) -> Option<CycleStats> {
+        let completed = match (self.trough, self.peak) {
+            (Some(start), Some(peak)) if self.count > 0 && trough.time_ms > start.time_ms => {
+                let period_ms = trough.time_ms - start.time_ms;
+                let rising_ms = peak.time_ms - start.time_ms;
+                let swing = peak.celsius - (start.celsius + trough.celsius) / 2.;
+                Some(CycleStats {
+                    mean: Temperature::from_celsius(self.sum / self.count as f32),
+                    center: Temperature::from_celsius(peak.celsius - swing / 2.),
+                    swing,
+                    period_ms: u32::try_from(period_ms).unwrap_or(u32::MAX),
+                    duty_cycle: rising_ms as f32 / period_ms as f32,
+                })
+            }
+            _ => None,
+        };
+
+        self.trough = Some(trough);
+        self.peak = None;
+        self.sum = 0.;
+        self.count = 0;
+        if completed.is_some() {
+            self.stats = completed;
+        }
+        completed
+    }
+}
+
+impl Default for CycleAnalyzer {
+    fn default() -> Self {
+        Self::new(DEFAULT_CYCLE_HYSTERESIS)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+
+    use super::*;
+    use core::f32::consts::PI;
+    use std::vec::Vec;
+
+    /// Feeds one reading a second from `trace` (seconds -> C), returning every completed cycle
+    fn analyze(seconds: u32, trace: impl Fn(f32) -> f32) -> Vec<CycleStats> {
+        let mut analyzer = CycleAnalyzer::default();
+        (0..seconds)
+            .filter_map(|n| {
+                let temp = Temperature::from_celsius(trace(n as f32));
+                analyzer.push(u64::from(n) * 1_000, temp)
+            })
+            .collect()
+    }
+
+    /// Heats `rise_s` seconds from 170C to 190C, then coasts back down over `fall_s` seconds
+    fn sawtooth(rise_s: f32, fall_s: f32) -> impl Fn(f32) -> f32 {
+        move |t| {
+            let t = t % (rise_s + fall_s);
+            if t < rise_s {
+                170. + 20. * t / rise_s
+            } else {
+                190. - 20. * (t - rise_s) / fall_s
+            }
+        }
+    }
+
+    fn assert_close(actual: f32, expected: f32, tolerance: f32) {
+        assert!(
+            (actual - expected).abs() <= tolerance,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn sawtooth_cycles() {
+        // On for 90s, off for 210s
+        let cycles = analyze(30 * 60, sawtooth(90., 210.));
+        // The first trough is at 5 minutes, so we see the cycles from there on
+        assert_eq!(cycles.len(), 4);
+        for stats in cycles {
+            assert_eq!(stats.period_ms, 300_000);
+            assert_close(stats.swing, 20., 0.01);
+            assert_close(stats.duty_cycle, 0.3, 0.01);
+            assert_close(stats.mean.celsius(), 180., 0.1);
+            assert_close(stats.center.celsius(), 180., 0.01);
+        }
+    }
+
+    #[test]
+    fn sine_cycles() {
+        // 175C +/- 8C every 4 minutes, with some noise on top
+        let cycles = analyze(30 * 60, |t| {
+            let noise = if (t as u32).is_multiple_of(2) {
+                0.3
+            } else {
+                -0.3
+            };
+            175. + 8. * (2. * PI * t / 240.).sin() + noise
+        });
+        assert!(cycles.len() >= 5, "{}", cycles.len());
+        for stats in cycles {
+            assert!(
+                (239_000..=241_000).contains(&stats.period_ms),
+                "{}",
+                stats.period_ms
+            );
+            assert_close(stats.swing, 16.6, 0.5);
+            assert_close(stats.duty_cycle, 0.5, 0.02);
+            assert_close(stats.mean.celsius(), 175., 0.1);
+        }
+    }
+
+    #[test]
+    fn noise_is_not_a_cycle() {
+        let cycles = analyze(10 * 60, |t| 180. + (t * 1.7).sin());
+        assert!(cycles.is_empty());
+    }
+
+    #[test]
+    fn reset_forgets_cycles() {
+        let mut analyzer = CycleAnalyzer::default();
+        let trace = sawtooth(60., 60.);
+        for n in 0..600 {
+            analyzer.push(n * 1_000, Temperature::from_celsius(trace(n as f32)));
+        }
+        assert!(analyzer.stats().is_some());
+        analyzer.reset();
+        assert_eq!(analyzer.stats(), None);
+        // Has to see a whole cycle again
+        for n in 600..720 {
+            analyzer.push(n * 1_000, Temperature::from_celsius(trace(n as f32)));
+        }
+        assert_eq!(analyzer.stats(), None);
+    }
+}
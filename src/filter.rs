@@ -0,0 +1,384 @@
+//! Filters for smoothing noisy thermocouple samples before they're displayed.
+//!
+//! The ADC already averages 32 samples in hardware, but what's left is still enough to make the
+//! tenths digit dance. All filters work in Celsius, one sample at a time.
+
+use crate::temperature::Temperature;
+
+/// Number of samples the app's median filter looks at
+pub const MEDIAN_WINDOW: usize = 5;
+
+/// Something that smooths a stream of temperature samples
+pub trait TemperatureFilter {
+    /// Adds a sample, returning the filtered temperature
+    fn update(&mut self, sample: Temperature) -> Temperature;
+
+    /// Forgets every sample seen so far
+    fn reset(&mut self);
+}
+
+/// Exponential moving average: each sample moves the output `alpha` of the way towards it
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Ema {
+    alpha: f32,
+    value: Option<f32>,
+}
+
+impl Ema {
+    /// Creates a filter with the given smoothing factor, clamped to `(0, 1]`. Smaller is smoother
+    /// but slower to respond.
+    #[must_use]
+    pub fn new(alpha: f32) -> Self {
+        let alpha = if alpha > 0. { alpha.min(1.) } else { 1. };
+        Self { alpha, value: None }
+    }
+}
+
+impl TemperatureFilter for Ema {
+    fn update(&mut self, sample: Temperature) -> Temperature {
+        let sample = sample.celsius();
+        let value = match self.value {
+            Some(value) => value + self.alpha * (sample - value),
+            None => sample,
+        };
+        self.value = Some(value);
+        Temperature::from_celsius(value)
+    }
+
+    fn reset(&mut self) {
+        self.value = None;
+    }
+}
+
+/// Median of the last `N` samples, which throws out spikes entirely
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Median<const N: usize> {
+    samples: [f32; N],
+    len: usize,
+    next: usize,
+}
+
+impl<const N: usize> Median<N> {
+    /// Creates an empty filter
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            samples: [0.; N],
+            len: 0,
+            next: 0,
+        }
+    }
+}
+
+impl<const N: usize> Default for Median<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> TemperatureFilter for Median<N> {
+    fn update(&mut self, sample: Temperature) -> Temperature {
+        if N == 0 {
+            return sample;
+        }
+        self.samples[self.next] = sample.celsius();
+        self.next = (self.next + 1) % N;
+        self.len = (self.len + 1).min(N);
+
+        let mut sorted = self.samples;
+        let sorted = &mut sorted[..self.len];
+        sorted.sort_unstable_by(|a, b| a.total_cmp(b));
+        let mid = self.len / 2;
+        let median = if self.len.is_multiple_of(2) {
+            (sorted[mid - 1] + sorted[mid]) / 2.
+        } else {
+            sorted[mid]
+        };
+        Temperature::from_celsius(median)
+    }
+
+    fn reset(&mut self) {
+        self.len = 0;
+        self.next = 0;
+    }
+}
+
+/// A one dimensional Kalman filter, modeling the temperature as a random walk
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Kalman {
+    process_noise: f32,
+    measurement_noise: f32,
+    /// Current estimate and its variance
+    estimate: Option<(f32, f32)>,
+}
+
+impl Kalman {
+    /// Creates a filter
+    ///
+    /// # Arguments
+    /// * `process_noise`: How much the real temperature wanders between samples, C^2
+    /// * `measurement_noise`: How noisy the samples are, C^2
+    #[must_use]
+    pub fn new(process_noise: f32, measurement_noise: f32) -> Self {
+        Self {
+            process_noise: process_noise.max(0.),
+            // Zero would make the gain 0/0 on the first update
+            measurement_noise: measurement_noise.max(f32::EPSILON),
+            estimate: None,
+        }
+    }
+}
+
+impl TemperatureFilter for Kalman {
+    fn update(&mut self, sample: Temperature) -> Temperature {
+        let sample = sample.celsius();
+        let (estimate, variance) = match self.estimate {
+            Some((estimate, variance)) => {
+                // Predict: the temperature may have wandered since the last sample
+                let variance = variance + self.process_noise;
+                // Correct: move towards the sample, trusting it by how uncertain we are
+                let gain = variance / (variance + self.measurement_noise);
+                (
+                    estimate + gain * (sample - estimate),
+                    (1. - gain) * variance,
+                )
+            }
+            None => (sample, self.measurement_noise),
+        };
+        self.estimate = Some((estimate, variance));
+        Temperature::from_celsius(estimate)
+    }
+
+    fn reset(&mut self) {
+        self.estimate = None;
+    }
+}
+
+/// Which filter the app smooths readings with, and how it's tuned
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum FilterConfig {
+    /// Show the raw samples
+    None,
+    /// Exponential moving average with the given smoothing factor
+    Ema { alpha: f32 },
+    /// Median of the last `MEDIAN_WINDOW` samples
+    Median,
+    /// One dimensional Kalman filter, with variances in C^2
+    Kalman {
+        process_noise: f32,
+        measurement_noise: f32,
+    },
+}
+
+impl Default for FilterConfig {
+    fn default() -> Self {
+        // An ADC count is ~0.16C, and the oven moves ~0.5C/s at most
+        FilterConfig::Kalman {
+            process_noise: 0.01,
+            measurement_noise: 0.04,
+        }
+    }
+}
+
+/// One of the filters, chosen at runtime
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Filter {
+    None,
+    Ema(Ema),
+    Median(Median<MEDIAN_WINDOW>),
+    Kalman(Kalman),
+}
+
+impl Filter {
+    /// Creates the configured filter
+    #[must_use]
+    pub fn new(config: FilterConfig) -> Self {
+        match config {
+            FilterConfig::None => Filter::None,
+            FilterConfig::Ema { alpha } => Filter::Ema(Ema::new(alpha)),
+            FilterConfig::Median => Filter::Median(Median::new()),
+            FilterConfig::Kalman {
+                process_noise,
+                measurement_noise,
+            } => Filter::Kalman(Kalman::new(process_noise, measurement_noise)),
+        }
+    }
+}
+
+impl TemperatureFilter for Filter {
+    fn update(&mut self, sample: Temperature) -> Temperature {
+        match self {
+            Filter::None => sample,
+            Filter::Ema(filter) => filter.update(sample),
+            Filter::Median(filter) => filter.update(sample),
+            Filter::Kalman(filter) => filter.update(sample),
+        }
+    }
+
+    fn reset(&mut self) {
+        match self {
+            Filter::None => {}
+            Filter::Ema(filter) => filter.reset(),
+            Filter::Median(filter) => filter.reset(),
+            Filter::Kalman(filter) => filter.reset(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Deterministic noise from a linear congruential generator
+    struct Lcg(u32);
+
+    impl Lcg {
+        /// Uniform noise in `[-1, 1)`
+        fn uniform(&mut self) -> f32 {
+            self.0 = self.0.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            (self.0 >> 8) as f32 / (1 << 23) as f32 - 1.
+        }
+
+        /// Roughly normal noise with the given standard deviation
+        fn normal(&mut self, std_dev: f32) -> f32 {
+            // The sum of 3 uniforms has a variance of 1
+            (self.uniform() + self.uniform() + self.uniform()) * std_dev
+        }
+    }
+
+    fn c(celsius: f32) -> Temperature {
+        Temperature::from_celsius(celsius)
+    }
+
+    fn filters() -> [Filter; 3] {
+        [
+            Filter::new(FilterConfig::Ema { alpha: 0.3 }),
+            Filter::new(FilterConfig::Median),
+            Filter::new(FilterConfig::default()),
+        ]
+    }
+
+    /// Variance of the filter's output around `truth` while fed noisy samples of it
+    fn output_variance(filter: &mut impl TemperatureFilter, truth: f32, noise: f32) -> f32 {
+        let mut lcg = Lcg(42);
+        // Let the filter settle first
+        for _ in 0..50 {
+            filter.update(c(truth + lcg.normal(noise)));
+        }
+        let samples = 1_000;
+        let mut sum_squares = 0.;
+        for _ in 0..samples {
+            let error = filter.update(c(truth + lcg.normal(noise))).celsius() - truth;
+            sum_squares += error * error;
+        }
+        sum_squares / samples as f32
+    }
+
+    #[test]
+    fn noise_is_random_enough() {
+        let mut lcg = Lcg(42);
+        let (mut sum, mut sum_squares) = (0., 0.);
+        for _ in 0..10_000 {
+            let sample = lcg.normal(1.);
+            sum += sample;
+            sum_squares += sample * sample;
+        }
+        assert!((sum / 10_000_f32).abs() < 0.05);
+        assert!((sum_squares / 10_000_f32 - 1.).abs() < 0.05);
+    }
+
+    #[test]
+    fn first_sample_passes_through() {
+        for mut filter in filters() {
+            assert_eq!(filter.update(c(176.)), c(176.), "{:?}", filter);
+        }
+    }
+
+    #[test]
+    fn rejects_noise() {
+        let noise = 0.3_f32;
+        for mut filter in filters() {
+            let variance = output_variance(&mut filter, 200., noise);
+            assert!(
+                variance < noise * noise / 2.,
+                "{:?}: {} vs {}",
+                filter,
+                variance,
+                noise * noise
+            );
+        }
+        assert!(output_variance(&mut Filter::None, 200., noise) > noise * noise * 0.9);
+    }
+
+    #[test]
+    fn step_response() {
+        for mut filter in filters() {
+            for _ in 0..20 {
+                filter.update(c(20.));
+            }
+            let mut previous = 20.;
+            let mut settled_after = None;
+            for n in 1..=20 {
+                let output = filter.update(c(180.)).celsius();
+                // Heads towards the new value without overshooting
+                assert!(
+                    output >= previous && output <= 180.,
+                    "{:?}: {}",
+                    filter,
+                    output
+                );
+                previous = output;
+                if settled_after.is_none() && 180. - output < 1. {
+                    settled_after = Some(n);
+                }
+            }
+            let settled_after = settled_after.unwrap();
+            assert!(settled_after <= 15, "{:?}: {}", filter, settled_after);
+        }
+    }
+
+    #[test]
+    fn median_ignores_spikes() {
+        let mut filter = Median::<MEDIAN_WINDOW>::new();
+        for sample in [200., 200.2, 600., 199.8, 200., -20., 200.1] {
+            let output = filter.update(c(sample)).celsius();
+            assert!((output - 200.).abs() < 0.3, "{} -> {}", sample, output);
+        }
+    }
+
+    #[test]
+    fn median_of_partial_window() {
+        let mut filter = Median::<4>::new();
+        assert_eq!(filter.update(c(10.)), c(10.));
+        assert_eq!(filter.update(c(20.)), c(15.));
+        assert_eq!(filter.update(c(0.)), c(10.));
+        assert_eq!(filter.update(c(30.)), c(15.));
+        // Oldest sample (10) drops out
+        assert_eq!(filter.update(c(40.)), c(25.));
+    }
+
+    #[test]
+    fn reset_forgets_history() {
+        for mut filter in filters() {
+            for _ in 0..10 {
+                filter.update(c(20.));
+            }
+            filter.reset();
+            assert_eq!(filter.update(c(200.)), c(200.), "{:?}", filter);
+        }
+    }
+
+    #[test]
+    fn clamps_tuning() {
+        let mut ema = Ema::new(0.);
+        ema.update(c(0.));
+        assert_eq!(ema.update(c(10.)), c(10.));
+        let mut ema = Ema::new(7.);
+        ema.update(c(0.));
+        assert_eq!(ema.update(c(10.)), c(10.));
+
+        let mut kalman = Kalman::new(-1., 0.);
+        assert_eq!(kalman.update(c(5.)), c(5.));
+        assert!(kalman.update(c(6.)).celsius().is_finite());
+    }
+}
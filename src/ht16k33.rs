@@ -1,8 +1,10 @@
 //! HT16K33 segment display driver, based on the Adafruit Arduino driver.
 
+use core::fmt;
+
 const DISPLAY_BUFFER_SIZE: usize = 8;
 
-const ALPHA_FONT_TABLE: [u16; 128] = [
+pub(crate) const ALPHA_FONT_TABLE: [u16; 128] = [
     0b0000_0000_0000_0001,
     0b0000_0000_0000_0010,
     0b0000_0000_0000_0100,
@@ -144,10 +146,6 @@ const ALPHA_FONT_TABLE: [u16; 128] = [
 
 const HT16K33_BLINK_CMD: u8 = 0x80;
 const HT16K33_BLINK_DISPLAYON: u8 = 0x01;
-const HT16K33_BLINK_OFF: u8 = 0;
-// const HT16K33_BLINK_2HZ: u8 = 1;
-// const HT16K33_BLINK_1HZ: u8 = 2;
-// const HT16K33_BLINK_HALFHZ: u8 = 3;
 
 const HT16K33_SYSTEM_SETUP: u8 = 0x20;
 const HT16K33_SYSTEM_SETUP_NORMAL: u8 = 0x01;
@@ -161,78 +159,330 @@ const HT16K33_CMD_BRIGHTNESS: u8 = 0xE0;
 // const BIN: u8 = 2;
 // const BYTE: u8 = 0;
 
-const ALPHA_POINT_MASK: u16 = 1 << 14;
+pub(crate) const ALPHA_POINT_MASK: u16 = 1 << 14;
+
+/// Number of characters on the display
+pub const DIGITS: u8 = 4;
+/// Shown in place of characters the font doesn't have
+const REPLACEMENT_GLYPH: char = '?';
+
+/// How fast the whole display blinks
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum BlinkRate {
+    Off,
+    TwoHz,
+    OneHz,
+    HalfHz,
+}
+
+impl BlinkRate {
+    /// The rate's bits in the display setup command
+    fn bits(self) -> u8 {
+        match self {
+            BlinkRate::Off => 0,
+            BlinkRate::TwoHz => 1,
+            BlinkRate::OneHz => 2,
+            BlinkRate::HalfHz => 3,
+        }
+    }
+}
+
+/// One of the display's 16 brightness levels
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd)]
+pub struct Brightness(u8);
+
+impl Brightness {
+    pub const MIN: Self = Self(0);
+    pub const MAX: Self = Self(15);
+
+    /// The given level, if it's one the display has
+    #[must_use]
+    pub const fn new(level: u8) -> Option<Self> {
+        if level <= Self::MAX.0 {
+            Some(Self(level))
+        } else {
+            None
+        }
+    }
+
+    /// The given level, or the brightest if it's past that
+    #[must_use]
+    pub const fn clamped(level: u8) -> Self {
+        match Self::new(level) {
+            Some(brightness) => brightness,
+            None => Self::MAX,
+        }
+    }
+
+    /// The level, from 0 to 15
+    #[must_use]
+    pub const fn level(self) -> u8 {
+        self.0
+    }
+}
+
+/// Whether the display is running
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum DisplayState {
+    /// Oscillator off, nothing shown, and barely any power drawn. The display RAM is kept.
+    Standby,
+    /// Showing the display RAM
+    On,
+}
+
+/// Errors from the display driver
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Error<CommE> {
+    /// Talking to the display over I2C failed
+    Bus(CommE),
+    /// There's no digit at this index on the display
+    InvalidDigit(u8),
+    /// The font has no glyph for this character
+    UnsupportedGlyph(char),
+    /// This value is more than a single decimal digit
+    InvalidValue(u8),
+}
+
+impl<CommE> From<CommE> for Error<CommE> {
+    fn from(err: CommE) -> Self {
+        Error::Bus(err)
+    }
+}
+
+/// The font's glyph for `character`, if it has one
+fn glyph(character: char) -> Option<u16> {
+    ALPHA_FONT_TABLE.get(character as usize).copied()
+}
+
+/// Where text shorter than the display sits on it
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Align {
+    Left,
+    Right,
+    /// Leaning left when it can't be exactly in the middle
+    Center,
+}
+
+/// Text laid out a digit at a time, as a character and whether to light the point after it
+///
+/// A `.` lights the point of the character before it rather than taking up a digit of its own,
+/// unless that point's already lit or there's nothing before it. Build one with `From<&str>` or
+/// `write!`; whatever doesn't fit in `N` digits is dropped.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Text<const N: usize> {
+    cells: [(char, bool); N],
+    len: usize,
+    truncated: bool,
+}
+
+impl<const N: usize> Text<N> {
+    /// Empty text
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            cells: [(' ', false); N],
+            len: 0,
+            truncated: false,
+        }
+    }
+
+    /// What each digit shows, from the first
+    #[must_use]
+    pub fn cells(&self) -> &[(char, bool)] {
+        &self.cells[..self.len]
+    }
+
+    /// Whether anything was dropped for not fitting
+    #[must_use]
+    pub fn is_truncated(&self) -> bool {
+        self.truncated
+    }
+
+    /// Adds a character, folding a `.` into the one before it
+    pub fn push(&mut self, c: char) {
+        if self.truncated {
+            // Anything after what was dropped is too, even a point that could be folded in
+            return;
+        }
+        if c == '.' {
+            if let Some(last) = self.len.checked_sub(1).map(|last| &mut self.cells[last]) {
+                if !last.1 {
+                    last.1 = true;
+                    return;
+                }
+            }
+        }
+        if self.len == N {
+            self.truncated = true;
+            return;
+        }
+        self.cells[self.len] = if c == '.' { (' ', true) } else { (c, false) };
+        self.len += 1;
+    }
+}
+
+impl<const N: usize> Default for Text<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> From<&str> for Text<N> {
+    fn from(text: &str) -> Self {
+        let mut cells = Self::new();
+        for c in text.chars() {
+            cells.push(c);
+        }
+        cells
+    }
+}
+
+impl<const N: usize> fmt::Write for Text<N> {
+    /// Never fails: anything that doesn't fit is dropped
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            self.push(c);
+        }
+        Ok(())
+    }
+}
+
+/// Text that fills the display
+pub type DisplayText = Text<{ DIGITS as usize }>;
 
 pub struct HT16K33 {
     i2c_addr: u8,
     display_buffer: [u16; DISPLAY_BUFFER_SIZE],
+    /// What the display RAM holds, as far as we know
+    sent_buffer: Option<[u16; DISPLAY_BUFFER_SIZE]>,
+    brightness: Brightness,
+    blink_rate: BlinkRate,
+    state: DisplayState,
 }
 
 impl HT16K33 {
-    pub fn init<I2C, CommE>(addr: u8, i2c: &mut I2C) -> Result<Self, CommE>
+    pub fn init<I2C, CommE>(addr: u8, i2c: &mut I2C) -> Result<Self, Error<CommE>>
     where
         I2C: embedded_hal::blocking::i2c::Write<Error = CommE>,
     {
-        let mut ht = Self {
+        let ht = Self {
             i2c_addr: addr,
             display_buffer: [0; DISPLAY_BUFFER_SIZE],
+            sent_buffer: None,
+            brightness: Brightness::MAX,
+            blink_rate: BlinkRate::Off,
+            state: DisplayState::On,
         };
 
-        // turn on oscillator
-        ht.configure_standby(i2c, false)?;
-        ht.blink_rate(HT16K33_BLINK_OFF, i2c)?;
-        ht.set_brightness(15, i2c)?; // max brightness
+        // We don't know what state the display was left in, so send everything
+        ht.send_state(ht.state, i2c)?; // turn on oscillator
+        ht.send_blink_rate(ht.blink_rate, i2c)?;
+        ht.send_brightness(ht.brightness, i2c)?; // max brightness
 
         Ok(ht)
     }
 
-    pub fn set_brightness<I2C, CommE>(&mut self, b: u8, i2c: &mut I2C) -> Result<(), CommE>
+    /// The brightness last set
+    #[must_use]
+    pub fn brightness(&self) -> Brightness {
+        self.brightness
+    }
+
+    /// Sets the brightness, if it isn't already
+    pub fn set_brightness<I2C, CommE>(
+        &mut self,
+        brightness: Brightness,
+        i2c: &mut I2C,
+    ) -> Result<(), Error<CommE>>
     where
         I2C: embedded_hal::blocking::i2c::Write<Error = CommE>,
     {
-        let mut b = b;
-        if b > 15 {
-            b = 15;
+        if brightness != self.brightness {
+            self.send_brightness(brightness, i2c)?;
+            self.brightness = brightness;
         }
-        let data: [u8; 1] = [HT16K33_CMD_BRIGHTNESS | b];
+        Ok(())
+    }
+
+    fn send_brightness<I2C, CommE>(
+        &self,
+        brightness: Brightness,
+        i2c: &mut I2C,
+    ) -> Result<(), Error<CommE>>
+    where
+        I2C: embedded_hal::blocking::i2c::Write<Error = CommE>,
+    {
+        let data: [u8; 1] = [HT16K33_CMD_BRIGHTNESS | brightness.level()];
         i2c.write(self.i2c_addr, &data)?;
         Ok(())
     }
 
-    pub fn blink_rate<I2C, CommE>(&mut self, b: u8, i2c: &mut I2C) -> Result<(), CommE>
+    /// The blink rate last set
+    #[must_use]
+    pub fn blink_rate(&self) -> BlinkRate {
+        self.blink_rate
+    }
+
+    /// Sets the blink rate, if it isn't already
+    pub fn set_blink_rate<I2C, CommE>(
+        &mut self,
+        rate: BlinkRate,
+        i2c: &mut I2C,
+    ) -> Result<(), Error<CommE>>
     where
         I2C: embedded_hal::blocking::i2c::Write<Error = CommE>,
     {
-        let mut bm = b;
-        if bm > 3 {
-            bm = 0; // turn off if not sure
+        if rate != self.blink_rate {
+            self.send_blink_rate(rate, i2c)?;
+            self.blink_rate = rate;
         }
+        Ok(())
+    }
 
-        let data: [u8; 1] = [HT16K33_BLINK_CMD | HT16K33_BLINK_DISPLAYON | (bm << 1)];
+    fn send_blink_rate<I2C, CommE>(
+        &self,
+        rate: BlinkRate,
+        i2c: &mut I2C,
+    ) -> Result<(), Error<CommE>>
+    where
+        I2C: embedded_hal::blocking::i2c::Write<Error = CommE>,
+    {
+        let data: [u8; 1] = [HT16K33_BLINK_CMD | HT16K33_BLINK_DISPLAYON | (rate.bits() << 1)];
         i2c.write(self.i2c_addr, &data)?;
         Ok(())
     }
 
-    pub fn configure_standby<I2C, CommE>(
+    /// Whether the display was last put in standby or turned on
+    #[must_use]
+    pub fn state(&self) -> DisplayState {
+        self.state
+    }
+
+    /// Puts the display in standby or turns it on, if it isn't already
+    pub fn set_state<I2C, CommE>(
         &mut self,
+        state: DisplayState,
         i2c: &mut I2C,
-        standby: bool,
-    ) -> Result<(), CommE>
+    ) -> Result<(), Error<CommE>>
     where
         I2C: embedded_hal::blocking::i2c::Write<Error = CommE>,
     {
-        if standby {
-            i2c.write(
-                self.i2c_addr,
-                &[HT16K33_SYSTEM_SETUP | HT16K33_SYSTEM_SETUP_STANDBY],
-            )?;
-        } else {
-            i2c.write(
-                self.i2c_addr,
-                &[HT16K33_SYSTEM_SETUP | HT16K33_SYSTEM_SETUP_NORMAL],
-            )?;
+        if state != self.state {
+            self.send_state(state, i2c)?;
+            self.state = state;
         }
+        Ok(())
+    }
 
+    fn send_state<I2C, CommE>(&self, state: DisplayState, i2c: &mut I2C) -> Result<(), Error<CommE>>
+    where
+        I2C: embedded_hal::blocking::i2c::Write<Error = CommE>,
+    {
+        let setup = match state {
+            DisplayState::Standby => HT16K33_SYSTEM_SETUP_STANDBY,
+            DisplayState::On => HT16K33_SYSTEM_SETUP_NORMAL,
+        };
+        i2c.write(self.i2c_addr, &[HT16K33_SYSTEM_SETUP | setup])?;
         Ok(())
     }
 
@@ -242,41 +492,685 @@ impl HT16K33 {
         }
     }
 
+    /// Writes a single decimal digit. Digits that aren't on the display are ignored, and values
+    /// over 9 are shown as `?`.
     pub fn write_digit_value(&mut self, n: u8, number: u8, point: bool) {
-        self.display_buffer[n as usize] = ALPHA_FONT_TABLE[(number + 0x30) as usize];
-        if point {
-            self.display_buffer[n as usize] |= ALPHA_POINT_MASK;
+        let character = if number <= 9 {
+            char::from(b'0' + number)
+        } else {
+            REPLACEMENT_GLYPH
+        };
+        self.write_digit_ascii(n, character, point);
+    }
+
+    /// Writes a single decimal digit
+    ///
+    /// # Errors
+    /// `InvalidDigit` if `n` isn't on the display, or `InvalidValue` if `number` is over 9
+    pub fn try_write_digit_value<CommE>(
+        &mut self,
+        n: u8,
+        number: u8,
+        point: bool,
+    ) -> Result<(), Error<CommE>> {
+        if number > 9 {
+            return Err(Error::InvalidValue(number));
         }
+        self.try_write_digit_ascii(n, char::from(b'0' + number), point)
     }
 
+    /// Writes as much of `msg` as fits from the first digit on, leaving the rest of the display
+    /// alone. A `.` lights the point of the character before it (see `Text`). Use a `Marquee` for
+    /// anything longer.
     pub fn write_str(&mut self, msg: &str) {
-        for (index, c) in msg.chars().enumerate() {
-            self.write_digit_ascii(index as u8, c, false);
-            if index >= 4 {
-                break;
-            }
+        let text = DisplayText::from(msg);
+        for (index, &(c, point)) in text.cells().iter().enumerate() {
+            self.write_digit_ascii(index as u8, c, point);
+        }
+    }
+
+    /// Writes `msg` from the first digit on, leaving the rest of the display alone. A `.` lights
+    /// the point of the character before it (see `Text`).
+    ///
+    /// # Errors
+    /// `InvalidDigit` if `msg` is too long for the display, or `UnsupportedGlyph` for the first
+    /// character the font doesn't have. Nothing is written if there's an error.
+    pub fn try_write_str<CommE>(&mut self, msg: &str) -> Result<(), Error<CommE>> {
+        let text = DisplayText::from(msg);
+        if text.is_truncated() {
+            return Err(Error::InvalidDigit(DIGITS));
+        }
+        for &(c, _) in text.cells() {
+            glyph(c).ok_or(Error::UnsupportedGlyph(c))?;
+        }
+        for (index, &(c, point)) in text.cells().iter().enumerate() {
+            self.try_write_digit_ascii(index as u8, c, point)?;
         }
+        Ok(())
+    }
+
+    /// Fills the display with `msg`, padded with blanks. See `write_text`.
+    pub fn write_str_aligned(&mut self, msg: &str, align: Align) {
+        self.write_text(&DisplayText::from(msg), align);
+    }
+
+    /// Fills the display with formatted text, padded with blanks, so numbers can be shown with
+    /// `format_args!` rather than working out each digit. See `write_text`.
+    ///
+    /// # Errors
+    /// If one of the values being formatted fails to. Nothing is written if so.
+    pub fn write_fmt_aligned(&mut self, align: Align, args: fmt::Arguments<'_>) -> fmt::Result {
+        let mut text = DisplayText::new();
+        fmt::write(&mut text, args)?;
+        self.write_text(&text, align);
+        Ok(())
     }
 
+    /// Fills the display with `text`, padded with blanks. Text that's too long shows as much as
+    /// fits from the start.
+    pub fn write_text<const N: usize>(&mut self, text: &Text<N>, align: Align) {
+        let cells = &text.cells()[..text.cells().len().min(usize::from(DIGITS))];
+        let spare = usize::from(DIGITS) - cells.len();
+        let start = match align {
+            Align::Left => 0,
+            Align::Right => spare,
+            Align::Center => spare / 2,
+        };
+        for n in 0..DIGITS {
+            let (c, point) = usize::from(n)
+                .checked_sub(start)
+                .and_then(|index| cells.get(index))
+                .copied()
+                .unwrap_or((' ', false));
+            self.write_digit_ascii(n, c, point);
+        }
+    }
+
+    /// Writes a single character. Digits that aren't on the display are ignored, and characters
+    /// the font doesn't have are shown as `?`.
     pub fn write_digit_ascii(&mut self, n: u8, character: char, point: bool) {
-        self.display_buffer[n as usize] = ALPHA_FONT_TABLE[character as usize];
+        let character = if glyph(character).is_some() {
+            character
+        } else {
+            REPLACEMENT_GLYPH
+        };
+        self.try_write_digit_ascii::<core::convert::Infallible>(n, character, point)
+            .ok();
+    }
+
+    /// Writes a single character
+    ///
+    /// # Errors
+    /// `InvalidDigit` if `n` isn't on the display, or `UnsupportedGlyph` if the font doesn't have
+    /// `character`
+    pub fn try_write_digit_ascii<CommE>(
+        &mut self,
+        n: u8,
+        character: char,
+        point: bool,
+    ) -> Result<(), Error<CommE>> {
+        if n >= DIGITS {
+            return Err(Error::InvalidDigit(n));
+        }
+        let mut segments = glyph(character).ok_or(Error::UnsupportedGlyph(character))?;
         if point {
-            self.display_buffer[n as usize] |= ALPHA_POINT_MASK;
+            segments |= ALPHA_POINT_MASK;
         }
+        self.display_buffer[usize::from(n)] = segments;
+        Ok(())
     }
 
-    pub fn write_display<I2C, CommE>(&mut self, i2c: &mut I2C) -> Result<(), CommE>
+    /// Sends the digits that changed since the last write to the display
+    pub fn write_display<I2C, CommE>(&mut self, i2c: &mut I2C) -> Result<(), Error<CommE>>
     where
         I2C: embedded_hal::blocking::i2c::Write<Error = CommE>,
     {
-        // Build up the payload. Start with 0
+        let changed = |i: &usize| {
+            self.sent_buffer
+                .is_none_or(|sent| sent[*i] != self.display_buffer[*i])
+        };
+        let first = match (0..DISPLAY_BUFFER_SIZE).find(changed) {
+            Some(first) => first,
+            None => return Ok(()),
+        };
+        let last = (0..DISPLAY_BUFFER_SIZE).rfind(changed).unwrap_or(first);
+
+        // The RAM address to start at, then two bytes a digit. The address auto-increments.
         let mut data: [u8; 17] = [0; 17];
-        data[0] = 0;
-        for i in 0..8 {
-            data[2 * i + 1] = (self.display_buffer[i as usize] & 0xFF) as u8;
-            data[2 * i + 2] = (self.display_buffer[i as usize] >> 8) as u8;
+        data[0] = (2 * first) as u8;
+        for (n, i) in (first..=last).enumerate() {
+            data[2 * n + 1] = (self.display_buffer[i] & 0xFF) as u8;
+            data[2 * n + 2] = (self.display_buffer[i] >> 8) as u8;
         }
-        i2c.write(self.i2c_addr, &data)?;
+        i2c.write(self.i2c_addr, &data[..2 * (last - first + 1) + 1])?;
+        self.sent_buffer = Some(self.display_buffer);
         Ok(())
     }
+
+    /// Sends everything to the display again, changed or not, in case it's lost track (after a
+    /// brown-out, say)
+    pub fn force_refresh<I2C, CommE>(&mut self, i2c: &mut I2C) -> Result<(), Error<CommE>>
+    where
+        I2C: embedded_hal::blocking::i2c::Write<Error = CommE>,
+    {
+        self.sent_buffer = None;
+        self.send_state(self.state, i2c)?;
+        self.send_blink_rate(self.blink_rate, i2c)?;
+        self.send_brightness(self.brightness, i2c)?;
+        self.write_display(i2c)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+
+    use super::*;
+    use crate::mock::{MockI2c, MockI2cError};
+    use core::convert::Infallible;
+    use core::fmt::Write as _;
+    use std::{string::String, vec, vec::Vec};
+
+    type Result = core::result::Result<(), Error<Infallible>>;
+
+    /// A small, repeatable source of arbitrary input
+    struct XorShift(u32);
+
+    impl XorShift {
+        fn next(&mut self) -> u32 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 17;
+            self.0 ^= self.0 << 5;
+            self.0
+        }
+
+        fn byte(&mut self) -> u8 {
+            self.next() as u8
+        }
+
+        /// Mostly ASCII, with control characters and the odd multibyte character mixed in
+        fn char(&mut self) -> char {
+            match self.next() % 8 {
+                0 => char::from_u32(self.next() % 0x11_0000).unwrap_or('\u{FFFD}'),
+                1 => char::from(self.byte()),
+                _ => char::from(self.byte() & 0x7F),
+            }
+        }
+
+        fn string(&mut self) -> String {
+            let len = self.next() % 10;
+            (0..len).map(|_| self.char()).collect()
+        }
+    }
+
+    fn new_display() -> HT16K33 {
+        HT16K33::init(0x70, &mut MockI2c::default()).unwrap()
+    }
+
+    #[test]
+    fn writes_characters() {
+        let mut display = new_display();
+        display.write_digit_ascii(0, 'A', false);
+        display.write_digit_value(1, 7, true);
+        assert_eq!(
+            display.try_write_digit_ascii::<Infallible>(2, 'Z', false),
+            Ok(())
+        );
+        assert_eq!(
+            display.try_write_digit_value::<Infallible>(3, 0, true),
+            Ok(())
+        );
+        assert_eq!(
+            display.display_buffer[..4],
+            [
+                ALPHA_FONT_TABLE[usize::from(b'A')],
+                ALPHA_FONT_TABLE[usize::from(b'7')] | ALPHA_POINT_MASK,
+                ALPHA_FONT_TABLE[usize::from(b'Z')],
+                ALPHA_FONT_TABLE[usize::from(b'0')] | ALPHA_POINT_MASK,
+            ]
+        );
+    }
+
+    #[test]
+    fn write_str_stops_at_the_last_digit() {
+        let mut display = new_display();
+        display.write_str("12345678");
+        assert_eq!(
+            display.display_buffer[usize::from(DIGITS)..],
+            [0; DISPLAY_BUFFER_SIZE - DIGITS as usize]
+        );
+        display.write_str("AB");
+        assert_eq!(display.display_buffer[1], glyph('B').unwrap());
+        assert_eq!(display.display_buffer[2], glyph('3').unwrap());
+    }
+
+    /// What `display` would show, with points as `.`
+    fn shown(display: &mut HT16K33) -> String {
+        let mut i2c = MockI2c::default();
+        display.force_refresh(&mut i2c).unwrap();
+        i2c.displayed_text().unwrap()
+    }
+
+    #[test]
+    fn folds_points_into_the_character_before() {
+        let mut display = new_display();
+        display.write_str("12.5");
+        assert_eq!(shown(&mut display), "12.5 ");
+        // A point on every digit still fits
+        display.write_str("1.2.3.4.");
+        assert_eq!(shown(&mut display), "1.2.3.4.");
+
+        // Points with nothing to fold into get a digit of their own
+        let text = DisplayText::from(".5..");
+        assert_eq!(text.cells(), [(' ', true), ('5', true), (' ', true)]);
+        assert!(!text.is_truncated());
+        let result: Result = display.try_write_str("..5.V");
+        assert_eq!(result, Ok(()));
+        assert_eq!(shown(&mut display), " . .5.V");
+
+        // Nothing after what doesn't fit is kept, points included
+        let text = DisplayText::from("1234.5.");
+        assert!(text.is_truncated());
+        assert_eq!(text.cells()[3], ('4', true));
+        let text = DisplayText::from("12345.");
+        assert_eq!(text.cells()[3], ('4', false));
+        let result: Result = display.try_write_str("1.2.3.4.5");
+        assert_eq!(result, Err(Error::InvalidDigit(DIGITS)));
+    }
+
+    #[test]
+    fn aligns_and_pads() {
+        let mut display = new_display();
+        display.write_str("8888");
+        display.write_str_aligned("HI", Align::Left);
+        assert_eq!(shown(&mut display), "HI  ");
+        display.write_str_aligned("HI", Align::Right);
+        assert_eq!(shown(&mut display), "  HI");
+        display.write_str_aligned("HI", Align::Center);
+        assert_eq!(shown(&mut display), " HI ");
+        display.write_str_aligned("1.5", Align::Center);
+        assert_eq!(shown(&mut display), " 1.5 ");
+        display.write_str_aligned("OFF", Align::Center);
+        assert_eq!(shown(&mut display), "OFF ");
+        display.write_str_aligned("", Align::Right);
+        assert_eq!(shown(&mut display), "    ");
+        // Too long shows the start, however it's aligned
+        display.write_str_aligned("PREHEAT", Align::Right);
+        assert_eq!(shown(&mut display), "PREH");
+    }
+
+    #[test]
+    fn writes_formatted_text() {
+        let mut display = new_display();
+        display
+            .write_fmt_aligned(Align::Right, format_args!("{:.1}", 12.46))
+            .unwrap();
+        assert_eq!(shown(&mut display), " 12.5");
+        display
+            .write_fmt_aligned(Align::Left, format_args!("{}{}", -7, 'C'))
+            .unwrap();
+        assert_eq!(shown(&mut display), "-7C ");
+        display
+            .write_fmt_aligned(Align::Right, format_args!("{:.2}", 1.2345))
+            .unwrap();
+        assert_eq!(shown(&mut display), " 1.23");
+
+        // Or build the text up first
+        let mut text = DisplayText::new();
+        write!(text, "{:02}.", 5).unwrap();
+        write!(text, "{:02}", 9).unwrap();
+        display.write_text(&text, Align::Left);
+        assert_eq!(shown(&mut display), "05.09");
+    }
+
+    #[test]
+    fn rejects_what_it_cannot_show() {
+        let mut display = new_display();
+        let before = display.display_buffer;
+        let result: Result = display.try_write_digit_ascii(DIGITS, 'A', false);
+        assert_eq!(result, Err(Error::InvalidDigit(DIGITS)));
+        let result: Result = display.try_write_digit_ascii(255, 'A', false);
+        assert_eq!(result, Err(Error::InvalidDigit(255)));
+        let result: Result = display.try_write_digit_ascii(0, '°', false);
+        assert_eq!(result, Err(Error::UnsupportedGlyph('°')));
+        let result: Result = display.try_write_digit_value(0, 10, false);
+        assert_eq!(result, Err(Error::InvalidValue(10)));
+        let result: Result = display.try_write_digit_value(0, 255, false);
+        assert_eq!(result, Err(Error::InvalidValue(255)));
+        let result: Result = display.try_write_digit_value(DIGITS, 3, false);
+        assert_eq!(result, Err(Error::InvalidDigit(DIGITS)));
+        let result: Result = display.try_write_str("12345");
+        assert_eq!(result, Err(Error::InvalidDigit(DIGITS)));
+        let result: Result = display.try_write_str("1°C");
+        assert_eq!(result, Err(Error::UnsupportedGlyph('°')));
+        // None of that touched the display
+        assert_eq!(display.display_buffer, before);
+    }
+
+    #[test]
+    fn shows_what_it_cannot_show_as_best_it_can() {
+        let mut display = new_display();
+        display.write_digit_ascii(DIGITS, 'A', false);
+        display.write_digit_ascii(255, 'A', false);
+        display.write_digit_value(200, 1, false);
+        assert_eq!(display.display_buffer, [0; DISPLAY_BUFFER_SIZE]);
+
+        let question = ALPHA_FONT_TABLE[usize::from(b'?')];
+        display.write_digit_ascii(0, 'é', true);
+        display.write_digit_value(1, 12, false);
+        assert_eq!(
+            display.display_buffer[..2],
+            [question | ALPHA_POINT_MASK, question]
+        );
+    }
+
+    #[test]
+    fn reports_bus_errors() {
+        let mut i2c = MockI2c::default();
+        let mut display = HT16K33::init(0x70, &mut i2c).unwrap();
+        i2c.set_fail(true);
+        assert_eq!(
+            display.write_display(&mut i2c),
+            Err(Error::Bus(MockI2cError))
+        );
+        assert_eq!(
+            display.set_brightness(Brightness::MIN, &mut i2c),
+            Err(Error::Bus(MockI2cError))
+        );
+        assert!(matches!(
+            HT16K33::init(0x70, &mut i2c),
+            Err(Error::Bus(MockI2cError))
+        ));
+    }
+
+    #[test]
+    fn init_sets_everything_up() {
+        let mut i2c = MockI2c::default();
+        let display = HT16K33::init(0x71, &mut i2c).unwrap();
+        assert_eq!(
+            i2c.writes(),
+            [(0x71, vec![0x21]), (0x71, vec![0x81]), (0x71, vec![0xEF])]
+        );
+        assert_eq!(display.state(), DisplayState::On);
+        assert_eq!(display.blink_rate(), BlinkRate::Off);
+        assert_eq!(display.brightness(), Brightness::MAX);
+    }
+
+    #[test]
+    fn sends_blink_rates() {
+        let mut i2c = MockI2c::default();
+        let mut display = HT16K33::init(0x70, &mut i2c).unwrap();
+        i2c.clear();
+        for rate in [
+            BlinkRate::TwoHz,
+            BlinkRate::OneHz,
+            BlinkRate::HalfHz,
+            BlinkRate::Off,
+        ] {
+            display.set_blink_rate(rate, &mut i2c).unwrap();
+            assert_eq!(display.blink_rate(), rate);
+        }
+        assert_eq!(
+            i2c.writes(),
+            [
+                (0x70, vec![0x83]),
+                (0x70, vec![0x85]),
+                (0x70, vec![0x87]),
+                (0x70, vec![0x81])
+            ]
+        );
+    }
+
+    #[test]
+    fn sends_brightness() {
+        let mut i2c = MockI2c::default();
+        let mut display = HT16K33::init(0x70, &mut i2c).unwrap();
+        i2c.clear();
+        display.set_brightness(Brightness::MIN, &mut i2c).unwrap();
+        display
+            .set_brightness(Brightness::new(7).unwrap(), &mut i2c)
+            .unwrap();
+        assert_eq!(display.brightness().level(), 7);
+        assert_eq!(i2c.writes(), [(0x70, vec![0xE0]), (0x70, vec![0xE7])]);
+
+        assert_eq!(Brightness::new(15), Some(Brightness::MAX));
+        assert_eq!(Brightness::new(16), None);
+        assert_eq!(Brightness::clamped(200), Brightness::MAX);
+        assert_eq!(Brightness::clamped(3).level(), 3);
+    }
+
+    #[test]
+    fn sends_display_state() {
+        let mut i2c = MockI2c::default();
+        let mut display = HT16K33::init(0x70, &mut i2c).unwrap();
+        i2c.clear();
+        display.set_state(DisplayState::Standby, &mut i2c).unwrap();
+        assert_eq!(display.state(), DisplayState::Standby);
+        display.set_state(DisplayState::On, &mut i2c).unwrap();
+        assert_eq!(i2c.writes(), [(0x70, vec![0x20]), (0x70, vec![0x21])]);
+    }
+
+    #[test]
+    fn skips_commands_that_change_nothing() {
+        let mut i2c = MockI2c::default();
+        let mut display = HT16K33::init(0x70, &mut i2c).unwrap();
+        i2c.clear();
+        display.set_state(DisplayState::On, &mut i2c).unwrap();
+        display.set_blink_rate(BlinkRate::Off, &mut i2c).unwrap();
+        display.set_brightness(Brightness::MAX, &mut i2c).unwrap();
+        assert!(i2c.writes().is_empty());
+
+        display.set_state(DisplayState::Standby, &mut i2c).unwrap();
+        display.set_state(DisplayState::Standby, &mut i2c).unwrap();
+        assert_eq!(i2c.writes(), [(0x70, vec![0x20])]);
+    }
+
+    #[test]
+    fn retries_commands_that_failed() {
+        let mut i2c = MockI2c::default();
+        let mut display = HT16K33::init(0x70, &mut i2c).unwrap();
+        i2c.set_fail(true);
+        assert!(display.set_blink_rate(BlinkRate::OneHz, &mut i2c).is_err());
+        // The display never heard about it
+        assert_eq!(display.blink_rate(), BlinkRate::Off);
+
+        i2c.set_fail(false);
+        i2c.clear();
+        display.set_blink_rate(BlinkRate::OneHz, &mut i2c).unwrap();
+        assert_eq!(i2c.writes(), [(0x70, vec![0x85])]);
+    }
+
+    /// The two bytes of display RAM holding `character`
+    fn ram(character: char) -> [u8; 2] {
+        glyph(character).unwrap().to_le_bytes()
+    }
+
+    #[test]
+    fn first_write_sends_the_whole_frame() {
+        let mut i2c = MockI2c::default();
+        let mut display = HT16K33::init(0x70, &mut i2c).unwrap();
+        i2c.clear();
+        display.write_display(&mut i2c).unwrap();
+        assert_eq!(i2c.writes(), [(0x70, vec![0; 17])]);
+    }
+
+    #[test]
+    fn unchanged_frames_send_nothing() {
+        let mut i2c = MockI2c::default();
+        let mut display = HT16K33::init(0x70, &mut i2c).unwrap();
+        display.write_str("1234");
+        display.write_display(&mut i2c).unwrap();
+        i2c.clear();
+
+        display.write_display(&mut i2c).unwrap();
+        // Rewriting the same thing isn't a change
+        display.clear();
+        display.write_str("1234");
+        display.write_display(&mut i2c).unwrap();
+        assert!(i2c.writes().is_empty());
+        assert_eq!(i2c.displayed_text().unwrap(), "1234");
+    }
+
+    #[test]
+    fn sends_only_what_changed() {
+        let mut i2c = MockI2c::default();
+        let mut display = HT16K33::init(0x70, &mut i2c).unwrap();
+        display.write_str("1234");
+        display.write_display(&mut i2c).unwrap();
+        i2c.clear();
+
+        // Just the decimal point on the third digit
+        display.write_digit_ascii(2, '3', true);
+        display.write_display(&mut i2c).unwrap();
+        let point = (glyph('3').unwrap() | ALPHA_POINT_MASK).to_le_bytes();
+        assert_eq!(i2c.writes(), [(0x70, vec![0x04, point[0], point[1]])]);
+        assert_eq!(i2c.displayed_text().unwrap(), "123.4");
+
+        // From the first change to the last, in one write
+        i2c.clear();
+        display.write_str("A2C4");
+        display.write_display(&mut i2c).unwrap();
+        let (a, two, c) = (ram('A'), ram('2'), ram('C'));
+        assert_eq!(
+            i2c.writes(),
+            [(0x70, vec![0x00, a[0], a[1], two[0], two[1], c[0], c[1]])]
+        );
+        assert_eq!(i2c.displayed_text().unwrap(), "A2C4");
+    }
+
+    #[test]
+    fn resends_frames_that_failed() {
+        let mut i2c = MockI2c::default();
+        let mut display = HT16K33::init(0x70, &mut i2c).unwrap();
+        display.write_display(&mut i2c).unwrap();
+        display.write_digit_ascii(3, '9', false);
+        i2c.set_fail(true);
+        assert!(display.write_display(&mut i2c).is_err());
+
+        i2c.set_fail(false);
+        display.write_display(&mut i2c).unwrap();
+        let nine = ram('9');
+        assert_eq!(i2c.writes().last().unwrap().1, [0x06, nine[0], nine[1]]);
+    }
+
+    #[test]
+    fn force_refresh_sends_everything() {
+        let mut i2c = MockI2c::default();
+        let mut display = HT16K33::init(0x70, &mut i2c).unwrap();
+        display.set_state(DisplayState::Standby, &mut i2c).unwrap();
+        display.set_blink_rate(BlinkRate::TwoHz, &mut i2c).unwrap();
+        display.write_str("HI");
+        display.write_display(&mut i2c).unwrap();
+        i2c.clear();
+
+        display.force_refresh(&mut i2c).unwrap();
+        let (h, i) = (ram('H'), ram('I'));
+        let mut frame = vec![0x00, h[0], h[1], i[0], i[1]];
+        frame.resize(17, 0);
+        assert_eq!(
+            i2c.writes(),
+            [
+                (0x70, vec![0x20]),
+                (0x70, vec![0x83]),
+                (0x70, vec![0xEF]),
+                (0x70, frame)
+            ]
+        );
+    }
+
+    #[test]
+    fn fuzz_never_panics() {
+        let mut rng = XorShift(0x1234_5678);
+        let mut display = new_display();
+        let mut i2c = MockI2c::default();
+        for _ in 0..10_000 {
+            let n = rng.byte();
+            let c = rng.char();
+            let number = rng.byte();
+            let point = rng.next().is_multiple_of(2);
+            let msg = rng.string();
+
+            // The fallible writes succeed exactly when the input fits, and agree with the lossy
+            // ones when they do
+            let mut lossy = new_display();
+            lossy.write_digit_ascii(n, c, point);
+            let result: Result = display.try_write_digit_ascii(n, c, point);
+            match result {
+                Ok(()) => {
+                    assert!(n < DIGITS && c.is_ascii());
+                    assert_eq!(
+                        display.display_buffer[usize::from(n)],
+                        lossy.display_buffer[usize::from(n)]
+                    );
+                }
+                Err(Error::InvalidDigit(digit)) => assert!(digit == n && n >= DIGITS),
+                Err(Error::UnsupportedGlyph(glyph)) => assert!(glyph == c && !c.is_ascii()),
+                Err(Error::InvalidValue(value)) => panic!("writing {:?} gave value {}", c, value),
+                Err(Error::Bus(never)) => match never {},
+            }
+
+            display.write_digit_value(n, number, point);
+            let result: Result = display.try_write_digit_value(n, number, point);
+            assert_eq!(result.is_ok(), n < DIGITS && number <= 9);
+            if number > 9 {
+                assert_eq!(result, Err(Error::InvalidValue(number)));
+            }
+
+            display.write_str(&msg);
+            let result: Result = display.try_write_str(&msg);
+            let fits = !DisplayText::from(msg.as_str()).is_truncated();
+            assert_eq!(result.is_ok(), fits && msg.is_ascii());
+            if !msg.contains('.') {
+                assert_eq!(fits, msg.chars().count() <= usize::from(DIGITS));
+            }
+
+            display
+                .set_brightness(Brightness::clamped(number), &mut i2c)
+                .unwrap();
+            let rates = [
+                BlinkRate::Off,
+                BlinkRate::TwoHz,
+                BlinkRate::OneHz,
+                BlinkRate::HalfHz,
+            ];
+            display
+                .set_blink_rate(rates[usize::from(number % 4)], &mut i2c)
+                .unwrap();
+            display.write_display(&mut i2c).unwrap();
+            i2c.clear();
+        }
+    }
+
+    #[test]
+    fn fuzzed_strings_show_what_fits() {
+        let mut rng = XorShift(0x9E37_79B9);
+        for _ in 0..1_000 {
+            let msg = rng.string();
+            let mut display = new_display();
+            display.write_str(&msg);
+            // Each character and whether its point is lit, with dots folded in where they can be
+            let mut cells: Vec<(char, bool)> = Vec::new();
+            for c in msg.chars() {
+                match cells.last_mut() {
+                    Some((_, point)) if c == '.' && !*point => *point = true,
+                    _ if c == '.' => cells.push((' ', true)),
+                    _ => cells.push((c, false)),
+                }
+            }
+            // Padded with blanks, which have no segments lit
+            cells.resize(cells.len().max(usize::from(DIGITS)), (' ', false));
+            let shown: Vec<u16> = cells[..usize::from(DIGITS)]
+                .iter()
+                .map(|&(c, point)| {
+                    let segments = glyph(c).unwrap_or(ALPHA_FONT_TABLE[usize::from(b'?')]);
+                    if point {
+                        segments | ALPHA_POINT_MASK
+                    } else {
+                        segments
+                    }
+                })
+                .collect();
+            assert_eq!(display.display_buffer[..usize::from(DIGITS)], shown[..]);
+        }
+    }
 }
@@ -4,12 +4,33 @@
 #![warn(rust_2018_idioms)]
 #![warn(clippy::all)]
 
+pub mod ambient;
+pub mod app;
 pub mod battery;
+pub mod buttons;
+pub mod calibration;
+pub mod command;
+pub mod cycle;
+pub mod filter;
 pub mod ht16k33;
+pub mod marquee;
+pub mod menu;
+#[cfg(target_arch = "arm")]
+pub mod nvm;
 pub mod oventemp;
+pub mod setpoint;
+pub mod settings;
+pub mod slope;
+pub mod storage;
+pub mod temperature;
+pub mod thermocouple;
+pub mod timer;
 #[cfg(feature = "usbserial")]
 pub mod usbserial;
 
+#[cfg(test)]
+mod mock;
+
 #[cfg(test)]
 mod test {
     #[test]
@@ -5,49 +5,44 @@
 #![warn(rust_2018_idioms)]
 #![warn(clippy::all)]
 
-/// 12 bit ADC
-const ADC_FULLSCALE: u32 = 4095;
-/// Using VDDA / 2 with digital gain 1/2, our reference is ~3.3v
-const ADC_REF_VOLTAGE: f32 = 3.3;
-/// The threshold for showing a low battery indication.
-/// We can't descern much below this voltage due to drop out
-const LOW_BATTERY_VOLTAGE: f32 = 3.7;
-
-const DELAY_OFF_MS: u32 = 1_000;
-const DELAY_COOLDOWN_MS: u32 = 1_000;
-const DELAY_RUNNING_MS: u32 = 1_000;
-const SECS_BETWEEN_BLINK: u32 = 5;
-
 use panic_semihosting as _; // Panic handler
 
-#[cfg(feature = "usbserial")]
-use oven_temp_rs::serial_write;
-#[cfg(feature = "usbserial")]
-use oven_temp_rs::usbserial;
-use oven_temp_rs::{
-    battery, ht16k33,
-    oventemp::{OvenTemp, OvenTempState},
-};
+use oven_temp_rs::app::{App, AppConfig};
+use oven_temp_rs::buttons::{PressLatch, WakeOnPress};
+use oven_temp_rs::nvm::Nvm;
+use oven_temp_rs::settings;
+use oven_temp_rs::storage::RecordStore;
 
 use bsp::entry;
 use bsp::{hal, pac};
+use core::mem::MaybeUninit;
+use core::ptr::{addr_of, addr_of_mut};
 use core::sync::atomic;
 use cortex_m::peripheral::NVIC;
 use feather_m0 as bsp;
 use hal::adc::Adc;
 use hal::clock::{enable_internal_32kosc, ClockGenId, ClockSource, GenericClockController};
 use hal::prelude::*;
-use pac::{adc, interrupt, CorePeripherals, Peripherals, TC4};
-
-#[cfg(not(feature = "usbserial"))]
-macro_rules! serial_write {
-    ($($tt:tt)*) => {{}};
-}
+use pac::{adc, interrupt, CorePeripherals, Peripherals, EIC, TC4};
 
 /// boolean indicating if our timer interrupt has fired
 #[allow(unused)]
 static INTERRUPT_FIRED: atomic::AtomicBool = atomic::AtomicBool::new(false);
 
+/// Set when the button's pressed, so a tap that's over before the app polls the button still counts
+static BUTTON_PRESSED: PressLatch = PressLatch::new();
+
+/// Where older firmware kept the user's calibration, in RAM that cortex-m-rt doesn't initialize
+/// so it survived resets. Only read now, to migrate it into flash.
+#[link_section = ".uninit.CALIBRATION"]
+static mut PERSISTED_CALIBRATION: MaybeUninit<[u32; 4]> = MaybeUninit::uninit();
+
+extern "C" {
+    /// The flash `memory.x` keeps out of the program for settings
+    static _settings_start: u8;
+    static _settings_end: u8;
+}
+
 /// Main function, controlling all of our logic
 #[entry]
 fn main() -> ! {
@@ -87,7 +82,7 @@ fn main() -> ! {
         );
     }
 
-    let mut i2c = bsp::i2c_master(
+    let i2c = bsp::i2c_master(
         &mut clocks,
         400.khz(),
         peripherals.SERCOM3,
@@ -96,11 +91,13 @@ fn main() -> ! {
         pins.scl,
     );
 
-    let mut red_led = pins.d13.into_push_pull_output();
-    red_led.set_high().unwrap();
+    let red_led = pins.d13.into_push_pull_output();
+
+    // The menu button, between D5 and ground. D5 is also EXTINT15, so a press can wake us up.
+    let button = pins.d5.into_pull_up_interrupt();
 
     #[cfg(feature = "sleeping-delay")]
-    let mut runner_delay = {
+    let runner_delay = {
         use hal::sleeping_delay::SleepingDelay;
         use hal::timer;
 
@@ -123,277 +120,104 @@ fn main() -> ! {
             NVIC::unmask(interrupt::TC4);
         }
 
+        // Wake up when the button's pressed, which only ends the part of the sleep it comes in (see
+        // `WakeOnPress`). The EIC runs off the same standby clock.
+        let eic_clock = clocks.eic(&timer_clock).unwrap();
+        let _eic = hal::eic::EIC::init(&mut peripherals.PM, eic_clock, peripherals.EIC);
+        unsafe {
+            let eic = &*EIC::ptr();
+            eic.config[1].modify(|_, w| w.sense7().fall());
+            eic.wakeup.modify(|_, w| w.wakeupen15().set_bit());
+            eic.intenset.write(|w| w.extint15().set_bit());
+            NVIC::unmask(interrupt::EIC);
+        }
+
         SleepingDelay::new(timer, &INTERRUPT_FIRED)
     };
 
     #[cfg(not(feature = "sleeping-delay"))]
-    let mut runner_delay = {
+    let runner_delay = {
         use hal::delay::Delay;
 
         Delay::new(core.SYST, &mut clocks)
     };
 
-    // wait here until the display is plugged in and communicating
-    let mut display = loop {
-        match ht16k33::HT16K33::init(0x70, &mut i2c) {
-            Ok(disp) => break disp,
-            _ => runner_delay.delay_ms(1_000_u32),
-        };
-    };
-
-    display.clear();
-    display.set_brightness(1, &mut i2c).unwrap();
-    display.write_str(" HI ");
-    display.write_display(&mut i2c).unwrap();
-    runner_delay.delay_ms(500_u32);
-    display.clear();
-    display.write_display(&mut i2c).unwrap();
-
     let mut adc = Adc::adc(peripherals.ADC, &mut peripherals.PM, &mut clocks);
     adc.gain(adc::inputctrl::GAIN_A::DIV2);
     adc.reference(adc::refctrl::REFSEL_A::INTVCC1);
     adc.samples(adc::avgctrl::SAMPLENUM_A::_32);
-    let mut therm_out = pins.a4.into_alternate::<hal::gpio::B>();
+    let therm_out = pins.a4.into_alternate::<hal::gpio::B>();
 
     // check the battery voltage (external HW divides the reading by two)
-    let mut batt_in_div_2 = pins.d9.into_alternate::<hal::gpio::B>();
-
-    red_led.set_low().unwrap();
-
-    let mut oven_state = OvenTemp::new();
-    let mut iteration = 0_u32;
+    let batt_in_div_2 = pins.d9.into_alternate::<hal::gpio::B>();
+
+    // A photoresistor, if one's fitted, for following the room's light with the display
+    #[cfg(feature = "light-sensor")]
+    let light_in = Some(pins.a1.into_alternate::<hal::gpio::B>());
+    #[cfg(not(feature = "light-sensor"))]
+    let light_in: Option<hal::gpio::Pin<hal::gpio::PB08, hal::gpio::AlternateB>> = None;
+
+    let (start, end) = (addr_of!(_settings_start), addr_of!(_settings_end));
+    let nvm = Nvm::new(
+        peripherals.NVMCTRL,
+        start as usize,
+        end as usize - start as usize,
+    );
+    // Reading the internal flash can't fail
+    let mut store = RecordStore::mount(nvm).unwrap();
+    let mut saved_config = load_config(&mut store);
+    let mut app = App::init(
+        i2c,
+        adc,
+        therm_out,
+        batt_in_div_2,
+        light_in,
+        red_led,
+        button,
+        WakeOnPress::new(runner_delay, &BUTTON_PRESSED),
+        saved_config,
+    );
+    app.set_button_latch(&BUTTON_PRESSED);
 
     loop {
-        // Check to make sure our battery is in good shape
-        let mut battery_reading: f32 = adc.read(&mut batt_in_div_2).unwrap();
-        battery_reading =
-            2.0 * ((battery_reading as f32 / ADC_FULLSCALE as f32) * ADC_REF_VOLTAGE as f32);
-
-        if battery_reading <= LOW_BATTERY_VOLTAGE {
-            // inform user of low battery. Thermocouple readings are not accurate
-            display.write_str("LOW");
-            display.write_display(&mut i2c).unwrap();
-            runner_delay.delay_ms(500_u32);
-            display.write_str("BATT");
-            display.write_display(&mut i2c).unwrap();
-            runner_delay.delay_ms(500_u32);
-            display.clear();
-            display.write_display(&mut i2c).unwrap();
-
-            // Delay for a long while with display in standby to save some power
-            display.configure_standby(&mut i2c, true).unwrap();
-            runner_delay.delay_ms(5_000_u32);
-            display.configure_standby(&mut i2c, false).unwrap();
-            runner_delay.delay_ms(100_u32);
-            continue; // Do not run the typical thermocouple routine
-        }
-
-        // Check the thermocouple
-        let therm_reading: u16 = adc.read(&mut therm_out).unwrap();
-        let therm_voltage: f32 =
-            (therm_reading as f32 / ADC_FULLSCALE as f32) * ADC_REF_VOLTAGE as f32;
-        let temp_c: f32 = (therm_voltage - 1.25) / 0.005;
-        let temp: f32 = temp_c * (9. / 5.) + 32.;
-        iteration += 1;
-
-        serial_write!("reading: {}.{}\r\n", temp as u32, (temp * 10.) as u32 % 10);
-
-        if run(
-            oven_state.state,
-            temp,
-            &mut i2c,
-            &mut display,
-            &mut runner_delay,
-        )
-        .is_err()
-        {
-            error(&mut red_led, &mut runner_delay);
-        }
-
-        if let Some(new_state) = oven_state.check_transition(temp) {
-            match new_state {
-                OvenTempState::Off | OvenTempState::CoolingDown => {
-                    // clear and turn off the display
-                    display.clear();
-                    if display.write_display(&mut i2c).is_err() {
-                        error(&mut red_led, &mut runner_delay);
-                    }
-                    if display.configure_standby(&mut i2c, true).is_err() {
-                        error(&mut red_led, &mut runner_delay);
-                    }
-                }
-                _ => {
-                    // take the display out of standby mode
-                    if display.configure_standby(&mut i2c, false).is_err() {
-                        error(&mut red_led, &mut runner_delay);
-                    }
-                }
-            }
-        }
-
-        // blink a dot to show we're alive, and show battery percentage
-        let battery_percentage = battery::voltage_to_percentage(battery_reading);
-        let mut blink_index: u8 = 0;
-        if battery_percentage >= 75 {
-            blink_index = 3;
-        } else if battery_percentage >= 50 {
-            blink_index = 2;
-        } else if battery_percentage >= 25 {
-            blink_index = 1;
-        }
-
-        // blink the dot
-        if (oven_state.state == OvenTempState::Off
-            || oven_state.state == OvenTempState::CoolingDown)
-            && (iteration % SECS_BETWEEN_BLINK == SECS_BETWEEN_BLINK - 1)
-        {
-            // Turn display on
-            if display.configure_standby(&mut i2c, false).is_err() {
-                error(&mut red_led, &mut runner_delay);
-            }
-
-            // Blink dot
-            display.clear();
-            display.write_digit_ascii(blink_index, ' ', true);
-            if display.write_display(&mut i2c).is_err() {
-                error(&mut red_led, &mut runner_delay);
-            }
-            runner_delay.delay_ms(50_u32);
-            display.clear();
-            if display.write_display(&mut i2c).is_err() {
-                error(&mut red_led, &mut runner_delay);
-            }
-
-            // turn display back off
-            if display.configure_standby(&mut i2c, false).is_err() {
-                error(&mut red_led, &mut runner_delay);
-            }
+        app.step();
+
+        // Settings can be changed over USB serial or in the menu
+        let config = *app.config();
+        if config != saved_config {
+            // If it fails there's nothing better to do than try again on the next change
+            settings::save(&mut store, &config).ok();
+            saved_config = config;
         }
     }
 }
 
-/// Display the given temperature on the display
-fn display_temp<I2C, CommE>(
-    temp_f: f32,
-    i2c: &mut I2C,
-    display: &mut ht16k33::HT16K33,
-) -> Result<(), CommE>
-where
-    I2C: embedded_hal::blocking::i2c::Write<Error = CommE>,
-{
-    display.clear();
-    if (10. ..100.).contains(&temp_f) {
-        let tens_place: u8 = (temp_f / 10.) as u8;
-        let ones_place: u8 = (temp_f % 10.) as u8;
-        let tenths_place: u8 = ((temp_f * 10.) % 10.) as u8;
-        let hundredths_place: u8 = ((temp_f * 100.) % 10.) as u8;
-        display.write_digit_value(0, tens_place, false);
-        display.write_digit_value(1, ones_place, true);
-        display.write_digit_value(2, tenths_place, false);
-        display.write_digit_value(3, hundredths_place, false);
-    } else if (100. ..600.).contains(&temp_f) {
-        let hundreds_place: u8 = (temp_f / 100.) as u8;
-        let tens_place: u8 = ((temp_f / 10.) % 10.) as u8;
-        let ones_place: u8 = (temp_f % 10.) as u8;
-        let tenths_place: u8 = ((temp_f * 10.) % 10.) as u8;
-        display.write_digit_value(0, hundreds_place, false);
-        display.write_digit_value(1, tens_place, false);
-        display.write_digit_value(2, ones_place, true);
-        display.write_digit_value(3, tenths_place, false);
-    } else {
-        // Temp < 10 or >= 600. >= 600 is generally disconnected thermocouple
-        display.write_str("ERR!");
+/// Loads the settings saved before the last reset, or moves the calibration older firmware kept in
+/// RAM into flash
+fn load_config(store: &mut RecordStore<Nvm>) -> AppConfig {
+    let defaults = AppConfig::default();
+    if let Ok(Some(config)) = settings::load(store, defaults) {
+        return config;
     }
 
-    display.write_display(i2c)
-}
-
-/// Blinks an SOS pattern indicating an error
-///
-/// # Parameters
-/// * `red_led`: The LED pin to blink
-/// * `delay`: The `Delay` instance to wait
-fn error<PIN, T>(red_led: &mut PIN, delay: &mut T)
-where
-    PIN: embedded_hal::digital::v2::OutputPin<Error = core::convert::Infallible>,
-    T: embedded_hal::blocking::delay::DelayMs<u32>,
-{
-    const SHORT_BLIP_MS: u32 = 250;
-    const LONG_BLIP_MS: u32 = 500;
-
-    // S
-    red_led.set_high().unwrap();
-    delay.delay_ms(SHORT_BLIP_MS);
-    red_led.set_low().unwrap();
-    delay.delay_ms(SHORT_BLIP_MS);
-    red_led.set_high().unwrap();
-    delay.delay_ms(SHORT_BLIP_MS);
-    red_led.set_low().unwrap();
-    delay.delay_ms(SHORT_BLIP_MS);
-    red_led.set_high().unwrap();
-    delay.delay_ms(SHORT_BLIP_MS);
-    red_led.set_low().unwrap();
-    delay.delay_ms(SHORT_BLIP_MS);
-
-    // O
-    red_led.set_high().unwrap();
-    delay.delay_ms(LONG_BLIP_MS);
-    red_led.set_low().unwrap();
-    delay.delay_ms(LONG_BLIP_MS);
-    red_led.set_high().unwrap();
-    delay.delay_ms(LONG_BLIP_MS);
-    red_led.set_low().unwrap();
-    delay.delay_ms(LONG_BLIP_MS);
-    red_led.set_high().unwrap();
-    delay.delay_ms(LONG_BLIP_MS);
-    red_led.set_low().unwrap();
-    delay.delay_ms(LONG_BLIP_MS);
-
-    // S
-    red_led.set_high().unwrap();
-    delay.delay_ms(SHORT_BLIP_MS);
-    red_led.set_low().unwrap();
-    delay.delay_ms(SHORT_BLIP_MS);
-    red_led.set_high().unwrap();
-    delay.delay_ms(SHORT_BLIP_MS);
-    red_led.set_low().unwrap();
-    delay.delay_ms(SHORT_BLIP_MS);
-    red_led.set_high().unwrap();
-    delay.delay_ms(SHORT_BLIP_MS);
-    red_led.set_low().unwrap();
-
-    delay.delay_ms(2 * LONG_BLIP_MS);
-}
-
-/// Run the main state display/sleep logic
-pub fn run<I2C, CommError, T>(
-    state: OvenTempState,
-    temp: f32,
-    i2c: &mut I2C,
-    display: &mut ht16k33::HT16K33,
-    delay: &mut T,
-) -> Result<(), CommError>
-where
-    I2C: embedded_hal::blocking::i2c::Write<Error = CommError>,
-    T: embedded_hal::blocking::delay::DelayMs<u32>,
-{
-    match state {
-        OvenTempState::Off => {
-            serial_write!("Off\r\n");
-            delay.delay_ms(DELAY_OFF_MS);
-            Ok(())
-        }
-        OvenTempState::CoolingDown => {
-            serial_write!("CoolingDown\r\n");
-            delay.delay_ms(DELAY_COOLDOWN_MS);
-            Ok(())
-        }
-        _ => {
-            serial_write!("WarmingUp or AtTemp\r\n");
-            let ret = display_temp(temp, i2c, display);
-            delay.delay_ms(DELAY_RUNNING_MS);
-            ret
+    // After power on this is garbage, which the checksum catches
+    let words = unsafe {
+        addr_of_mut!(PERSISTED_CALIBRATION)
+            .cast::<[u32; 4]>()
+            .read_volatile()
+    };
+    let mut bytes = [0; 16];
+    for (chunk, word) in bytes.chunks_exact_mut(4).zip(words) {
+        chunk.copy_from_slice(&word.to_le_bytes());
+    }
+    match settings::decode(settings::CALIBRATION_WORDS_VERSION, &bytes, defaults) {
+        Some(config) => {
+            // The main loop only saves changes, and this is already the config it starts from. If
+            // it fails we'll try again after the next reset, while the RAM still holds it.
+            settings::save(store, &config).ok();
+            config
         }
+        None => defaults,
     }
 }
 
@@ -411,3 +235,20 @@ fn TC4() {
             .modify(|_, w| w.ovf().set_bit());
     }
 }
+
+/// The button interrupt, on the falling edge of a press
+///
+/// It wakes the sleeping delay and latches the press. `WakeOnPress` then gives up the rest of the
+/// sleep, and the app's next poll of the button counts the press even if it's already over.
+#[interrupt]
+fn EIC() {
+    BUTTON_PRESSED.set();
+    INTERRUPT_FIRED.store(true, atomic::Ordering::Relaxed);
+    unsafe {
+        EIC::ptr()
+            .as_ref()
+            .unwrap()
+            .intflag
+            .write(|w| w.extint15().set_bit());
+    }
+}
@@ -0,0 +1,228 @@
+//! Messages too long for the display, scrolled across it.
+//!
+//! The marquee doesn't wait on anything itself: the main loop calls `tick` as often as it likes,
+//! and redraws when it says the text has moved.
+
+use crate::ht16k33::{Text, DIGITS, HT16K33};
+
+/// Most digits a message can take up, after folding in decimal points. Anything past that is
+/// dropped.
+pub const MAX_CELLS: usize = 32;
+
+/// What one digit shows: a character, and whether to light the point after it
+type Cell = (char, bool);
+
+const BLANK: Cell = (' ', false);
+
+/// A message scrolling right to left across the display, over and over with a gap between
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Marquee {
+    text: Text<MAX_CELLS>,
+    step_ms: u32,
+    /// Which cell is in the first digit, counting the gap after the message
+    offset: usize,
+    /// When the text last moved, or the first tick if it hasn't
+    last_step_ms: Option<u64>,
+    passes: u32,
+}
+
+impl Marquee {
+    /// Creates a marquee showing the start of `text`
+    ///
+    /// # Arguments
+    /// * `text`: The message. A `.` lights the point of the character before it rather than
+    ///   taking up a digit of its own.
+    /// * `step_ms`: How long each position is shown before the text moves along one digit
+    #[must_use]
+    pub fn new(text: &str, step_ms: u32) -> Self {
+        Self {
+            text: Text::from(text),
+            step_ms: step_ms.max(1),
+            offset: 0,
+            last_step_ms: None,
+            passes: 0,
+        }
+    }
+
+    /// Whether the message is too long to show all at once, and so scrolls
+    #[must_use]
+    pub fn is_scrolling(&self) -> bool {
+        self.len() > usize::from(DIGITS)
+    }
+
+    /// How many digits the whole message takes up
+    fn len(&self) -> usize {
+        self.text.cells().len()
+    }
+
+    /// How many times the whole message has scrolled off the display, which it's just done when
+    /// the display goes blank. A message that fits counts one every step.
+    #[must_use]
+    pub fn passes(&self) -> u32 {
+        self.passes
+    }
+
+    /// Moves the text along if it's been shown long enough. Falling behind moves it as many steps
+    /// as were missed.
+    ///
+    /// # Returns
+    /// whether what's shown has changed, and the display needs redrawing
+    pub fn tick(&mut self, now_ms: u64) -> bool {
+        let last_step_ms = *self.last_step_ms.get_or_insert(now_ms);
+        let steps = now_ms.saturating_sub(last_step_ms) / u64::from(self.step_ms);
+        if steps == 0 {
+            return false;
+        }
+        self.last_step_ms = Some(last_step_ms + steps * u64::from(self.step_ms));
+
+        if !self.is_scrolling() {
+            self.passes = self.passes.saturating_add(steps as u32);
+            return false;
+        }
+        let period = (self.len() + usize::from(DIGITS)) as u64;
+        let offset = self.offset as u64 + steps;
+        // Count the times we reached the gap, which starts `DIGITS` before the end of the period
+        let gap = u64::from(DIGITS);
+        let passes = (offset + gap) / period - (self.offset as u64 + gap) / period;
+        self.passes = self.passes.saturating_add(passes as u32);
+        self.offset = (offset % period) as usize;
+        true
+    }
+
+    /// Draws what's showing into the display's buffer
+    pub fn render(&self, display: &mut HT16K33) {
+        display.clear();
+        for (n, (character, point)) in self.frame().iter().enumerate() {
+            display.write_digit_ascii(n as u8, *character, *point);
+        }
+    }
+
+    /// What each digit shows right now
+    fn frame(&self) -> [Cell; DIGITS as usize] {
+        let mut frame = [BLANK; DIGITS as usize];
+        let cells = self.text.cells();
+        if !self.is_scrolling() {
+            frame[..cells.len()].copy_from_slice(cells);
+            return frame;
+        }
+        let period = cells.len() + usize::from(DIGITS);
+        for (n, cell) in frame.iter_mut().enumerate() {
+            if let Some(&shown) = cells.get((self.offset + n) % period) {
+                *cell = shown;
+            }
+        }
+        frame
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+
+    use super::*;
+    use crate::mock::MockI2c;
+    use std::{string::String, vec::Vec};
+
+    /// What the marquee puts on the display
+    fn shown(marquee: &Marquee) -> String {
+        let mut i2c = MockI2c::default();
+        let mut display = HT16K33::init(0x70, &mut i2c).unwrap();
+        marquee.render(&mut display);
+        display.write_display(&mut i2c).unwrap();
+        i2c.displayed_text().unwrap()
+    }
+
+    /// Everything shown, a step at a time, for `steps` steps
+    fn run(marquee: &mut Marquee, steps: u64) -> Vec<String> {
+        let mut frames = Vec::from([shown(marquee)]);
+        marquee.tick(0);
+        for step in 1..=steps {
+            assert!(marquee.tick(step * 100));
+            frames.push(shown(marquee));
+        }
+        frames
+    }
+
+    #[test]
+    fn scrolls_long_messages() {
+        let mut marquee = Marquee::new("LOW BATT", 100);
+        assert!(marquee.is_scrolling());
+        assert_eq!(
+            run(&mut marquee, 13),
+            [
+                "LOW ", "OW B", "W BA", " BAT", "BATT", "ATT ", "TT  ", "T   ", "    ", "   L",
+                "  LO", " LOW", "LOW ", "OW B"
+            ]
+        );
+        assert_eq!(marquee.passes(), 1);
+
+        // A pass is done once the message has gone
+        let mut marquee = Marquee::new("LOW BATT", 100);
+        run(&mut marquee, 7);
+        assert_eq!(marquee.passes(), 0);
+        marquee.tick(800);
+        assert_eq!((shown(&marquee).as_str(), marquee.passes()), ("    ", 1));
+    }
+
+    #[test]
+    fn shows_short_messages_as_they_are() {
+        let mut marquee = Marquee::new("HI", 100);
+        assert!(!marquee.is_scrolling());
+        assert_eq!(shown(&marquee), "HI  ");
+        marquee.tick(0);
+        assert!(!marquee.tick(250));
+        assert_eq!(shown(&marquee), "HI  ");
+        assert_eq!(marquee.passes(), 2);
+    }
+
+    #[test]
+    fn folds_points_into_the_digit_before() {
+        let marquee = Marquee::new("3.14", 100);
+        assert!(!marquee.is_scrolling());
+        assert_eq!(shown(&marquee), "3.14 ");
+
+        // Points with nothing to fold into get a digit of their own
+        assert_eq!(shown(&Marquee::new(".5..V.", 100)), " .5. .V.");
+        let mut marquee = Marquee::new("1.2.3.4.5.", 100);
+        assert_eq!(run(&mut marquee, 2), ["1.2.3.4.", "2.3.4.5.", "3.4.5. "]);
+    }
+
+    #[test]
+    fn waits_for_each_step() {
+        let mut marquee = Marquee::new("PREHEAT DONE", 250);
+        assert!(!marquee.tick(1_000));
+        assert!(!marquee.tick(1_249));
+        assert_eq!(shown(&marquee), "PREH");
+        assert!(marquee.tick(1_250));
+        assert_eq!(shown(&marquee), "REHE");
+        assert!(!marquee.tick(1_499));
+
+        // Catches up after a long sleep, keeping to the same beat
+        assert!(marquee.tick(2_260));
+        assert_eq!(shown(&marquee), "AT D");
+        assert!(!marquee.tick(2_499));
+        assert!(marquee.tick(2_500));
+        assert_eq!(shown(&marquee), "T DO");
+
+        // A whole pass is 16 steps, message and gap
+        assert!(marquee.tick(2_500 + 10 * 250));
+        assert_eq!(marquee.passes(), 1);
+        assert_eq!(shown(&marquee), "PREH");
+    }
+
+    #[test]
+    fn drops_what_does_not_fit() {
+        let text: String = core::iter::repeat_n('A', MAX_CELLS + 5).collect();
+        let mut marquee = Marquee::new(&text, 100);
+        marquee.tick(0);
+        marquee.tick((MAX_CELLS as u64 - 1) * 100);
+        assert_eq!(shown(&marquee), "A   ");
+
+        // A point can still fold into the last digit
+        let text = String::from(&text[..MAX_CELLS]) + ".";
+        let mut marquee = Marquee::new(&text, 100);
+        marquee.tick(0);
+        marquee.tick((MAX_CELLS as u64 - 1) * 100);
+        assert_eq!(shown(&marquee), "A.   ");
+    }
+}
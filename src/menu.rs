@@ -0,0 +1,496 @@
+//! The on-device settings menu.
+//!
+//! With one button and four characters there isn't room for much, so the menu is a loop of four
+//! letter labels. A press moves on to the next label (or value, while editing), a long press picks
+//! the setting shown or saves the value being edited, and a double press backs out without saving.
+
+use crate::buttons::Gesture;
+use crate::ht16k33::{Align, Brightness, HT16K33};
+use crate::temperature::TemperatureUnit;
+
+/// The display's brightest setting
+pub const MAX_BRIGHTNESS: u8 = Brightness::MAX.level();
+/// Calibration offsets are edited in steps of this, in the display unit
+const OFFSET_STEP: f32 = 0.5;
+/// The largest calibration offset that fits on the display
+const MAX_MENU_OFFSET: f32 = 9.5;
+/// Timer lengths to pick from, minutes. 0 turns the timer off.
+const TIMER_MINUTES: [u32; 14] = [0, 1, 2, 3, 5, 10, 15, 20, 25, 30, 45, 60, 90, 120];
+
+/// What the user asked the menu to do
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum MenuEvent {
+    /// Move on to the next item or value
+    Next,
+    /// Pick the item shown, or save the value being edited
+    Select,
+    /// Leave the value being edited alone, or close the menu
+    Back,
+}
+
+impl From<Gesture> for MenuEvent {
+    fn from(gesture: Gesture) -> Self {
+        match gesture {
+            Gesture::Press => MenuEvent::Next,
+            Gesture::LongPress => MenuEvent::Select,
+            Gesture::DoublePress => MenuEvent::Back,
+        }
+    }
+}
+
+/// The settings in the menu, in the order they're shown
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum MenuItem {
+    /// The unit temperatures are shown in
+    Unit,
+    /// How bright the display is
+    Brightness,
+    /// The calibration offset
+    Calibration,
+    /// The countdown timer
+    Timer,
+}
+
+impl MenuItem {
+    /// The four character label shown for the item
+    #[must_use]
+    pub fn label(self) -> &'static str {
+        match self {
+            MenuItem::Unit => "UNIT",
+            MenuItem::Brightness => "BRIT",
+            MenuItem::Calibration => "CAL ",
+            MenuItem::Timer => "TIMR",
+        }
+    }
+
+    fn next(self) -> Self {
+        match self {
+            MenuItem::Unit => MenuItem::Brightness,
+            MenuItem::Brightness => MenuItem::Calibration,
+            MenuItem::Calibration => MenuItem::Timer,
+            MenuItem::Timer => MenuItem::Unit,
+        }
+    }
+}
+
+/// The current value of everything the menu can change
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct MenuSettings {
+    /// The unit temperatures are shown in
+    pub unit: TemperatureUnit,
+    /// Display brightness, from 0 to `MAX_BRIGHTNESS`
+    pub brightness: u8,
+    /// Whether the brightness follows the ambient light, or `None` if there's no light sensor
+    pub auto_brightness: Option<bool>,
+    /// Calibration offset, in `unit`
+    pub offset: f32,
+    /// Minutes left on the timer, or 0 if it isn't running
+    pub timer_minutes: u32,
+}
+
+/// A setting the user saved
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum MenuChange {
+    Unit(TemperatureUnit),
+    Brightness(u8),
+    /// Brightness following the ambient light
+    AutoBrightness,
+    /// Calibration offset, in the unit temperatures are shown in (which may have just changed)
+    Offset(f32),
+    /// Start the timer for this many minutes, or stop it if 0
+    Timer(u32),
+}
+
+impl MenuChange {
+    /// The value `item` starts at when the user begins editing it
+    fn current(item: MenuItem, settings: &MenuSettings) -> Self {
+        match item {
+            MenuItem::Unit => MenuChange::Unit(settings.unit),
+            MenuItem::Brightness if settings.auto_brightness == Some(true) => {
+                MenuChange::AutoBrightness
+            }
+            MenuItem::Brightness => MenuChange::Brightness(settings.brightness.min(MAX_BRIGHTNESS)),
+            MenuItem::Calibration => {
+                let offset = libm::roundf(settings.offset / OFFSET_STEP) * OFFSET_STEP;
+                MenuChange::Offset(offset.clamp(-MAX_MENU_OFFSET, MAX_MENU_OFFSET))
+            }
+            // The shortest choice that covers what's left
+            MenuItem::Timer => MenuChange::Timer(
+                TIMER_MINUTES
+                    .iter()
+                    .copied()
+                    .find(|&minutes| minutes >= settings.timer_minutes)
+                    .unwrap_or(TIMER_MINUTES[TIMER_MINUTES.len() - 1]),
+            ),
+        }
+    }
+
+    /// The item this is a value of
+    fn item(self) -> MenuItem {
+        match self {
+            MenuChange::Unit(_) => MenuItem::Unit,
+            MenuChange::Brightness(_) | MenuChange::AutoBrightness => MenuItem::Brightness,
+            MenuChange::Offset(_) => MenuItem::Calibration,
+            MenuChange::Timer(_) => MenuItem::Timer,
+        }
+    }
+
+    /// The next value to offer, wrapping around at the end
+    fn next(self, settings: &MenuSettings) -> Self {
+        match self {
+            MenuChange::Unit(TemperatureUnit::Fahrenheit) => {
+                MenuChange::Unit(TemperatureUnit::Celsius)
+            }
+            MenuChange::Unit(TemperatureUnit::Celsius) => {
+                MenuChange::Unit(TemperatureUnit::Fahrenheit)
+            }
+            // Automatic comes after the brightest, if there's a sensor to follow
+            MenuChange::Brightness(MAX_BRIGHTNESS) if settings.auto_brightness.is_some() => {
+                MenuChange::AutoBrightness
+            }
+            MenuChange::Brightness(level) => {
+                MenuChange::Brightness((level + 1) % (MAX_BRIGHTNESS + 1))
+            }
+            MenuChange::AutoBrightness => MenuChange::Brightness(0),
+            MenuChange::Offset(offset) if offset + OFFSET_STEP > MAX_MENU_OFFSET => {
+                MenuChange::Offset(-MAX_MENU_OFFSET)
+            }
+            MenuChange::Offset(offset) => MenuChange::Offset(offset + OFFSET_STEP),
+            MenuChange::Timer(minutes) => MenuChange::Timer(
+                TIMER_MINUTES
+                    .iter()
+                    .copied()
+                    .find(|&choice| choice > minutes)
+                    .unwrap_or(TIMER_MINUTES[0]),
+            ),
+        }
+    }
+
+    /// Draws the value into the display's buffer
+    fn render(self, display: &mut HT16K33) {
+        // None of these can fail to format
+        let _ = match self {
+            MenuChange::Unit(unit) => {
+                display.write_fmt_aligned(Align::Left, format_args!("DEG{}", unit.symbol()))
+            }
+            MenuChange::Brightness(level) => {
+                display.write_fmt_aligned(Align::Right, format_args!("{}", level))
+            }
+            MenuChange::AutoBrightness => {
+                display.write_str_aligned("AUTO", Align::Left);
+                Ok(())
+            }
+            MenuChange::Offset(offset) => {
+                let tenths = libm::roundf(libm::fabsf(offset) * 10.) as u32;
+                let sign = if tenths == 0 {
+                    ""
+                } else if offset < 0. {
+                    "-"
+                } else {
+                    "+"
+                };
+                display.write_fmt_aligned(
+                    Align::Right,
+                    format_args!("{}{}.{}", sign, tenths / 10, tenths % 10),
+                )
+            }
+            MenuChange::Timer(0) => {
+                display.write_str_aligned("OFF", Align::Right);
+                Ok(())
+            }
+            MenuChange::Timer(minutes) => {
+                display.write_fmt_aligned(Align::Right, format_args!("{}M", minutes))
+            }
+        };
+    }
+}
+
+/// Where the user is in the menu
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum MenuState {
+    Closed,
+    /// Looking through the items, with this one shown
+    Browsing(MenuItem),
+    /// Editing an item, with this value shown
+    Editing(MenuChange),
+}
+
+/// The settings menu
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Menu {
+    state: MenuState,
+    settings: MenuSettings,
+}
+
+impl Menu {
+    /// Creates a menu that isn't open
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            state: MenuState::Closed,
+            settings: MenuSettings {
+                unit: TemperatureUnit::Fahrenheit,
+                brightness: MAX_BRIGHTNESS,
+                auto_brightness: None,
+                offset: 0.,
+                timer_minutes: 0,
+            },
+        }
+    }
+
+    /// Whether the menu is open, and should be on the display
+    #[must_use]
+    pub fn is_open(&self) -> bool {
+        self.state != MenuState::Closed
+    }
+
+    /// Opens the menu at the first item
+    ///
+    /// # Arguments
+    /// * `settings`: The current settings, which editing starts from
+    pub fn open(&mut self, settings: MenuSettings) {
+        self.settings = settings;
+        self.state = MenuState::Browsing(MenuItem::Unit);
+    }
+
+    /// Closes the menu, dropping any value being edited
+    pub fn close(&mut self) {
+        self.state = MenuState::Closed;
+    }
+
+    /// Acts on what the user asked for
+    ///
+    /// # Returns
+    /// the setting the user saved, if they did
+    pub fn handle(&mut self, event: MenuEvent) -> Option<MenuChange> {
+        let (state, saved) = match (self.state, event) {
+            (MenuState::Closed, _) => (MenuState::Closed, None),
+            (MenuState::Browsing(item), MenuEvent::Next) => {
+                (MenuState::Browsing(item.next()), None)
+            }
+            (MenuState::Browsing(item), MenuEvent::Select) => (
+                MenuState::Editing(MenuChange::current(item, &self.settings)),
+                None,
+            ),
+            (MenuState::Browsing(_), MenuEvent::Back) => (MenuState::Closed, None),
+            (MenuState::Editing(value), MenuEvent::Next) => {
+                (MenuState::Editing(value.next(&self.settings)), None)
+            }
+            (MenuState::Editing(value), MenuEvent::Select) => {
+                self.save(value);
+                (MenuState::Browsing(value.item()), Some(value))
+            }
+            (MenuState::Editing(value), MenuEvent::Back) => {
+                (MenuState::Browsing(value.item()), None)
+            }
+        };
+        self.state = state;
+        saved
+    }
+
+    /// Remembers a saved value, so editing it again starts from there
+    fn save(&mut self, value: MenuChange) {
+        match value {
+            MenuChange::Unit(unit) => {
+                // The offset's in the old unit
+                let celsius = self.settings.unit.delta_to_celsius(self.settings.offset);
+                self.settings.offset = unit.delta_from_celsius(celsius);
+                self.settings.unit = unit;
+            }
+            MenuChange::Brightness(level) => {
+                self.settings.brightness = level;
+                self.settings.auto_brightness = self.settings.auto_brightness.map(|_| false);
+            }
+            MenuChange::AutoBrightness => self.settings.auto_brightness = Some(true),
+            MenuChange::Offset(offset) => self.settings.offset = offset,
+            MenuChange::Timer(minutes) => self.settings.timer_minutes = minutes,
+        }
+    }
+
+    /// Draws the menu into the display's buffer. Does nothing while it's closed.
+    pub fn render(&self, display: &mut HT16K33) {
+        match self.state {
+            MenuState::Closed => {}
+            MenuState::Browsing(item) => {
+                display.clear();
+                display.write_str(item.label());
+            }
+            MenuState::Editing(value) => {
+                display.clear();
+                value.render(display);
+            }
+        }
+    }
+}
+
+impl Default for Menu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+
+    use super::*;
+    use crate::mock::MockI2c;
+    use std::{string::String, vec::Vec};
+
+    const SETTINGS: MenuSettings = MenuSettings {
+        unit: TemperatureUnit::Fahrenheit,
+        brightness: MAX_BRIGHTNESS,
+        auto_brightness: None,
+        offset: -4.4,
+        timer_minutes: 0,
+    };
+
+    /// What the menu puts on the display
+    fn shown(menu: &Menu) -> String {
+        let mut i2c = MockI2c::default();
+        let mut display = HT16K33::init(0x70, &mut i2c).unwrap();
+        display.write_str("XXXX");
+        menu.render(&mut display);
+        display.write_display(&mut i2c).unwrap();
+        i2c.displayed_text().unwrap()
+    }
+
+    /// Sends each event in turn, returning what's shown after each and everything saved
+    fn walk(menu: &mut Menu, events: &[MenuEvent]) -> (Vec<String>, Vec<MenuChange>) {
+        let mut saved = Vec::new();
+        let shown = events
+            .iter()
+            .map(|&event| {
+                saved.extend(menu.handle(event));
+                shown(menu)
+            })
+            .collect();
+        (shown, saved)
+    }
+
+    use MenuEvent::{Back, Next, Select};
+
+    #[test]
+    fn browses_the_items() {
+        let mut menu = Menu::new();
+        assert!(!menu.is_open());
+        assert_eq!(shown(&menu), "XXXX");
+        menu.open(SETTINGS);
+        assert_eq!(shown(&menu), "UNIT");
+        let (shown, saved) = walk(&mut menu, &[Next, Next, Next, Next, Back]);
+        assert_eq!(shown, ["BRIT", "CAL ", "TIMR", "UNIT", "XXXX"]);
+        assert!(saved.is_empty());
+        assert!(!menu.is_open());
+    }
+
+    #[test]
+    fn edits_the_unit() {
+        let mut menu = Menu::new();
+        menu.open(SETTINGS);
+        let (shown, saved) = walk(&mut menu, &[Select, Next, Next, Next, Select]);
+        assert_eq!(shown, ["DEGF", "DEGC", "DEGF", "DEGC", "UNIT"]);
+        assert_eq!(saved, [MenuChange::Unit(TemperatureUnit::Celsius)]);
+
+        // The offset is converted along with it
+        let (shown, _) = walk(&mut menu, &[Next, Next, Select]);
+        assert_eq!(shown, ["BRIT", "CAL ", " -2.5"]);
+    }
+
+    #[test]
+    fn edits_brightness() {
+        let mut menu = Menu::new();
+        menu.open(SETTINGS);
+        let (shown, saved) = walk(&mut menu, &[Next, Select, Next, Next, Select]);
+        assert_eq!(shown, ["BRIT", "  15", "   0", "   1", "BRIT"]);
+        assert_eq!(saved, [MenuChange::Brightness(1)]);
+    }
+
+    #[test]
+    fn offers_automatic_brightness_with_a_light_sensor() {
+        let mut menu = Menu::new();
+        menu.open(MenuSettings {
+            auto_brightness: Some(false),
+            ..SETTINGS
+        });
+        let (shown, saved) = walk(&mut menu, &[Next, Select, Next, Next, Select]);
+        assert_eq!(shown, ["BRIT", "  15", "AUTO", "   0", "BRIT"]);
+        assert_eq!(saved, [MenuChange::Brightness(0)]);
+
+        // Picking it starts from it next time
+        let (shown, saved) = walk(&mut menu, &[Select, Next, Next, Next, Back, Select]);
+        assert_eq!(shown, ["   0", "   1", "   2", "   3", "BRIT", "   0"]);
+        assert!(saved.is_empty());
+        let mut events: Vec<_> = core::iter::repeat_n(Next, 16).collect();
+        events.extend([Select, Select]);
+        let (shown, saved) = walk(&mut menu, &events);
+        assert_eq!(shown[15..], ["AUTO", "BRIT", "AUTO"]);
+        assert_eq!(saved, [MenuChange::AutoBrightness]);
+    }
+
+    #[test]
+    fn edits_the_calibration() {
+        let mut menu = Menu::new();
+        menu.open(SETTINGS);
+        let mut events = Vec::from([Next, Next, Select]);
+        events.extend(core::iter::repeat_n(Next, 9));
+        events.push(Select);
+        let (shown, saved) = walk(&mut menu, &events);
+        assert_eq!(
+            shown,
+            [
+                "BRIT", "CAL ", " -4.5", " -4.0", " -3.5", " -3.0", " -2.5", " -2.0", " -1.5",
+                " -1.0", " -0.5", "  0.0", "CAL "
+            ]
+        );
+        assert_eq!(saved, [MenuChange::Offset(0.)]);
+
+        // Wraps around from the largest offset to the smallest
+        let mut events = Vec::from([Select]);
+        events.extend(core::iter::repeat_n(Next, 20));
+        let (shown, _) = walk(&mut menu, &events);
+        assert_eq!(shown[19..], [" +9.5", " -9.5"]);
+    }
+
+    #[test]
+    fn edits_the_timer() {
+        let mut menu = Menu::new();
+        menu.open(SETTINGS);
+        let (shown, saved) = walk(&mut menu, &[Next, Next, Next, Select, Next, Next, Select]);
+        assert_eq!(
+            shown,
+            ["BRIT", "CAL ", "TIMR", " OFF", "  1M", "  2M", "TIMR"]
+        );
+        assert_eq!(saved, [MenuChange::Timer(2)]);
+
+        // A running timer starts from what's left
+        menu.open(MenuSettings {
+            timer_minutes: 42,
+            ..SETTINGS
+        });
+        let (shown, _) = walk(&mut menu, &[Next, Next, Next, Select, Next, Next, Next]);
+        assert_eq!(shown[3..], [" 45M", " 60M", " 90M", "120M"]);
+        let (shown, _) = walk(&mut menu, &[Next]);
+        assert_eq!(shown, [" OFF"]);
+    }
+
+    #[test]
+    fn cancels_edits() {
+        let mut menu = Menu::new();
+        menu.open(SETTINGS);
+        let (shown, saved) = walk(&mut menu, &[Next, Select, Next, Back, Select]);
+        assert_eq!(shown, ["BRIT", "  15", "   0", "BRIT", "  15"]);
+        assert!(saved.is_empty());
+
+        // Closing drops the edit too
+        menu.close();
+        assert_eq!(menu.handle(Select), None);
+        assert!(!menu.is_open());
+    }
+
+    #[test]
+    fn gestures_drive_the_menu() {
+        assert_eq!(MenuEvent::from(Gesture::Press), Next);
+        assert_eq!(MenuEvent::from(Gesture::LongPress), Select);
+        assert_eq!(MenuEvent::from(Gesture::DoublePress), Back);
+    }
+}
@@ -0,0 +1,271 @@
+//! Mock peripherals for exercising the library on a host.
+
+extern crate std;
+
+use crate::ht16k33::{ALPHA_FONT_TABLE, ALPHA_POINT_MASK};
+use core::cell::{Cell, RefCell};
+use core::convert::Infallible;
+use embedded_hal::{
+    adc::{Channel, OneShot},
+    blocking::{delay::DelayMs, i2c},
+    digital::v2::{InputPin, OutputPin},
+};
+use std::{rc::Rc, string::String, vec::Vec};
+
+/// Error returned by [`MockI2c`] when it's told to fail
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct MockI2cError;
+
+/// A single I2C write, as `(address, bytes)`
+pub type Write = (u8, Vec<u8>);
+
+/// An I2C bus that records every write made to it, and keeps track of what a display on it would
+/// show
+#[derive(Clone, Default)]
+pub struct MockI2c {
+    writes: Rc<RefCell<Vec<Write>>>,
+    fail: Rc<Cell<bool>>,
+    /// The display RAM, once anything's been written to it
+    ram: Rc<RefCell<Option<[u8; 16]>>>,
+    /// The text shown after each write to the display RAM
+    frames: Rc<RefCell<Vec<String>>>,
+}
+
+impl MockI2c {
+    /// Every write made so far
+    pub fn writes(&self) -> Vec<Write> {
+        self.writes.borrow().clone()
+    }
+
+    /// Forgets all of the writes and frames so far. The display keeps showing what it was.
+    pub fn clear(&self) {
+        self.writes.borrow_mut().clear();
+        self.frames.borrow_mut().clear();
+    }
+
+    /// Makes every following write fail (or succeed again)
+    pub fn set_fail(&self, fail: bool) {
+        self.fail.set(fail);
+    }
+
+    /// The text the display shows, if anything's been written to it
+    pub fn displayed_text(&self) -> Option<String> {
+        self.ram.borrow().map(|ram| decode_frame(&ram))
+    }
+
+    /// The text shown after each write to the display since the last `clear`
+    pub fn frames(&self) -> Vec<String> {
+        self.frames.borrow().clone()
+    }
+
+    /// Whether the last system setup command put the display into standby
+    pub fn in_standby(&self) -> bool {
+        self.writes
+            .borrow()
+            .iter()
+            .rev()
+            .find(|(_, bytes)| bytes.len() == 1 && bytes[0] & 0xF0 == 0x20)
+            .is_some_and(|(_, bytes)| bytes[0] & 0x01 == 0)
+    }
+}
+
+impl i2c::Write for MockI2c {
+    type Error = MockI2cError;
+
+    fn write(&mut self, address: u8, bytes: &[u8]) -> Result<(), Self::Error> {
+        if self.fail.get() {
+            return Err(MockI2cError);
+        }
+        self.writes.borrow_mut().push((address, bytes.to_vec()));
+
+        // Display RAM writes start with the address, which auto-increments. Commands are a lone
+        // byte.
+        if let [start, data @ ..] = bytes {
+            if usize::from(*start) < 16 && !data.is_empty() {
+                let mut ram = self.ram.borrow_mut();
+                let ram = ram.get_or_insert([0; 16]);
+                for (offset, byte) in data.iter().enumerate() {
+                    ram[(usize::from(*start) + offset) % 16] = *byte;
+                }
+                self.frames.borrow_mut().push(decode_frame(ram));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Decodes display RAM (little endian 16 bit words) back into text. Digits and letters win over
+/// punctuation sharing the same glyph, and a lit decimal point is shown as a trailing `.`.
+pub fn decode_frame(ram: &[u8]) -> String {
+    let mut text = String::new();
+    for word in ram.chunks(2).take(4) {
+        let glyph = u16::from(word[0]) | (u16::from(word[1]) << 8);
+        let segments = glyph & !ALPHA_POINT_MASK;
+        let c = ('0'..='9')
+            .chain('A'..='Z')
+            .chain(' '..='~')
+            .find(|c| ALPHA_FONT_TABLE[*c as usize] == segments)
+            .unwrap_or('?');
+        text.push(c);
+        if glyph & ALPHA_POINT_MASK != 0 {
+            text.push('.');
+        }
+    }
+    text
+}
+
+/// How `text` reads back after a round trip through the display, for glyphs shared between
+/// characters (`1` and `!`, for instance)
+pub fn shown_as(text: &str) -> String {
+    let mut ram = Vec::new();
+    for c in text.chars() {
+        let glyph = ALPHA_FONT_TABLE[c as usize];
+        ram.push((glyph & 0xFF) as u8);
+        ram.push((glyph >> 8) as u8);
+    }
+    decode_frame(&ram)
+}
+
+/// An ADC whose readings are set by the test
+#[derive(Clone, Default)]
+pub struct MockAdc {
+    therm: Rc<Cell<u16>>,
+    batt: Rc<Cell<u16>>,
+    light: Rc<Cell<u16>>,
+}
+
+impl MockAdc {
+    /// Sets the raw reading of the thermocouple amplifier
+    pub fn set_therm(&self, reading: u16) {
+        self.therm.set(reading);
+    }
+
+    /// Sets the raw reading of the battery divider
+    pub fn set_batt(&self, reading: u16) {
+        self.batt.set(reading);
+    }
+
+    /// Sets the raw reading of the photoresistor
+    pub fn set_light(&self, reading: u16) {
+        self.light.set(reading);
+    }
+}
+
+/// The thermocouple amplifier's ADC channel
+pub struct ThermPin;
+
+impl Channel<MockAdc> for ThermPin {
+    type ID = u8;
+
+    fn channel() -> u8 {
+        0
+    }
+}
+
+/// The battery divider's ADC channel
+pub struct BattPin;
+
+impl Channel<MockAdc> for BattPin {
+    type ID = u8;
+
+    fn channel() -> u8 {
+        1
+    }
+}
+
+/// The photoresistor's ADC channel
+pub struct LightPin;
+
+impl Channel<MockAdc> for LightPin {
+    type ID = u8;
+
+    fn channel() -> u8 {
+        2
+    }
+}
+
+impl OneShot<MockAdc, u16, ThermPin> for MockAdc {
+    type Error = ();
+
+    fn read(&mut self, _pin: &mut ThermPin) -> nb::Result<u16, ()> {
+        Ok(self.therm.get())
+    }
+}
+
+impl OneShot<MockAdc, u16, BattPin> for MockAdc {
+    type Error = ();
+
+    fn read(&mut self, _pin: &mut BattPin) -> nb::Result<u16, ()> {
+        Ok(self.batt.get())
+    }
+}
+
+impl OneShot<MockAdc, u16, LightPin> for MockAdc {
+    type Error = ();
+
+    fn read(&mut self, _pin: &mut LightPin) -> nb::Result<u16, ()> {
+        Ok(self.light.get())
+    }
+}
+
+/// A pin that remembers its state, which can be driven from either end
+#[derive(Clone, Default)]
+pub struct MockPin {
+    high: Rc<Cell<bool>>,
+}
+
+impl MockPin {
+    pub fn is_high(&self) -> bool {
+        self.high.get()
+    }
+
+    /// Drives the pin from outside, as a button would
+    pub fn set(&self, high: bool) {
+        self.high.set(high);
+    }
+}
+
+impl OutputPin for MockPin {
+    type Error = Infallible;
+
+    fn set_low(&mut self) -> Result<(), Infallible> {
+        self.high.set(false);
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Infallible> {
+        self.high.set(true);
+        Ok(())
+    }
+}
+
+impl InputPin for MockPin {
+    type Error = Infallible;
+
+    fn is_high(&self) -> Result<bool, Infallible> {
+        Ok(self.high.get())
+    }
+
+    fn is_low(&self) -> Result<bool, Infallible> {
+        Ok(!self.high.get())
+    }
+}
+
+/// A delay that returns immediately, keeping track of how long it should have waited
+#[derive(Clone, Default)]
+pub struct MockDelay {
+    elapsed_ms: Rc<Cell<u32>>,
+}
+
+impl MockDelay {
+    /// Total time delayed so far
+    pub fn elapsed_ms(&self) -> u32 {
+        self.elapsed_ms.get()
+    }
+}
+
+impl DelayMs<u32> for MockDelay {
+    fn delay_ms(&mut self, ms: u32) {
+        self.elapsed_ms.set(self.elapsed_ms.get() + ms);
+    }
+}
@@ -0,0 +1,130 @@
+//! The SAMD21's own flash, through its NVM controller, as a [`Flash`] region for the settings
+//! store.
+
+extern crate feather_m0 as bsp;
+
+use crate::storage::Flash;
+use bsp::hal::pac::{nvmctrl::ctrla, NVMCTRL};
+use core::ptr;
+
+/// Bytes in a row, the smallest erasable unit
+const ROW_SIZE: usize = 256;
+/// Bytes in a page, the smallest writable unit
+const PAGE_SIZE: usize = 64;
+
+/// Errors the NVM controller reports
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum NvmError {
+    /// The region is locked
+    Locked,
+    /// The controller was given a bad command or address
+    Programming,
+    /// Anything else the controller flagged
+    Other,
+}
+
+/// A region of the SAMD21's flash, which must be kept out of the program (see `memory.x`)
+pub struct Nvm {
+    nvmctrl: NVMCTRL,
+    start: usize,
+    len: usize,
+}
+
+impl Nvm {
+    /// Takes over the NVM controller for the given region of flash
+    ///
+    /// # Arguments
+    /// * `nvmctrl`: The NVM controller
+    /// * `start`: Address of the region, aligned to a row
+    /// * `len`: Size of the region in bytes, a whole number of rows
+    pub fn new(nvmctrl: NVMCTRL, start: usize, len: usize) -> Self {
+        debug_assert!(start.is_multiple_of(ROW_SIZE) && len.is_multiple_of(ROW_SIZE));
+        // Write pages only when told to, rather than whenever the last word of the page buffer is
+        // written
+        nvmctrl.ctrlb.modify(|_, w| w.manw().set_bit());
+        Self {
+            nvmctrl,
+            start,
+            len,
+        }
+    }
+
+    /// Runs a command on the given address, waiting for it to finish
+    fn command(
+        &mut self,
+        address: usize,
+        cmd: impl FnOnce(&mut ctrla::W) -> &mut ctrla::W,
+    ) -> Result<(), NvmError> {
+        self.wait_ready();
+        // Clear errors left over from last time
+        self.nvmctrl.status.write(|w| {
+            w.proge().set_bit();
+            w.locke().set_bit();
+            w.nvme().set_bit()
+        });
+        // The address register is in 16 bit words
+        self.nvmctrl
+            .addr
+            .write(|w| unsafe { w.addr().bits((address / 2) as u32) });
+        self.nvmctrl.ctrla.write(|w| cmd(w).cmdex().key());
+        self.wait_ready();
+
+        let status = self.nvmctrl.status.read();
+        if status.locke().bit_is_set() {
+            Err(NvmError::Locked)
+        } else if status.proge().bit_is_set() {
+            Err(NvmError::Programming)
+        } else if status.nvme().bit_is_set() || self.nvmctrl.intflag.read().error().bit_is_set() {
+            Err(NvmError::Other)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Drops anything the flash cache holds, so reads see what was just erased or written
+    fn invalidate_cache(&mut self) -> Result<(), NvmError> {
+        self.command(self.start, |w| w.cmd().invall())
+    }
+
+    fn wait_ready(&self) {
+        while self.nvmctrl.intflag.read().ready().bit_is_clear() {}
+    }
+}
+
+impl Flash for Nvm {
+    type Error = NvmError;
+
+    const ERASE_SIZE: usize = ROW_SIZE;
+    const WRITE_SIZE: usize = PAGE_SIZE;
+
+    fn capacity(&self) -> usize {
+        self.len
+    }
+
+    fn read(&mut self, offset: usize, buf: &mut [u8]) -> Result<(), NvmError> {
+        let address = self.start + offset;
+        for (n, byte) in buf.iter_mut().enumerate() {
+            *byte = unsafe { ptr::read_volatile((address + n) as *const u8) };
+        }
+        Ok(())
+    }
+
+    fn erase(&mut self, offset: usize) -> Result<(), NvmError> {
+        self.command(self.start + offset, |w| w.cmd().er())?;
+        self.invalidate_cache()
+    }
+
+    fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), NvmError> {
+        for (n, page) in data.chunks(PAGE_SIZE).enumerate() {
+            let address = self.start + offset + n * PAGE_SIZE;
+            self.command(address, |w| w.cmd().pbc())?;
+            // The page buffer only takes whole words, written to the addresses they're bound for
+            for (i, word) in page.chunks(4).enumerate() {
+                let word = u32::from_le_bytes([word[0], word[1], word[2], word[3]]);
+                unsafe { ptr::write_volatile((address + i * 4) as *mut u32, word) };
+            }
+            self.command(address, |w| w.cmd().wp())?;
+        }
+        self.invalidate_cache()
+    }
+}
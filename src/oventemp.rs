@@ -1,13 +1,42 @@
 //! State-machine for when to display the temperature and when to conserve power.
 
-/// Threshold at which we start displaying the temperature
-const TEMP_ON_THRESHOLD: f32 = 100.;
-/// Threshold at which we turn the display back off as the oven cools off
-const TEMP_OFF_THRESHOLD: f32 = 300.;
-/// Some hysteresis to avoid thrash
-const TEMP_HYSTERESIS: f32 = 10.;
-
-#[derive(Copy, Clone, Eq, PartialEq)]
+use crate::cycle::{CycleAnalyzer, CycleStats, DEFAULT_CYCLE_HYSTERESIS};
+use crate::setpoint::{SetPointEstimate, SetPointEstimator};
+use crate::slope::SlopeTracker;
+use crate::temperature::{Temperature, TemperatureUnit};
+use crate::thermocouple::ThermocoupleFault;
+use core::convert::TryFrom;
+
+/// Unit the default thresholds are given in
+const DEFAULT_TEMP_UNIT: TemperatureUnit = TemperatureUnit::Fahrenheit;
+/// Default threshold at which we start displaying the temperature
+const DEFAULT_TEMP_ON_THRESHOLD: f32 = 100.;
+/// Default threshold at which we turn the display back off as the oven cools off
+const DEFAULT_TEMP_OFF_THRESHOLD: f32 = 300.;
+/// Default hysteresis to avoid thrash
+const DEFAULT_TEMP_HYSTERESIS: f32 = 10.;
+
+/// Number of readings the slope is fit over
+pub const SLOPE_WINDOW: usize = 30;
+/// Heating slower than this (C/min) means the oven has leveled off at its set point
+const PREHEAT_PLATEAU_SLOPE: f32 = 1.;
+/// Falling this far (C) from the peak means the thermostat has cut the element for the first time
+const PREHEAT_CYCLE_DROP: f32 = 2.;
+/// Falling faster than this (C/min) while at temperature means the door's been opened
+const DOOR_OPEN_RATE: f32 = 60.;
+/// Falling slower than this (C/min) with the door open means it's been closed again
+const DOOR_CLOSED_RATE: f32 = 10.;
+/// Number of readings the door's slope is fit over
+const DOOR_WINDOW: usize = 4;
+/// Getting back within this much (C) of where we were before the door opened counts as recovered
+const DOOR_RECOVERED_MARGIN: f32 = 2.;
+/// How long we wait for the door to close, and then again for the oven to recover, before
+/// believing it's cooling down. The oven may have been turned off too.
+const DOOR_TIMEOUT_MS: u64 = 5 * 60 * 1_000;
+/// Events we hold on to until they're polled
+const EVENT_QUEUE_LEN: usize = 4;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum OvenTempState {
     /// The oven is determined to be off and not running
     Off,
@@ -17,71 +46,962 @@ pub enum OvenTempState {
     AtTemp,
     /// The oven is cooling off and we should stop displaying the temp
     CoolingDown,
+    /// The thermocouple can't be trusted, so we don't know what the oven is doing
+    Fault(ThermocoupleFault),
+}
+
+/// A change of state, and when it happened
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Transition {
+    /// The state we left
+    pub from: OvenTempState,
+    /// The state we entered
+    pub to: OvenTempState,
+    /// Timestamp of the reading that caused it, ms
+    pub at: u64,
+}
+
+/// Something that happened worth telling the user about
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum OvenTempEvent {
+    /// The oven has finished preheating
+    PreheatComplete,
+    /// The thermostat finished a cycle while we were at temperature
+    CycleComplete(CycleStats),
+    /// The door was opened while the oven was at temperature
+    DoorOpened,
+    /// The door was closed again, after being open for `open_ms`
+    DoorClosed { open_ms: u32 },
+    /// The oven got back to temperature `recovery_ms` after the door was closed
+    DoorRecovered { recovery_ms: u32 },
+}
+
+/// Where we are in preheating the oven
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum PreheatStatus {
+    /// The oven hasn't been turned on since we've been watching
+    Idle,
+    /// The oven is heating up to its set point
+    Preheating,
+    /// The oven has reached its set point
+    Complete,
+}
+
+/// What we think the oven door is doing
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum DoorStatus {
+    /// Closed, as far as we can tell
+    Closed,
+    /// Open, with the oven losing heat fast
+    Open,
+    /// Closed again, with the oven heating back up to temperature
+    Recovering,
+}
+
+/// Reasons an [`OvenTempConfig`] can be rejected
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ConfigError {
+    /// The hysteresis is negative or not a number
+    InvalidHysteresis,
+    /// A threshold is not a finite number
+    InvalidThreshold,
+    /// The on and off bands overlap once hysteresis is applied
+    OverlappingThresholds,
+}
+
+/// The thresholds the [`OvenTemp`] state machine transitions on
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct OvenTempConfig {
+    unit: TemperatureUnit,
+    on_threshold: f32,
+    off_threshold: f32,
+    hysteresis: f32,
+}
+
+impl OvenTempConfig {
+    /// Creates a new, validated `OvenTempConfig`
+    ///
+    /// # Arguments
+    /// * `unit`: The unit the thresholds and hysteresis are given in
+    /// * `on_threshold`: Temperature at which we start displaying the temperature
+    /// * `off_threshold`: Temperature at which we turn the display back off as the oven cools off
+    /// * `hysteresis`: Band applied around both thresholds to avoid thrash
+    ///
+    /// # Returns
+    /// the config, or an error if `on_threshold + hysteresis` is not strictly below
+    /// `off_threshold - hysteresis`
+    pub fn new(
+        unit: TemperatureUnit,
+        on_threshold: f32,
+        off_threshold: f32,
+        hysteresis: f32,
+    ) -> Result<Self, ConfigError> {
+        if !on_threshold.is_finite() || !off_threshold.is_finite() {
+            return Err(ConfigError::InvalidThreshold);
+        }
+        if !hysteresis.is_finite() || hysteresis < 0. {
+            return Err(ConfigError::InvalidHysteresis);
+        }
+        if on_threshold + hysteresis >= off_threshold - hysteresis {
+            return Err(ConfigError::OverlappingThresholds);
+        }
+
+        Ok(Self {
+            unit,
+            on_threshold,
+            off_threshold,
+            hysteresis,
+        })
+    }
+
+    /// The unit the thresholds and hysteresis are given in
+    #[must_use]
+    pub const fn unit(&self) -> TemperatureUnit {
+        self.unit
+    }
+
+    /// Converts the thresholds and hysteresis to the given unit
+    #[must_use]
+    pub fn in_unit(&self, unit: TemperatureUnit) -> Self {
+        let convert = |value| Temperature::from_unit(value, self.unit).in_unit(unit);
+        Self {
+            unit,
+            on_threshold: convert(self.on_threshold),
+            off_threshold: convert(self.off_threshold),
+            hysteresis: unit.delta_from_celsius(self.unit.delta_to_celsius(self.hysteresis)),
+        }
+    }
+
+    /// Temperature at which we start displaying the temperature
+    #[must_use]
+    pub const fn on_threshold(&self) -> f32 {
+        self.on_threshold
+    }
+
+    /// Temperature at which we turn the display back off as the oven cools off
+    #[must_use]
+    pub const fn off_threshold(&self) -> f32 {
+        self.off_threshold
+    }
+
+    /// Band applied around both thresholds to avoid thrash
+    #[must_use]
+    pub const fn hysteresis(&self) -> f32 {
+        self.hysteresis
+    }
+}
+
+impl Default for OvenTempConfig {
+    fn default() -> Self {
+        Self {
+            unit: DEFAULT_TEMP_UNIT,
+            on_threshold: DEFAULT_TEMP_ON_THRESHOLD,
+            off_threshold: DEFAULT_TEMP_OFF_THRESHOLD,
+            hysteresis: DEFAULT_TEMP_HYSTERESIS,
+        }
+    }
+}
+
+/// Progress towards detecting the end of preheating
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Preheat {
+    Idle,
+    /// Hottest reading so far, and how many readings since we started watching
+    Preheating {
+        peak: f32,
+        readings: usize,
+    },
+    Complete,
+}
+
+/// Tracking of the door, with where the temperature was before it opened
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Door {
+    Closed,
+    Open { before: f32, opened_ms: u64 },
+    Recovering { before: f32, closed_ms: u64 },
 }
 
 /// Structure to keep track of our oven temp state
 pub struct OvenTemp {
     /// The current state of our oven
     pub state: OvenTempState,
+    /// The thresholds we transition on
+    config: OvenTempConfig,
+    /// Timestamp of the last reading, ms
+    time_ms: u64,
+    /// When we entered the current state, ms
+    entered_ms: u64,
+    slope: SlopeTracker<SLOPE_WINDOW>,
+    preheat: Preheat,
+    /// Short term slope, for catching the door being opened
+    door_slope: SlopeTracker<DOOR_WINDOW>,
+    door: Door,
+    /// Only fed while `AtTemp` with the door shut, where the thermostat is in charge
+    cycle: CycleAnalyzer,
+    set_point: SetPointEstimator,
+    events: [Option<OvenTempEvent>; EVENT_QUEUE_LEN],
 }
 
 impl OvenTemp {
     /// Creates a new `OvenTemp` object
+    ///
+    /// # Arguments
+    /// * `config`: The thresholds to transition on
     #[must_use]
-    pub const fn new() -> Self {
+    pub const fn new(config: OvenTempConfig) -> Self {
         Self {
             state: OvenTempState::AtTemp,
+            config,
+            time_ms: 0,
+            entered_ms: 0,
+            slope: SlopeTracker::new(),
+            preheat: Preheat::Idle,
+            door_slope: SlopeTracker::new(),
+            door: Door::Closed,
+            cycle: CycleAnalyzer::new(DEFAULT_CYCLE_HYSTERESIS),
+            set_point: SetPointEstimator::new(),
+            events: [None; EVENT_QUEUE_LEN],
         }
     }
 
+    /// When we entered the current state, ms
+    #[must_use]
+    pub fn entered_at(&self) -> u64 {
+        self.entered_ms
+    }
+
+    /// How long we've been in the current state as of the last reading, ms
+    #[must_use]
+    pub fn time_in_state(&self) -> u64 {
+        self.time_ms.saturating_sub(self.entered_ms)
+    }
+
+    /// How fast the temperature is changing, in degrees per minute of the config's unit
+    ///
+    /// # Returns
+    /// the slope, or `None` until we've seen `SLOPE_WINDOW` readings
+    #[must_use]
+    pub fn slope(&self) -> Option<f32> {
+        self.slope.per_minute()
+    }
+
+    /// Where we are in preheating the oven
+    #[must_use]
+    pub fn preheat(&self) -> PreheatStatus {
+        match self.preheat {
+            Preheat::Idle => PreheatStatus::Idle,
+            Preheat::Preheating { .. } => PreheatStatus::Preheating,
+            Preheat::Complete => PreheatStatus::Complete,
+        }
+    }
+
+    /// What we think the oven door is doing
+    #[must_use]
+    pub fn door(&self) -> DoorStatus {
+        match self.door {
+            Door::Closed => DoorStatus::Closed,
+            Door::Open { .. } => DoorStatus::Open,
+            Door::Recovering { .. } => DoorStatus::Recovering,
+        }
+    }
+
+    /// How the thermostat is cycling the oven
+    ///
+    /// # Returns
+    /// the last full cycle, or `None` if we haven't been `AtTemp` long enough to see one
+    #[must_use]
+    pub fn cycle(&self) -> Option<CycleStats> {
+        self.cycle.stats()
+    }
+
+    /// What the oven's dial is really set to, judging by the thermostat cycles we've seen since
+    /// the oven was last off
+    #[must_use]
+    pub fn set_point(&self) -> Option<SetPointEstimate> {
+        self.set_point.estimate()
+    }
+
+    /// Takes the oldest event that hasn't been polled yet
+    pub fn poll_event(&mut self) -> Option<OvenTempEvent> {
+        let event = self.events[0].take();
+        self.events.rotate_left(1);
+        event
+    }
+
+    /// Queues an event, dropping the oldest if nobody's been polling
+    fn push_event(&mut self, event: OvenTempEvent) {
+        if self.events[EVENT_QUEUE_LEN - 1].is_some() {
+            self.poll_event();
+        }
+        if let Some(slot) = self.events.iter_mut().find(|slot| slot.is_none()) {
+            *slot = Some(event);
+        }
+    }
+
+    /// Watches for the oven to finish preheating: either leveling off, or the thermostat cutting
+    /// the element for the first time
+    fn check_preheat(&mut self, temp: f32) {
+        let unit = self.config.unit;
+        if let Preheat::Preheating { peak, readings } = &mut self.preheat {
+            *peak = peak.max(temp);
+            *readings += 1;
+            let heating = matches!(self.state, OvenTempState::HeatingUp | OvenTempState::AtTemp);
+            // Only trust a slope fit entirely to readings taken while preheating
+            let plateaued = *readings >= SLOPE_WINDOW
+                && self.slope.per_minute().is_some_and(|slope| {
+                    slope.abs() < unit.delta_from_celsius(PREHEAT_PLATEAU_SLOPE)
+                });
+            let cycled = *peak - temp >= unit.delta_from_celsius(PREHEAT_CYCLE_DROP);
+            if heating && (plateaued || cycled) {
+                self.preheat = Preheat::Complete;
+                self.push_event(OvenTempEvent::PreheatComplete);
+            }
+        }
+    }
+
+    /// Watches for the door being opened while at temperature, and the oven recovering once it's
+    /// closed again
+    fn check_door(&mut self, temp: f32) {
+        let unit = self.config.unit;
+        if self.state != OvenTempState::AtTemp {
+            self.door = Door::Closed;
+            return;
+        }
+        let slope = match self.door_slope.per_minute() {
+            Some(slope) => slope,
+            None => return,
+        };
+        let opening = slope <= -unit.delta_from_celsius(DOOR_OPEN_RATE);
+        let now_ms = self.time_ms;
+        let elapsed = |since_ms: u64| u32::try_from(now_ms - since_ms).unwrap_or(u32::MAX);
+
+        match self.door {
+            Door::Closed => {
+                if opening {
+                    self.door = Door::Open {
+                        // The drop started before it was steep enough for us to notice
+                        before: self.door_slope.oldest().unwrap_or(temp),
+                        opened_ms: self.time_ms,
+                    };
+                    self.push_event(OvenTempEvent::DoorOpened);
+                }
+            }
+            Door::Open { before, opened_ms } => {
+                if self.time_ms - opened_ms >= DOOR_TIMEOUT_MS {
+                    // Not a door, or not one that's going to be closed: let it cool down
+                    self.door = Door::Closed;
+                } else if slope > -unit.delta_from_celsius(DOOR_CLOSED_RATE) {
+                    self.door = Door::Recovering {
+                        before,
+                        closed_ms: self.time_ms,
+                    };
+                    self.push_event(OvenTempEvent::DoorClosed {
+                        open_ms: elapsed(opened_ms),
+                    });
+                }
+            }
+            Door::Recovering { before, closed_ms } => {
+                if opening {
+                    // Opened again before we'd recovered, so we're still aiming for the original
+                    // temperature
+                    self.door = Door::Open {
+                        before,
+                        opened_ms: self.time_ms,
+                    };
+                    self.push_event(OvenTempEvent::DoorOpened);
+                } else if temp >= before - unit.delta_from_celsius(DOOR_RECOVERED_MARGIN) {
+                    self.door = Door::Closed;
+                    self.push_event(OvenTempEvent::DoorRecovered {
+                        recovery_ms: elapsed(closed_ms),
+                    });
+                } else if self.time_ms - closed_ms >= DOOR_TIMEOUT_MS {
+                    // Not coming back: let it cool down
+                    self.door = Door::Closed;
+                }
+            }
+        }
+    }
+
+    /// The thresholds this state machine transitions on
+    #[must_use]
+    pub const fn config(&self) -> &OvenTempConfig {
+        &self.config
+    }
+
     /// Checks for state transitions given the new temperature information
     ///
     /// # Arguments
     /// * `temp`: The new temperature reading
+    /// * `now_ms`: When the reading was taken, from a monotonic clock in ms
     ///
     /// # Returns
-    /// the new oven temp state, if a transition occurred
-    pub fn check_transition(&mut self, temp: f32) -> Option<OvenTempState> {
+    /// the transition, if one occurred
+    pub fn check_transition(&mut self, temp: Temperature, now_ms: u64) -> Option<Transition> {
+        let reading = temp;
+        let temp = temp.in_unit(self.config.unit);
+        let from = self.state;
+        self.time_ms = now_ms;
+        self.slope.push(self.time_ms, temp);
+        self.door_slope.push(self.time_ms, temp);
+        self.check_door(temp);
+        let on_threshold = self.config.on_threshold;
+        let off_threshold = self.config.off_threshold;
+        let hysteresis = self.config.hysteresis;
+
         // Potentially move to a new state
         let new_state_opt = match self.state {
             OvenTempState::Off => {
-                if temp >= TEMP_ON_THRESHOLD + TEMP_HYSTERESIS {
+                if temp >= on_threshold + hysteresis {
                     Some(OvenTempState::HeatingUp)
                 } else {
                     None
                 }
             }
             OvenTempState::HeatingUp => {
-                if temp >= TEMP_OFF_THRESHOLD + TEMP_HYSTERESIS {
+                if temp >= off_threshold + hysteresis {
                     Some(OvenTempState::AtTemp)
-                } else if temp < TEMP_ON_THRESHOLD - TEMP_HYSTERESIS {
+                } else if temp < on_threshold - hysteresis {
                     Some(OvenTempState::Off)
                 } else {
                     None
                 }
             }
             OvenTempState::AtTemp => {
-                if temp <= TEMP_OFF_THRESHOLD - TEMP_HYSTERESIS {
+                // An open door isn't the oven turning off, so stay put until it's had a chance
+                // to recover
+                if temp <= off_threshold - hysteresis && self.door == Door::Closed {
                     Some(OvenTempState::CoolingDown)
                 } else {
                     None
                 }
             }
             OvenTempState::CoolingDown => {
-                if temp <= TEMP_ON_THRESHOLD - TEMP_HYSTERESIS {
+                if temp <= on_threshold - hysteresis {
                     Some(OvenTempState::Off)
-                } else if temp >= TEMP_OFF_THRESHOLD + TEMP_HYSTERESIS {
+                } else if temp >= off_threshold + hysteresis {
                     Some(OvenTempState::AtTemp)
                 } else {
                     None
                 }
             }
+            OvenTempState::Fault(_) => {
+                // We lost track of the oven, so pick up wherever it is now. In between the
+                // thresholds we can't tell heating from cooling, so keep the display on.
+                if temp < on_threshold {
+                    Some(OvenTempState::Off)
+                } else if temp >= off_threshold {
+                    Some(OvenTempState::AtTemp)
+                } else {
+                    Some(OvenTempState::HeatingUp)
+                }
+            }
         };
 
         if let Some(new_state) = new_state_opt {
             self.state = new_state;
+            self.entered_ms = now_ms;
+            match new_state {
+                OvenTempState::Off => {
+                    // The dial's probably going somewhere else next time
+                    self.preheat = Preheat::Idle;
+                    self.set_point.reset();
+                }
+                OvenTempState::HeatingUp if self.preheat != Preheat::Complete => {
+                    self.preheat = Preheat::Preheating {
+                        peak: temp,
+                        readings: 0,
+                    };
+                }
+                _ => {}
+            }
+        }
+        self.check_preheat(temp);
+
+        if self.state == OvenTempState::AtTemp && self.door == Door::Closed {
+            if let Some(stats) = self.cycle.push(self.time_ms, reading) {
+                self.set_point.push(&stats);
+                self.push_event(OvenTempEvent::CycleComplete(stats));
+            }
+        } else {
+            self.cycle.reset();
+        }
+
+        new_state_opt.map(|to| Transition {
+            from,
+            to,
+            at: now_ms,
+        })
+    }
+
+    /// Moves into the fault state, where we stay until readings can be trusted again and
+    /// `check_transition` is next called
+    ///
+    /// # Arguments
+    /// * `fault`: What's wrong with the thermocouple
+    /// * `now_ms`: When the fault was found, from the same clock as `check_transition`
+    ///
+    /// # Returns
+    /// the transition, if the state changed
+    pub fn set_fault(&mut self, fault: ThermocoupleFault, now_ms: u64) -> Option<Transition> {
+        let new_state = OvenTempState::Fault(fault);
+        self.time_ms = now_ms;
+        if self.state == new_state {
+            None
+        } else {
+            let from = self.state;
+            self.state = new_state;
+            self.entered_ms = now_ms;
+            self.door = Door::Closed;
+            self.cycle.reset();
+            Some(Transition {
+                from,
+                to: new_state,
+                at: now_ms,
+            })
+        }
+    }
+}
+
+impl Default for OvenTemp {
+    fn default() -> Self {
+        Self::new(OvenTempConfig::default())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+
+    use super::*;
+    use std::vec::Vec;
+
+    fn c(celsius: f32) -> Temperature {
+        Temperature::from_celsius(celsius)
+    }
+
+    /// on = 200, off = 400, hysteresis = 5, so the edges are 195 / 205 / 395 / 405
+    fn custom_config() -> OvenTempConfig {
+        OvenTempConfig::new(TemperatureUnit::Celsius, 200., 400., 5.).unwrap()
+    }
+
+    fn oven_in(state: OvenTempState) -> OvenTemp {
+        let mut oven = OvenTemp::new(custom_config());
+        oven.state = state;
+        oven
+    }
+
+    /// Feeds a reading a second after the last one, returning the state we moved to, if any
+    fn step(oven: &mut OvenTemp, celsius: f32) -> Option<OvenTempState> {
+        let now_ms = oven.time_ms + 1_000;
+        oven.check_transition(c(celsius), now_ms)
+            .map(|transition| transition.to)
+    }
+
+    #[test]
+    fn config_validation() {
+        assert!(OvenTempConfig::new(TemperatureUnit::Celsius, 100., 300., 10.).is_ok());
+        assert!(OvenTempConfig::new(TemperatureUnit::Celsius, 100., 300., 0.).is_ok());
+        assert_eq!(
+            OvenTempConfig::new(TemperatureUnit::Celsius, 100., 300., -1.),
+            Err(ConfigError::InvalidHysteresis)
+        );
+        assert_eq!(
+            OvenTempConfig::new(TemperatureUnit::Celsius, 100., 300., f32::NAN),
+            Err(ConfigError::InvalidHysteresis)
+        );
+        assert_eq!(
+            OvenTempConfig::new(TemperatureUnit::Celsius, f32::INFINITY, 300., 10.),
+            Err(ConfigError::InvalidThreshold)
+        );
+        // bands touch once hysteresis is applied
+        assert_eq!(
+            OvenTempConfig::new(TemperatureUnit::Celsius, 100., 120., 10.),
+            Err(ConfigError::OverlappingThresholds)
+        );
+        // thresholds swapped
+        assert_eq!(
+            OvenTempConfig::new(TemperatureUnit::Celsius, 300., 100., 10.),
+            Err(ConfigError::OverlappingThresholds)
+        );
+        assert_eq!(
+            OvenTempConfig::default(),
+            OvenTempConfig::new(TemperatureUnit::Fahrenheit, 100., 300., 10.).unwrap()
+        );
+    }
+
+    #[test]
+    fn off_transitions() {
+        let mut oven = oven_in(OvenTempState::Off);
+        assert_eq!(step(&mut oven, 204.9), None);
+        assert_eq!(step(&mut oven, 205.), Some(OvenTempState::HeatingUp));
+        assert!(oven.state == OvenTempState::HeatingUp);
+    }
+
+    #[test]
+    fn heating_up_transitions() {
+        let mut oven = oven_in(OvenTempState::HeatingUp);
+        assert_eq!(step(&mut oven, 195.), None);
+        assert_eq!(step(&mut oven, 404.9), None);
+        assert_eq!(step(&mut oven, 405.), Some(OvenTempState::AtTemp));
+
+        let mut oven = oven_in(OvenTempState::HeatingUp);
+        assert_eq!(step(&mut oven, 194.9), Some(OvenTempState::Off));
+    }
+
+    #[test]
+    fn at_temp_transitions() {
+        let mut oven = oven_in(OvenTempState::AtTemp);
+        assert_eq!(step(&mut oven, 395.1), None);
+        assert_eq!(step(&mut oven, 1000.), None);
+        assert_eq!(step(&mut oven, 395.), Some(OvenTempState::CoolingDown));
+    }
+
+    #[test]
+    fn cooling_down_transitions() {
+        let mut oven = oven_in(OvenTempState::CoolingDown);
+        assert_eq!(step(&mut oven, 195.1), None);
+        assert_eq!(step(&mut oven, 404.9), None);
+        assert_eq!(step(&mut oven, 405.), Some(OvenTempState::AtTemp));
+
+        let mut oven = oven_in(OvenTempState::CoolingDown);
+        assert_eq!(step(&mut oven, 195.), Some(OvenTempState::Off));
+    }
+
+    #[test]
+    fn transitions_are_timestamped() {
+        let mut oven = oven_in(OvenTempState::Off);
+        assert_eq!(oven.check_transition(c(20.), 5_000), None);
+        assert_eq!(oven.time_in_state(), 5_000);
+        assert_eq!(
+            oven.check_transition(c(250.), 65_000),
+            Some(Transition {
+                from: OvenTempState::Off,
+                to: OvenTempState::HeatingUp,
+                at: 65_000
+            })
+        );
+        assert_eq!(oven.time_in_state(), 0);
+        oven.check_transition(c(300.), 125_000);
+        assert_eq!(oven.time_in_state(), 60_000);
+
+        // Readings don't have to be evenly spaced
+        oven.check_transition(c(350.), 126_500);
+        assert_eq!(oven.time_in_state(), 61_500);
+        assert_eq!(
+            oven.check_transition(c(410.), 400_000),
+            Some(Transition {
+                from: OvenTempState::HeatingUp,
+                to: OvenTempState::AtTemp,
+                at: 400_000
+            })
+        );
+    }
+
+    #[test]
+    fn fault_transitions() {
+        let open = OvenTempState::Fault(ThermocoupleFault::Open);
+        let mut oven = oven_in(OvenTempState::AtTemp);
+        assert_eq!(
+            oven.set_fault(ThermocoupleFault::Open, 1_000),
+            Some(Transition {
+                from: OvenTempState::AtTemp,
+                to: open,
+                at: 1_000
+            })
+        );
+        assert_eq!(oven.set_fault(ThermocoupleFault::Open, 2_000), None);
+        assert_eq!(oven.time_in_state(), 1_000);
+        assert_eq!(
+            oven.set_fault(ThermocoupleFault::Shorted, 3_000),
+            Some(Transition {
+                from: open,
+                to: OvenTempState::Fault(ThermocoupleFault::Shorted),
+                at: 3_000
+            })
+        );
+
+        // Recovering picks up wherever the oven is now, without hysteresis
+        for (temp, state) in [
+            (199.9, OvenTempState::Off),
+            (200., OvenTempState::HeatingUp),
+            (399.9, OvenTempState::HeatingUp),
+            (400., OvenTempState::AtTemp),
+        ] {
+            let mut oven = oven_in(open);
+            assert_eq!(step(&mut oven, temp), Some(state), "{}", temp);
+        }
+    }
+
+    /// Feeds readings one (simulated) second apart, returning how many it took for preheating
+    /// to complete, if it did
+    fn feed(oven: &mut OvenTemp, readings: impl Iterator<Item = f32>) -> Option<usize> {
+        let mut completed_after = None;
+        for (n, reading) in readings.enumerate() {
+            step(oven, reading);
+            while let Some(event) = oven.poll_event() {
+                if event == OvenTempEvent::PreheatComplete {
+                    assert!(completed_after.is_none(), "completed twice");
+                    completed_after = Some(n);
+                }
+            }
+        }
+        completed_after
+    }
+
+    /// Heat from 20C to the given temperature at 1C/s
+    fn heat_to(celsius: u16) -> impl Iterator<Item = f32> {
+        (20..=celsius).map(f32::from)
+    }
+
+    #[test]
+    fn tracks_slope() {
+        let mut oven = OvenTemp::new(custom_config());
+        assert_eq!(oven.slope(), None);
+        feed(&mut oven, heat_to(100));
+        assert!((oven.slope().unwrap() - 60.).abs() < 0.01);
+
+        // Reported in the config's unit, and honoring the timestamps
+        let mut oven = OvenTemp::default();
+        for (n, temp) in heat_to(100).enumerate() {
+            oven.check_transition(c(temp), n as u64 * 2_000);
+        }
+        assert!((oven.slope().unwrap() - 54.).abs() < 0.01);
+    }
+
+    #[test]
+    fn preheat_completes_on_plateau() {
+        let mut oven = oven_in(OvenTempState::Off);
+        assert_eq!(oven.preheat(), PreheatStatus::Idle);
+        assert_eq!(feed(&mut oven, heat_to(380)), None);
+        assert_eq!(oven.preheat(), PreheatStatus::Preheating);
+
+        // Levels off below the AtTemp threshold: still counts
+        let completed_after = feed(&mut oven, core::iter::repeat_n(380., 60)).unwrap();
+        assert!(
+            completed_after > 20 && completed_after < 30,
+            "{}",
+            completed_after
+        );
+        assert_eq!(oven.preheat(), PreheatStatus::Complete);
+    }
+
+    #[test]
+    fn preheat_completes_on_first_cycle() {
+        let mut oven = oven_in(OvenTempState::Off);
+        assert_eq!(feed(&mut oven, heat_to(420)), None);
+        // Thermostat cuts out and the oven starts to drift down
+        let cooling = (0..20).map(|n| 420. - n as f32 * 0.5);
+        assert_eq!(feed(&mut oven, cooling), Some(4));
+
+        // Only once, even as it cycles
+        let cycling = (0..200).map(|n| 410. + ((n % 40) as f32 - 20.).abs());
+        assert_eq!(feed(&mut oven, cycling), None);
+    }
+
+    #[test]
+    fn preheat_rearms_after_turning_off() {
+        let mut oven = oven_in(OvenTempState::Off);
+        feed(&mut oven, heat_to(420));
+        feed(&mut oven, core::iter::repeat_n(420., 40));
+        assert_eq!(oven.preheat(), PreheatStatus::Complete);
+
+        // Opening the door mid-bake doesn't start a new preheat
+        feed(&mut oven, core::iter::repeat_n(380., 5));
+        feed(&mut oven, heat_to(420).skip(360));
+        assert_eq!(oven.preheat(), PreheatStatus::Complete);
+
+        feed(&mut oven, (20..=420_u16).rev().map(f32::from));
+        assert_eq!(oven.state, OvenTempState::Off);
+        assert_eq!(oven.preheat(), PreheatStatus::Idle);
+        assert!(feed(
+            &mut oven,
+            heat_to(420).chain(core::iter::repeat_n(420., 40))
+        )
+        .is_some());
+    }
+
+    #[test]
+    fn booting_hot_is_not_preheating() {
+        let mut oven = OvenTemp::new(custom_config());
+        assert_eq!(feed(&mut oven, core::iter::repeat_n(420., 100)), None);
+        assert_eq!(oven.preheat(), PreheatStatus::Idle);
+    }
+
+    #[test]
+    fn measures_thermostat_cycles() {
+        let mut oven = oven_in(OvenTempState::AtTemp);
+        // Heats from 410C to 430C over a minute, then coasts back down over two
+        let sawtooth = |n: u32| {
+            let t = n % 180;
+            if t < 60 {
+                410. + t as f32 / 3.
+            } else {
+                430. - (t - 60) as f32 / 6.
+            }
+        };
+        let mut cycles = 0;
+        for n in 0..15 * 60 {
+            step(&mut oven, sawtooth(n));
+            while let Some(event) = oven.poll_event() {
+                if let OvenTempEvent::CycleComplete(stats) = event {
+                    assert_eq!(Some(stats), oven.cycle());
+                    cycles += 1;
+                }
+            }
+        }
+        // The first trough is at 3 minutes, so the cycles ending at 6, 9 and 12 minutes
+        assert_eq!(cycles, 3);
+        let stats = oven.cycle().unwrap();
+        assert_eq!(stats.period_ms, 180_000);
+        assert!((stats.swing - 20.).abs() < 0.01, "{:?}", stats);
+        assert!((stats.duty_cycle - 1. / 3.).abs() < 0.01, "{:?}", stats);
+        assert!((stats.mean.celsius() - 420.).abs() < 0.1, "{:?}", stats);
+        let set_point = oven.set_point().unwrap();
+        assert!((set_point.set_point.celsius() - 420.).abs() < 0.01);
+        assert!((set_point.confidence - 0.75).abs() < 0.01);
+
+        // Turning the oven off knocks us out of AtTemp, and what we knew about the cycle with it
+        feed(&mut oven, (0..200).map(|n| 425. - n as f32 * 0.2));
+        assert_eq!(oven.state, OvenTempState::CoolingDown);
+        assert_eq!(oven.cycle(), None);
+        // But not the dial
+        assert!(oven.set_point().is_some());
+        step(&mut oven, 190.);
+        assert_eq!(oven.state, OvenTempState::Off);
+        assert_eq!(oven.set_point(), None);
+    }
+
+    /// Feeds the readings, returning the door events along with the reading they came after
+    fn door_events(
+        oven: &mut OvenTemp,
+        readings: impl Iterator<Item = f32>,
+    ) -> Vec<(usize, OvenTempEvent)> {
+        let mut events = Vec::new();
+        for (n, reading) in readings.enumerate() {
+            step(oven, reading);
+            assert_eq!(oven.state, OvenTempState::AtTemp, "{}: {}", n, reading);
+            while let Some(event) = oven.poll_event() {
+                if !matches!(event, OvenTempEvent::CycleComplete(_)) {
+                    events.push((n, event));
+                }
+            }
         }
+        events
+    }
+
+    #[test]
+    fn door_open_is_not_cooling_down() {
+        let mut oven = oven_in(OvenTempState::AtTemp);
+        let holding = core::iter::repeat_n(420., 10);
+        // Loses 4C a second with the door open for 10s, bottoming out below AtTemp
+        let open = (1..=10).map(|n| 420. - 4. * n as f32);
+        // Then heats back up at 0.5C/s once it's closed
+        let recovering = (1..=80).map(|n| 380. + 0.5 * n as f32);
+        let events = door_events(
+            &mut oven,
+            holding
+                .chain(open)
+                .chain(recovering)
+                .chain(core::iter::repeat_n(420., 10)),
+        );
+
+        assert_eq!(events.len(), 3, "{:?}", events);
+        let (opened, closed, recovered) = (events[0].0, events[1].0, events[2].0);
+        assert_eq!(events[0].1, OvenTempEvent::DoorOpened);
+        assert!((10..14).contains(&opened), "{}", opened);
+        assert_eq!(
+            events[1].1,
+            OvenTempEvent::DoorClosed {
+                open_ms: (closed - opened) as u32 * 1_000
+            }
+        );
+        assert!((20..24).contains(&closed), "{}", closed);
+        // Back within 2C of the 420C it was at before the door opened
+        assert_eq!(
+            events[2].1,
+            OvenTempEvent::DoorRecovered {
+                recovery_ms: (recovered - closed) as u32 * 1_000
+            }
+        );
+        assert_eq!(recovered, 19 + 76);
+        assert_eq!(oven.door(), DoorStatus::Closed);
+    }
 
-        new_state_opt
+    #[test]
+    fn door_left_open_times_out() {
+        let mut oven = oven_in(OvenTempState::AtTemp);
+        feed(&mut oven, core::iter::repeat_n(600., 10));
+        // The oven was turned off and the door left open, so it keeps falling fast
+        let mut readings = (0..).map(|n| 600. - 1.2 * n as f32);
+        feed(&mut oven, readings.by_ref().take(10));
+        assert_eq!(oven.door(), DoorStatus::Open);
+        feed(&mut oven, readings.by_ref().take(5 * 60 - 10));
+        assert_eq!(oven.state, OvenTempState::AtTemp);
+        feed(&mut oven, readings.by_ref().take(10));
+        assert_eq!(oven.state, OvenTempState::CoolingDown);
+        assert_eq!(oven.door(), DoorStatus::Closed);
+    }
+
+    #[test]
+    fn event_queue_drops_oldest() {
+        let mut oven = OvenTemp::default();
+        assert_eq!(oven.poll_event(), None);
+        for _ in 0..EVENT_QUEUE_LEN + 2 {
+            oven.push_event(OvenTempEvent::PreheatComplete);
+        }
+        for _ in 0..EVENT_QUEUE_LEN {
+            assert_eq!(oven.poll_event(), Some(OvenTempEvent::PreheatComplete));
+        }
+        assert_eq!(oven.poll_event(), None);
+    }
+
+    #[test]
+    fn readings_in_another_unit() {
+        // Default config is in Fahrenheit: HeatingUp at 110F (43.3C), AtTemp at 310F (154.4C)
+        let mut oven = OvenTemp {
+            state: OvenTempState::Off,
+            ..OvenTemp::default()
+        };
+        assert_eq!(step(&mut oven, 43.), None);
+        assert_eq!(step(&mut oven, 44.), Some(OvenTempState::HeatingUp));
+        assert_eq!(step(&mut oven, 154.), None);
+        assert_eq!(step(&mut oven, 155.), Some(OvenTempState::AtTemp));
+    }
+
+    #[test]
+    fn config_unit_conversion() {
+        let config = OvenTempConfig::default().in_unit(TemperatureUnit::Celsius);
+        assert_eq!(config.unit(), TemperatureUnit::Celsius);
+        assert!((config.on_threshold() - 37.778).abs() < 0.01);
+        assert!((config.off_threshold() - 148.889).abs() < 0.01);
+        assert!((config.hysteresis() - 5.556).abs() < 0.01);
+    }
+
+    #[test]
+    fn full_cycle_with_custom_config() {
+        let mut oven = OvenTemp::new(custom_config());
+        // we boot assuming the oven is hot, and fall back to off once it's cold
+        assert_eq!(step(&mut oven, 70.), Some(OvenTempState::CoolingDown));
+        assert_eq!(step(&mut oven, 70.), Some(OvenTempState::Off));
+
+        let mut transitions = [None; 6];
+        for (slot, temp) in transitions
+            .iter_mut()
+            .zip([150., 250., 350., 450., 300., 100.])
+        {
+            *slot = step(&mut oven, temp);
+        }
+        assert_eq!(
+            transitions,
+            [
+                None,
+                Some(OvenTempState::HeatingUp),
+                None,
+                Some(OvenTempState::AtTemp),
+                Some(OvenTempState::CoolingDown),
+                Some(OvenTempState::Off),
+            ]
+        );
     }
 }
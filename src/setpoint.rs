@@ -0,0 +1,154 @@
+//! Working out what the oven's dial is really set to, from how its thermostat cycles.
+//!
+//! The thermostat holds the oven in a band around the set point, so the middle of each cycle's band
+//! is a measurement of it. We average the last few, and trust the answer more the more cycles we've
+//! seen and the better they agree.
+
+use crate::cycle::CycleStats;
+use crate::temperature::Temperature;
+
+/// Number of cycles the estimate is averaged over
+pub const SET_POINT_CYCLES: usize = 4;
+/// Cycle centers spread out this much (standard deviation, C) means the estimate is worthless
+const MAX_SET_POINT_SPREAD: f32 = 5.;
+
+/// Our best guess at the set point
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SetPointEstimate {
+    /// The temperature the oven is holding
+    pub set_point: Temperature,
+    /// How much we trust `set_point`, from 0 to 1
+    pub confidence: f32,
+}
+
+/// Keeps a running estimate of the set point from completed thermostat cycles
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SetPointEstimator {
+    /// Center of each cycle's band, C
+    centers: [f32; SET_POINT_CYCLES],
+    len: usize,
+    next: usize,
+}
+
+impl SetPointEstimator {
+    /// Creates an estimator that hasn't seen any cycles
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            centers: [0.; SET_POINT_CYCLES],
+            len: 0,
+            next: 0,
+        }
+    }
+
+    /// Adds a completed cycle, pushing out the oldest once we have `SET_POINT_CYCLES`
+    pub fn push(&mut self, cycle: &CycleStats) {
+        self.centers[self.next] = cycle.center.celsius();
+        self.next = (self.next + 1) % SET_POINT_CYCLES;
+        self.len = (self.len + 1).min(SET_POINT_CYCLES);
+    }
+
+    /// Forgets every cycle, for when the dial may have been turned
+    pub fn reset(&mut self) {
+        self.len = 0;
+        self.next = 0;
+    }
+
+    /// The current estimate, or `None` until we've seen a cycle
+    #[must_use]
+    pub fn estimate(&self) -> Option<SetPointEstimate> {
+        if self.len == 0 {
+            return None;
+        }
+        let centers = &self.centers[..self.len];
+        let n = self.len as f32;
+        let mean = centers.iter().sum::<f32>() / n;
+        let variance = centers
+            .iter()
+            .map(|center| (center - mean) * (center - mean))
+            .sum::<f32>()
+            / n;
+
+        let agreement = (1. - libm::sqrtf(variance) / MAX_SET_POINT_SPREAD).max(0.);
+        Some(SetPointEstimate {
+            set_point: Temperature::from_celsius(mean),
+            confidence: agreement * n / SET_POINT_CYCLES as f32,
+        })
+    }
+}
+
+impl Default for SetPointEstimator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn cycle(center: f32) -> CycleStats {
+        CycleStats {
+            mean: Temperature::from_celsius(center - 1.),
+            center: Temperature::from_celsius(center),
+            swing: 20.,
+            period_ms: 150_000,
+            duty_cycle: 0.4,
+        }
+    }
+
+    #[test]
+    fn needs_a_cycle() {
+        let mut estimator = SetPointEstimator::new();
+        assert_eq!(estimator.estimate(), None);
+        estimator.push(&cycle(177.));
+        estimator.reset();
+        assert_eq!(estimator.estimate(), None);
+    }
+
+    #[test]
+    fn confidence_grows_with_cycles() {
+        let mut estimator = SetPointEstimator::new();
+        let mut confidence = 0.;
+        for _ in 0..SET_POINT_CYCLES {
+            estimator.push(&cycle(177.));
+            let estimate = estimator.estimate().unwrap();
+            assert_eq!(estimate.set_point, Temperature::from_celsius(177.));
+            assert!(estimate.confidence > confidence);
+            confidence = estimate.confidence;
+        }
+        assert_eq!(confidence, 1.);
+    }
+
+    #[test]
+    fn disagreeing_cycles_lower_confidence() {
+        let mut estimator = SetPointEstimator::new();
+        for center in [175., 179., 175., 179.] {
+            estimator.push(&cycle(center));
+        }
+        let estimate = estimator.estimate().unwrap();
+        assert_eq!(estimate.set_point, Temperature::from_celsius(177.));
+        assert!((estimate.confidence - 0.6).abs() < 0.01);
+
+        // Wildly different cycles mean we have no idea
+        for center in [150., 200., 150., 200.] {
+            estimator.push(&cycle(center));
+        }
+        assert_eq!(estimator.estimate().unwrap().confidence, 0.);
+    }
+
+    #[test]
+    fn follows_the_dial() {
+        let mut estimator = SetPointEstimator::new();
+        for _ in 0..SET_POINT_CYCLES {
+            estimator.push(&cycle(177.));
+        }
+        // Turned up to 400F: the old cycles age out
+        for _ in 0..SET_POINT_CYCLES {
+            estimator.push(&cycle(204.));
+        }
+        let estimate = estimator.estimate().unwrap();
+        assert_eq!(estimate.set_point, Temperature::from_celsius(204.));
+        assert_eq!(estimate.confidence, 1.);
+    }
+}
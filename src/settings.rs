@@ -0,0 +1,260 @@
+//! The settings that survive a reset, and how they're laid out in a stored record.
+//!
+//! Each layout has a version. Records saved by older firmware are migrated on load, taking what
+//! they have and defaulting the rest, so updating the firmware doesn't lose the user's settings.
+
+use crate::app::AppConfig;
+use crate::calibration::Calibration;
+use crate::menu::MAX_BRIGHTNESS;
+use crate::oventemp::OvenTempConfig;
+use crate::storage::{Flash, RecordStore, StorageError, MAX_PAYLOAD};
+use crate::temperature::TemperatureUnit;
+
+/// The layout `encode` writes
+pub const SETTINGS_VERSION: u8 = 2;
+/// The calibration alone, as the four words it was kept in RAM as before settings moved to flash
+pub const CALIBRATION_WORDS_VERSION: u8 = 1;
+
+/// Bytes in a version 2 record
+const SETTINGS_LEN: usize = 24;
+const FLAG_SHOW_UNIT: u8 = 1 << 0;
+const FLAG_SHOW_COOK_TIME: u8 = 1 << 1;
+/// Set when the brightness doesn't follow the ambient light, so records from before it could
+/// keep the default
+const FLAG_MANUAL_BRIGHTNESS: u8 = 1 << 2;
+
+/// Packs the persisted parts of `config` into the current layout
+///
+/// # Returns
+/// the buffer, and how much of it was used
+#[must_use]
+pub fn encode(config: &AppConfig) -> ([u8; MAX_PAYLOAD], usize) {
+    let mut buf = [0; MAX_PAYLOAD];
+    let mut flags = 0;
+    if config.show_unit {
+        flags |= FLAG_SHOW_UNIT;
+    }
+    if config.show_cook_time {
+        flags |= FLAG_SHOW_COOK_TIME;
+    }
+    if !config.auto_brightness {
+        flags |= FLAG_MANUAL_BRIGHTNESS;
+    }
+    let oven = config.oven;
+
+    buf[0..4].copy_from_slice(&config.calibration.gain().to_le_bytes());
+    buf[4..8].copy_from_slice(&config.calibration.offset().to_le_bytes());
+    buf[8] = encode_unit(config.unit);
+    buf[9] = flags;
+    buf[10] = config.brightness;
+    buf[11] = encode_unit(oven.unit());
+    buf[12..16].copy_from_slice(&oven.on_threshold().to_le_bytes());
+    buf[16..20].copy_from_slice(&oven.off_threshold().to_le_bytes());
+    buf[20..24].copy_from_slice(&oven.hysteresis().to_le_bytes());
+    (buf, SETTINGS_LEN)
+}
+
+/// Unpacks settings saved in any layout we know, on top of `base`
+///
+/// # Arguments
+/// * `version`: The layout `payload` is in
+/// * `payload`: The saved settings
+/// * `base`: Where settings the layout doesn't have come from
+///
+/// # Returns
+/// the settings, or `None` if the layout is unknown or the payload doesn't fit it
+#[must_use]
+pub fn decode(version: u8, payload: &[u8], base: AppConfig) -> Option<AppConfig> {
+    match version {
+        CALIBRATION_WORDS_VERSION => {
+            if payload.len() != 16 {
+                return None;
+            }
+            let mut words = [0; 4];
+            for (word, bytes) in words.iter_mut().zip(payload.chunks_exact(4)) {
+                *word = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+            }
+            Some(AppConfig {
+                calibration: Calibration::from_words(&words)?,
+                ..base
+            })
+        }
+        SETTINGS_VERSION => {
+            if payload.len() < SETTINGS_LEN {
+                return None;
+            }
+            let float = |at: usize| {
+                f32::from_le_bytes([
+                    payload[at],
+                    payload[at + 1],
+                    payload[at + 2],
+                    payload[at + 3],
+                ])
+            };
+            // Thresholds that don't make sense fall back to the defaults, rather than losing
+            // everything else
+            let oven =
+                OvenTempConfig::new(decode_unit(payload[11])?, float(12), float(16), float(20))
+                    .unwrap_or(base.oven);
+            Some(AppConfig {
+                calibration: Calibration::new(float(0), float(4)),
+                unit: decode_unit(payload[8])?,
+                show_unit: payload[9] & FLAG_SHOW_UNIT != 0,
+                show_cook_time: payload[9] & FLAG_SHOW_COOK_TIME != 0,
+                brightness: payload[10].min(MAX_BRIGHTNESS),
+                auto_brightness: payload[9] & FLAG_MANUAL_BRIGHTNESS == 0,
+                oven,
+                ..base
+            })
+        }
+        _ => None,
+    }
+}
+
+/// Loads the newest settings from `store`, on top of `base`
+///
+/// # Returns
+/// the settings, or `None` if there aren't any we can read
+pub fn load<F: Flash>(
+    store: &mut RecordStore<F>,
+    base: AppConfig,
+) -> Result<Option<AppConfig>, F::Error> {
+    Ok(store
+        .load()?
+        .and_then(|record| decode(record.version, record.payload(), base)))
+}
+
+/// Saves the persisted parts of `config` to `store`, in the current layout
+pub fn save<F: Flash>(
+    store: &mut RecordStore<F>,
+    config: &AppConfig,
+) -> Result<(), StorageError<F::Error>> {
+    let (buf, len) = encode(config);
+    store.save(SETTINGS_VERSION, &buf[..len])
+}
+
+fn encode_unit(unit: TemperatureUnit) -> u8 {
+    match unit {
+        TemperatureUnit::Celsius => 0,
+        TemperatureUnit::Fahrenheit => 1,
+    }
+}
+
+fn decode_unit(byte: u8) -> Option<TemperatureUnit> {
+    match byte {
+        0 => Some(TemperatureUnit::Celsius),
+        1 => Some(TemperatureUnit::Fahrenheit),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::storage::RamFlash;
+
+    fn custom() -> AppConfig {
+        AppConfig {
+            unit: TemperatureUnit::Celsius,
+            show_unit: true,
+            oven: OvenTempConfig::new(TemperatureUnit::Celsius, 50., 60., 2.).unwrap(),
+            calibration: Calibration::new(1.05, -2.5),
+            show_cook_time: true,
+            brightness: 9,
+            auto_brightness: false,
+            ..AppConfig::default()
+        }
+    }
+
+    #[test]
+    fn round_trips() {
+        let config = custom();
+        let (buf, len) = encode(&config);
+        assert_eq!(
+            decode(SETTINGS_VERSION, &buf[..len], AppConfig::default()),
+            Some(config)
+        );
+
+        // Records from before automatic brightness leave the flag clear, so they keep it
+        let (mut buf, len) = encode(&AppConfig::default());
+        assert_eq!(buf[9] & FLAG_MANUAL_BRIGHTNESS, 0);
+        buf[9] |= FLAG_MANUAL_BRIGHTNESS;
+        let config = decode(SETTINGS_VERSION, &buf[..len], AppConfig::default()).unwrap();
+        assert!(!config.auto_brightness);
+    }
+
+    #[test]
+    fn persists_across_mounts() {
+        let mut store = RecordStore::mount(RamFlash::<1024>::new()).unwrap();
+        assert_eq!(load(&mut store, AppConfig::default()).unwrap(), None);
+        save(&mut store, &custom()).unwrap();
+
+        let mut store = RecordStore::mount(store.free()).unwrap();
+        assert_eq!(
+            load(&mut store, AppConfig::default()).unwrap(),
+            Some(custom())
+        );
+    }
+
+    #[test]
+    fn migrates_calibration_words() {
+        let calibration = Calibration::new(0.98, 3.);
+        let mut payload = [0; 16];
+        for (bytes, word) in payload.chunks_exact_mut(4).zip(calibration.to_words()) {
+            bytes.copy_from_slice(&word.to_le_bytes());
+        }
+        let config = decode(CALIBRATION_WORDS_VERSION, &payload, AppConfig::default()).unwrap();
+        assert_eq!(config.calibration, calibration);
+        assert_eq!(
+            config,
+            AppConfig {
+                calibration,
+                ..AppConfig::default()
+            }
+        );
+
+        // Garbage, as RAM is after power on, isn't a calibration
+        assert_eq!(
+            decode(CALIBRATION_WORDS_VERSION, &[0xA5; 16], AppConfig::default()),
+            None
+        );
+    }
+
+    #[test]
+    fn rejects_what_it_cannot_read() {
+        let (buf, len) = encode(&custom());
+        // From newer firmware
+        assert_eq!(
+            decode(SETTINGS_VERSION + 1, &buf[..len], AppConfig::default()),
+            None
+        );
+        // Cut short
+        assert_eq!(
+            decode(SETTINGS_VERSION, &buf[..len - 1], AppConfig::default()),
+            None
+        );
+        // A unit that doesn't exist
+        let mut bad = buf;
+        bad[8] = 7;
+        assert_eq!(
+            decode(SETTINGS_VERSION, &bad[..len], AppConfig::default()),
+            None
+        );
+    }
+
+    #[test]
+    fn sanitizes_values() {
+        let (mut buf, len) = encode(&custom());
+        buf[10] = 200;
+        // Thresholds the wrong way round
+        buf[12..16].copy_from_slice(&500_f32.to_le_bytes());
+        buf[16..20].copy_from_slice(&100_f32.to_le_bytes());
+        // A gain that's surely a typo
+        buf[0..4].copy_from_slice(&5_f32.to_le_bytes());
+
+        let config = decode(SETTINGS_VERSION, &buf[..len], AppConfig::default()).unwrap();
+        assert_eq!(config.brightness, MAX_BRIGHTNESS);
+        assert_eq!(config.oven, AppConfig::default().oven);
+        assert_eq!(config.calibration.gain(), crate::calibration::MAX_GAIN);
+    }
+}
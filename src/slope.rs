@@ -0,0 +1,154 @@
+//! Rate of change of the temperature over a sliding window of samples.
+
+/// Tracks how fast a value is changing, from a least squares fit over the last `N` samples
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SlopeTracker<const N: usize> {
+    /// `(time_ms, value)`, oldest first once `len` wraps
+    samples: [(u64, f32); N],
+    len: usize,
+    next: usize,
+}
+
+impl<const N: usize> SlopeTracker<N> {
+    /// Creates a tracker that hasn't seen any samples
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            samples: [(0, 0.); N],
+            len: 0,
+            next: 0,
+        }
+    }
+
+    /// Adds a sample taken at the given time (ms)
+    pub fn push(&mut self, time_ms: u64, value: f32) {
+        if N == 0 {
+            return;
+        }
+        self.samples[self.next] = (time_ms, value);
+        self.next = (self.next + 1) % N;
+        self.len = (self.len + 1).min(N);
+    }
+
+    /// Forgets every sample
+    pub fn reset(&mut self) {
+        self.len = 0;
+        self.next = 0;
+    }
+
+    /// Whether the window has filled up
+    #[must_use]
+    pub fn is_full(&self) -> bool {
+        self.len == N
+    }
+
+    /// The oldest value still in the window
+    #[must_use]
+    pub fn oldest(&self) -> Option<f32> {
+        match self.len {
+            0 => None,
+            len if len < N => Some(self.samples[0].1),
+            _ => Some(self.samples[self.next].1),
+        }
+    }
+
+    /// The slope over the window, in units per minute
+    ///
+    /// # Returns
+    /// the slope, or `None` until the window has filled up (or if every sample is at the same time)
+    #[must_use]
+    pub fn per_minute(&self) -> Option<f32> {
+        if !self.is_full() || N < 2 {
+            return None;
+        }
+        let newest = self.samples[(self.next + N - 1) % N].0;
+        // Minutes before the newest sample, small enough for f32 to keep precise
+        let minutes = |time_ms: u64| (time_ms as i64 - newest as i64) as f32 / 60_000.;
+
+        let n = N as f32;
+        let (sum_x, sum_y) = self
+            .samples
+            .iter()
+            .fold((0., 0.), |(x, y), &(t, v)| (x + minutes(t), y + v));
+        let (mean_x, mean_y) = (sum_x / n, sum_y / n);
+        let (covariance, variance) = self.samples.iter().fold((0., 0.), |(cov, var), &(t, v)| {
+            let dx = minutes(t) - mean_x;
+            (cov + dx * (v - mean_y), var + dx * dx)
+        });
+        if variance > 0. {
+            Some(covariance / variance)
+        } else {
+            None
+        }
+    }
+}
+
+impl<const N: usize> Default for SlopeTracker<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn needs_a_full_window() {
+        let mut slope = SlopeTracker::<4>::new();
+        for n in 0..3 {
+            slope.push(n * 1_000, 20.);
+            assert_eq!(slope.per_minute(), None);
+        }
+        slope.push(3_000, 20.);
+        assert_eq!(slope.per_minute(), Some(0.));
+        slope.reset();
+        assert_eq!(slope.per_minute(), None);
+    }
+
+    #[test]
+    fn oldest_sample() {
+        let mut slope = SlopeTracker::<3>::new();
+        assert_eq!(slope.oldest(), None);
+        for value in [1., 2., 3.] {
+            slope.push(0, value);
+            assert_eq!(slope.oldest(), Some(1.));
+        }
+        slope.push(0, 4.);
+        assert_eq!(slope.oldest(), Some(2.));
+        slope.reset();
+        assert_eq!(slope.oldest(), None);
+    }
+
+    #[test]
+    fn fits_a_line() {
+        let mut slope = SlopeTracker::<10>::new();
+        // 0.5 degrees a second, with uneven sample spacing and some wiggle
+        for n in 0..30_u64 {
+            let time_ms = n * 1_000 + (n % 3) * 150;
+            let wiggle = if n % 2 == 0 { 0.1 } else { -0.1 };
+            slope.push(time_ms, 100. + time_ms as f32 / 2_000. + wiggle);
+        }
+        let per_minute = slope.per_minute().unwrap();
+        assert!((per_minute - 30.).abs() < 0.5, "{}", per_minute);
+    }
+
+    #[test]
+    fn cooling_is_negative() {
+        let mut slope = SlopeTracker::<5>::new();
+        for n in 0..5_u64 {
+            slope.push(1_000_000_000 + n * 2_000, 200. - n as f32);
+        }
+        let per_minute = slope.per_minute().unwrap();
+        assert!((per_minute + 30.).abs() < 0.01, "{}", per_minute);
+    }
+
+    #[test]
+    fn same_time_has_no_slope() {
+        let mut slope = SlopeTracker::<3>::new();
+        for value in [1., 2., 3.] {
+            slope.push(5_000, value);
+        }
+        assert_eq!(slope.per_minute(), None);
+    }
+}
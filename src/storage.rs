@@ -0,0 +1,495 @@
+//! A small store of versioned records in flash, for keeping settings across resets.
+//!
+//! Flash has to be erased a block at a time, and wears out after enough erases, so records are
+//! never overwritten in place. Each save goes in the next free slot of a ring spread over the whole
+//! region, erasing a block only when the ring moves into it, so every block wears at the same rate.
+//! Loading picks the valid record with the highest sequence number.
+//!
+//! A block is only ever erased once the ring has moved past every record in it, and a record is
+//! only ever written to a blank slot, so losing power part way through either leaves the previous
+//! record intact. A half written record fails its CRC, and is skipped over by the next save.
+
+/// Bytes in a record slot: one SAMD21 flash page
+pub const RECORD_SIZE: usize = 64;
+/// Largest payload a record can hold
+pub const MAX_PAYLOAD: usize = RECORD_SIZE - HEADER_SIZE - CRC_SIZE;
+
+/// Marks a slot as holding a record, rather than being erased or garbage
+const RECORD_MAGIC: u16 = 0x5E77;
+/// Magic, version, length and sequence number
+const HEADER_SIZE: usize = 8;
+const CRC_SIZE: usize = 4;
+/// What flash reads as once erased
+const ERASED: u8 = 0xFF;
+
+/// A region of flash
+///
+/// Erasing sets every byte in a block to `0xFF`, and writing can only clear bits, so anything
+/// written over has to be erased first. Offsets are from the start of the region.
+pub trait Flash {
+    type Error;
+
+    /// Bytes erased at once
+    const ERASE_SIZE: usize;
+    /// Bytes written at once. Writes are always a multiple of this, and aligned to it.
+    const WRITE_SIZE: usize;
+
+    /// Size of the region in bytes, a multiple of `ERASE_SIZE`
+    fn capacity(&self) -> usize;
+
+    /// Reads `buf.len()` bytes starting at `offset`
+    fn read(&mut self, offset: usize, buf: &mut [u8]) -> Result<(), Self::Error>;
+
+    /// Erases the block starting at `offset`, which must be a multiple of `ERASE_SIZE`
+    fn erase(&mut self, offset: usize) -> Result<(), Self::Error>;
+
+    /// Writes `data` starting at `offset`, both a multiple of `WRITE_SIZE`
+    fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), Self::Error>;
+}
+
+/// Reasons a record can't be saved
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum StorageError<E> {
+    /// The flash reported an error
+    Flash(E),
+    /// The payload is longer than `MAX_PAYLOAD`
+    TooLarge,
+    /// No slot would hold the record. The flash is worn out.
+    Full,
+}
+
+impl<E> From<E> for StorageError<E> {
+    fn from(err: E) -> Self {
+        StorageError::Flash(err)
+    }
+}
+
+/// A record read back from the store
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Record {
+    /// Version of the payload's layout, for its owner to migrate from
+    pub version: u8,
+    len: u8,
+    payload: [u8; MAX_PAYLOAD],
+}
+
+impl Record {
+    /// What was saved
+    #[must_use]
+    pub fn payload(&self) -> &[u8] {
+        &self.payload[..usize::from(self.len)]
+    }
+}
+
+/// Versioned records kept in a ring of flash slots
+pub struct RecordStore<F> {
+    flash: F,
+    /// Slot and sequence number of the newest valid record
+    latest: Option<(usize, u32)>,
+    /// Slot the next record goes in, if it's blank
+    next: usize,
+}
+
+impl<F: Flash> RecordStore<F> {
+    /// Opens the store, scanning the flash for the newest record
+    ///
+    /// # Arguments
+    /// * `flash`: The region the store owns, at least two erase blocks so there's always one
+    ///   holding the newest record while the other's erased
+    pub fn mount(flash: F) -> Result<Self, F::Error> {
+        debug_assert!(flash.capacity() >= 2 * F::ERASE_SIZE);
+        debug_assert!(F::ERASE_SIZE.is_multiple_of(RECORD_SIZE));
+        debug_assert!(RECORD_SIZE.is_multiple_of(F::WRITE_SIZE));
+
+        let mut store = Self {
+            flash,
+            latest: None,
+            next: 0,
+        };
+        let mut slot = [0; RECORD_SIZE];
+        for index in 0..store.slots() {
+            store.flash.read(index * RECORD_SIZE, &mut slot)?;
+            if let Some((sequence, _)) = parse(&slot) {
+                // Sequence numbers only go up, so the newest is the largest
+                if store.latest.is_none_or(|(_, newest)| sequence > newest) {
+                    store.latest = Some((index, sequence));
+                }
+            }
+        }
+        store.next = store.latest.map_or(0, |(index, _)| store.after(index));
+        Ok(store)
+    }
+
+    /// Reads the newest record, or `None` if nothing's been saved
+    pub fn load(&mut self) -> Result<Option<Record>, F::Error> {
+        let (index, _) = match self.latest {
+            Some(latest) => latest,
+            None => return Ok(None),
+        };
+        let mut slot = [0; RECORD_SIZE];
+        self.flash.read(index * RECORD_SIZE, &mut slot)?;
+        Ok(parse(&slot).map(|(_, record)| record))
+    }
+
+    /// Saves a record, which `load` returns from then on
+    ///
+    /// # Arguments
+    /// * `version`: Version of the payload's layout
+    /// * `payload`: Up to `MAX_PAYLOAD` bytes
+    pub fn save(&mut self, version: u8, payload: &[u8]) -> Result<(), StorageError<F::Error>> {
+        if payload.len() > MAX_PAYLOAD {
+            return Err(StorageError::TooLarge);
+        }
+        let sequence = self
+            .latest
+            .map_or(0, |(_, sequence)| sequence.wrapping_add(1));
+        let mut record = [ERASED; RECORD_SIZE];
+        record[..2].copy_from_slice(&RECORD_MAGIC.to_le_bytes());
+        record[2] = version;
+        record[3] = payload.len() as u8;
+        record[4..HEADER_SIZE].copy_from_slice(&sequence.to_le_bytes());
+        record[HEADER_SIZE..HEADER_SIZE + payload.len()].copy_from_slice(payload);
+        let crc = crc32(&record[..RECORD_SIZE - CRC_SIZE]);
+        record[RECORD_SIZE - CRC_SIZE..].copy_from_slice(&crc.to_le_bytes());
+
+        // Every slot once, in case some have gone bad
+        for _ in 0..self.slots() {
+            let index = self.next;
+            let offset = index * RECORD_SIZE;
+            if offset.is_multiple_of(F::ERASE_SIZE) {
+                // Moving into the next block, which only holds records older than the newest
+                if !self.is_blank(offset, F::ERASE_SIZE)? {
+                    self.flash.erase(offset)?;
+                }
+            } else if !self.is_blank(offset, RECORD_SIZE)? {
+                // Left half written by a power loss
+                self.next = self.after(index);
+                continue;
+            }
+
+            self.flash.write(offset, &record)?;
+            self.next = self.after(index);
+            let mut written = [0; RECORD_SIZE];
+            self.flash.read(offset, &mut written)?;
+            if written == record {
+                self.latest = Some((index, sequence));
+                return Ok(());
+            }
+        }
+        Err(StorageError::Full)
+    }
+
+    /// Gives the flash back
+    pub fn free(self) -> F {
+        self.flash
+    }
+
+    fn slots(&self) -> usize {
+        self.flash.capacity() / RECORD_SIZE
+    }
+
+    fn after(&self, index: usize) -> usize {
+        (index + 1) % self.slots()
+    }
+
+    /// Whether `len` bytes from `offset` are all erased
+    fn is_blank(&mut self, offset: usize, len: usize) -> Result<bool, F::Error> {
+        let mut chunk = [0; RECORD_SIZE];
+        for start in (offset..offset + len).step_by(RECORD_SIZE) {
+            self.flash.read(start, &mut chunk)?;
+            if chunk.iter().any(|&byte| byte != ERASED) {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+}
+
+/// Unpacks a slot, if it holds an intact record
+fn parse(slot: &[u8; RECORD_SIZE]) -> Option<(u32, Record)> {
+    let magic = u16::from_le_bytes([slot[0], slot[1]]);
+    let len = slot[3];
+    let body = &slot[..RECORD_SIZE - CRC_SIZE];
+    let crc = &slot[RECORD_SIZE - CRC_SIZE..];
+    if magic != RECORD_MAGIC || usize::from(len) > MAX_PAYLOAD || crc32(body).to_le_bytes() != crc {
+        return None;
+    }
+
+    let mut payload = [0; MAX_PAYLOAD];
+    payload.copy_from_slice(&slot[HEADER_SIZE..HEADER_SIZE + MAX_PAYLOAD]);
+    let sequence = u32::from_le_bytes([slot[4], slot[5], slot[6], slot[7]]);
+    Some((
+        sequence,
+        Record {
+            version: slot[2],
+            len,
+            payload,
+        },
+    ))
+}
+
+/// CRC-32 (IEEE), bit at a time: records are small, and a table would cost 1k of flash
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0_u32;
+    for &byte in data {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+/// Most erase blocks a [`RamFlash`] keeps erase counts for
+const RAM_FLASH_MAX_BLOCKS: usize = 32;
+
+/// Error returned by [`RamFlash`] once its power's been cut
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct PowerLoss;
+
+/// Flash simulated in RAM, laid out like the SAMD21's, that can lose power part way through an
+/// operation
+#[derive(Clone, Debug)]
+pub struct RamFlash<const SIZE: usize> {
+    data: [u8; SIZE],
+    /// Bytes that can be changed before the power goes out, if it's going to
+    power_budget: Option<usize>,
+    erases: [u32; RAM_FLASH_MAX_BLOCKS],
+}
+
+impl<const SIZE: usize> RamFlash<SIZE> {
+    /// Creates flash that's been erased, as it comes from the factory
+    #[must_use]
+    pub fn new() -> Self {
+        debug_assert!(SIZE / Self::ERASE_SIZE <= RAM_FLASH_MAX_BLOCKS);
+        Self {
+            data: [ERASED; SIZE],
+            power_budget: None,
+            erases: [0; RAM_FLASH_MAX_BLOCKS],
+        }
+    }
+
+    /// Cuts the power once this many more bytes have been written or erased. Writes and erases
+    /// fail from then on, until `restore_power`.
+    pub fn cut_power_after(&mut self, bytes: usize) {
+        self.power_budget = Some(bytes);
+    }
+
+    /// Powers back up, as after a reset
+    pub fn restore_power(&mut self) {
+        self.power_budget = None;
+    }
+
+    /// How many times the block starting at `offset` has been erased
+    #[must_use]
+    pub fn erase_count(&self, offset: usize) -> u32 {
+        self.erases[offset / Self::ERASE_SIZE]
+    }
+
+    /// The raw contents, for corrupting
+    pub fn data_mut(&mut self) -> &mut [u8; SIZE] {
+        &mut self.data
+    }
+
+    /// Changes one byte, if there's still power to
+    fn program(&mut self, offset: usize, value: impl Fn(u8) -> u8) -> Result<(), PowerLoss> {
+        match &mut self.power_budget {
+            Some(0) => return Err(PowerLoss),
+            Some(budget) => *budget -= 1,
+            None => {}
+        }
+        self.data[offset] = value(self.data[offset]);
+        Ok(())
+    }
+}
+
+impl<const SIZE: usize> Default for RamFlash<SIZE> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const SIZE: usize> Flash for RamFlash<SIZE> {
+    type Error = PowerLoss;
+
+    const ERASE_SIZE: usize = 256;
+    const WRITE_SIZE: usize = 64;
+
+    fn capacity(&self) -> usize {
+        SIZE
+    }
+
+    fn read(&mut self, offset: usize, buf: &mut [u8]) -> Result<(), PowerLoss> {
+        buf.copy_from_slice(&self.data[offset..offset + buf.len()]);
+        Ok(())
+    }
+
+    fn erase(&mut self, offset: usize) -> Result<(), PowerLoss> {
+        debug_assert!(offset.is_multiple_of(Self::ERASE_SIZE));
+        self.erases[offset / Self::ERASE_SIZE] += 1;
+        for n in offset..offset + Self::ERASE_SIZE {
+            self.program(n, |_| ERASED)?;
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), PowerLoss> {
+        debug_assert!(offset.is_multiple_of(Self::WRITE_SIZE));
+        debug_assert!(data.len().is_multiple_of(Self::WRITE_SIZE));
+        for (n, &byte) in data.iter().enumerate() {
+            // Writing can only clear bits
+            self.program(offset + n, |old| old & byte)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Four blocks of four slots
+    type TestFlash = RamFlash<1024>;
+
+    fn payload(store: &mut RecordStore<TestFlash>) -> Option<(u8, u32)> {
+        store.load().unwrap().map(|record| {
+            let bytes = record.payload();
+            (
+                record.version,
+                u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+            )
+        })
+    }
+
+    /// Saves `value` as a version 1 record
+    fn save(store: &mut RecordStore<TestFlash>, value: u32) -> Result<(), StorageError<PowerLoss>> {
+        store.save(1, &value.to_le_bytes())
+    }
+
+    /// Resets, keeping what's in flash
+    fn remount(store: RecordStore<TestFlash>) -> RecordStore<TestFlash> {
+        let mut flash = store.free();
+        flash.restore_power();
+        RecordStore::mount(flash).unwrap()
+    }
+
+    #[test]
+    fn crc() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32(&[]), 0);
+    }
+
+    #[test]
+    fn starts_empty() {
+        let mut store = RecordStore::mount(TestFlash::new()).unwrap();
+        assert_eq!(payload(&mut store), None);
+    }
+
+    #[test]
+    fn keeps_the_newest_record() {
+        let mut store = RecordStore::mount(TestFlash::new()).unwrap();
+        for value in 0..50 {
+            save(&mut store, value).unwrap();
+            assert_eq!(payload(&mut store), Some((1, value)));
+        }
+        store.save(2, &[7, 0, 0, 0, 9]).unwrap();
+        let mut store = remount(store);
+        let record = store.load().unwrap().unwrap();
+        assert_eq!(record.version, 2);
+        assert_eq!(record.payload(), [7, 0, 0, 0, 9]);
+    }
+
+    #[test]
+    fn levels_wear() {
+        let mut store = RecordStore::mount(TestFlash::new()).unwrap();
+        for value in 0..160 {
+            save(&mut store, value).unwrap();
+        }
+        // 160 records over 16 slots goes round the ring 10 times, erasing each block as it goes.
+        // The first lap found them blank.
+        let flash = store.free();
+        for block in 0..4 {
+            assert_eq!(flash.erase_count(block * 256), 9);
+        }
+    }
+
+    #[test]
+    fn rejects_oversized_payloads() {
+        let mut store = RecordStore::mount(TestFlash::new()).unwrap();
+        assert_eq!(
+            store.save(1, &[0; MAX_PAYLOAD + 1]),
+            Err(StorageError::TooLarge)
+        );
+        store.save(1, &[0xAB; MAX_PAYLOAD]).unwrap();
+        assert_eq!(
+            store.load().unwrap().unwrap().payload(),
+            [0xAB; MAX_PAYLOAD]
+        );
+    }
+
+    #[test]
+    fn survives_power_loss_while_writing() {
+        // Cut the power at every point of a save: the old record or the new one comes back,
+        // never garbage, and the store carries on afterwards
+        for cut_after in 0..RECORD_SIZE {
+            let mut store = RecordStore::mount(TestFlash::new()).unwrap();
+            save(&mut store, 1).unwrap();
+            save(&mut store, 2).unwrap();
+
+            let mut flash = store.free();
+            flash.cut_power_after(cut_after);
+            let mut store = RecordStore::mount(flash).unwrap();
+            assert!(save(&mut store, 3).is_err());
+            let mut store = remount(store);
+            assert_eq!(payload(&mut store), Some((1, 2)), "cut after {}", cut_after);
+
+            save(&mut store, 4).unwrap();
+            let mut store = remount(store);
+            assert_eq!(payload(&mut store), Some((1, 4)));
+        }
+    }
+
+    #[test]
+    fn survives_power_loss_while_erasing() {
+        for cut_after in [0, 1, 100, 255] {
+            let mut store = RecordStore::mount(TestFlash::new()).unwrap();
+            // Fill the ring, so the next save has to erase the first block
+            for value in 0..16 {
+                save(&mut store, value).unwrap();
+            }
+            let mut flash = store.free();
+            flash.cut_power_after(cut_after);
+            let mut store = RecordStore::mount(flash).unwrap();
+            assert!(save(&mut store, 16).is_err());
+            let mut store = remount(store);
+            assert_eq!(
+                payload(&mut store),
+                Some((1, 15)),
+                "cut after {}",
+                cut_after
+            );
+
+            save(&mut store, 17).unwrap();
+            let mut store = remount(store);
+            assert_eq!(payload(&mut store), Some((1, 17)));
+        }
+    }
+
+    #[test]
+    fn skips_corrupt_records() {
+        let mut store = RecordStore::mount(TestFlash::new()).unwrap();
+        save(&mut store, 1).unwrap();
+        save(&mut store, 2).unwrap();
+        let mut flash = store.free();
+        // Flip a bit in the newest record's payload
+        flash.data_mut()[RECORD_SIZE + HEADER_SIZE] ^= 1;
+        let mut store = RecordStore::mount(flash).unwrap();
+        assert_eq!(payload(&mut store), Some((1, 1)));
+
+        // And the next save goes after it, rather than on top of it
+        save(&mut store, 3).unwrap();
+        let mut store = remount(store);
+        assert_eq!(payload(&mut store), Some((1, 3)));
+    }
+}
@@ -0,0 +1,127 @@
+//! Strongly-typed temperatures, so Celsius and Fahrenheit readings can't get mixed up.
+
+/// The unit a temperature is displayed or configured in
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum TemperatureUnit {
+    /// Degrees Celsius
+    Celsius,
+    /// Degrees Fahrenheit
+    #[default]
+    Fahrenheit,
+}
+
+impl TemperatureUnit {
+    /// The character used to label a temperature in this unit
+    #[must_use]
+    pub const fn symbol(self) -> char {
+        match self {
+            Self::Celsius => 'C',
+            Self::Fahrenheit => 'F',
+        }
+    }
+
+    /// Converts a temperature difference (not an absolute temperature) from Celsius to this unit
+    #[must_use]
+    pub fn delta_from_celsius(self, delta: f32) -> f32 {
+        match self {
+            Self::Celsius => delta,
+            Self::Fahrenheit => delta * (9. / 5.),
+        }
+    }
+
+    /// Converts a temperature difference (not an absolute temperature) in this unit to Celsius
+    #[must_use]
+    pub fn delta_to_celsius(self, delta: f32) -> f32 {
+        match self {
+            Self::Celsius => delta,
+            Self::Fahrenheit => delta * (5. / 9.),
+        }
+    }
+}
+
+/// An absolute temperature. Stored internally in Celsius.
+#[derive(Copy, Clone, Debug, PartialEq, PartialOrd)]
+pub struct Temperature(f32);
+
+impl Temperature {
+    /// Creates a temperature from a value in degrees Celsius
+    #[must_use]
+    pub const fn from_celsius(celsius: f32) -> Self {
+        Self(celsius)
+    }
+
+    /// Creates a temperature from a value in degrees Fahrenheit
+    #[must_use]
+    pub fn from_fahrenheit(fahrenheit: f32) -> Self {
+        Self((fahrenheit - 32.) * (5. / 9.))
+    }
+
+    /// Creates a temperature from a value in the given unit
+    #[must_use]
+    pub fn from_unit(value: f32, unit: TemperatureUnit) -> Self {
+        match unit {
+            TemperatureUnit::Celsius => Self::from_celsius(value),
+            TemperatureUnit::Fahrenheit => Self::from_fahrenheit(value),
+        }
+    }
+
+    /// The temperature in degrees Celsius
+    #[must_use]
+    pub const fn celsius(self) -> f32 {
+        self.0
+    }
+
+    /// The temperature in degrees Fahrenheit
+    #[must_use]
+    pub fn fahrenheit(self) -> f32 {
+        self.0 * (9. / 5.) + 32.
+    }
+
+    /// The temperature in the given unit
+    #[must_use]
+    pub fn in_unit(self, unit: TemperatureUnit) -> f32 {
+        match unit {
+            TemperatureUnit::Celsius => self.celsius(),
+            TemperatureUnit::Fahrenheit => self.fahrenheit(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn assert_close(a: f32, b: f32) {
+        assert!((a - b).abs() < 0.001, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn conversions() {
+        assert_close(Temperature::from_celsius(100.).fahrenheit(), 212.);
+        assert_close(Temperature::from_fahrenheit(32.).celsius(), 0.);
+        assert_close(Temperature::from_fahrenheit(-40.).celsius(), -40.);
+        assert_close(Temperature::from_fahrenheit(350.).fahrenheit(), 350.);
+
+        let t = Temperature::from_unit(180., TemperatureUnit::Celsius);
+        assert_close(t.in_unit(TemperatureUnit::Fahrenheit), 356.);
+        assert_close(t.in_unit(TemperatureUnit::Celsius), 180.);
+        assert_close(
+            Temperature::from_unit(356., TemperatureUnit::Fahrenheit).celsius(),
+            180.,
+        );
+    }
+
+    #[test]
+    fn deltas() {
+        assert_close(TemperatureUnit::Fahrenheit.delta_from_celsius(10.), 18.);
+        assert_close(TemperatureUnit::Fahrenheit.delta_to_celsius(18.), 10.);
+        assert_close(TemperatureUnit::Celsius.delta_from_celsius(10.), 10.);
+        assert_close(TemperatureUnit::Celsius.delta_to_celsius(10.), 10.);
+    }
+
+    #[test]
+    fn ordering() {
+        assert!(Temperature::from_fahrenheit(213.) > Temperature::from_celsius(100.));
+        assert!(Temperature::from_celsius(0.) < Temperature::from_fahrenheit(33.));
+    }
+}
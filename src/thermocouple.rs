@@ -0,0 +1,511 @@
+//! Type K thermocouple conversions for the AD8495 amplifier.
+//!
+//! The AD8495 assumes the thermocouple is linear (5 mV/C), which drifts by a few degrees at oven
+//! temperatures. Following Analog Devices' AN-1087, we instead work back from the amplifier's
+//! output to the thermocouple's EMF and run that through the NIST ITS-90 type K polynomials.
+
+// Coefficients are copied verbatim from NIST, even where f32 can't hold every digit
+#![allow(clippy::excessive_precision)]
+
+use crate::temperature::Temperature;
+
+/// AD8495 gain
+const AD8495_GAIN: f32 = 122.4;
+/// AD8495 output sensitivity, V/C
+const AD8495_VOLTS_PER_DEGREE: f32 = 0.005;
+/// The Adafruit breakout references the AD8495's output to 1.25v
+const AD8495_REFERENCE_VOLTAGE: f32 = 1.25;
+/// The AD8495 compensates for its own (cold junction) temperature linearly, mV/C
+const AD8495_COLD_JUNCTION_MV_PER_DEGREE: f32 = AD8495_VOLTS_PER_DEGREE * 1000. / AD8495_GAIN;
+/// We don't measure the board's temperature, so assume it's at room temperature
+pub const DEFAULT_COLD_JUNCTION: Temperature = Temperature::from_celsius(25.);
+
+/// NIST type K EMF (mV) from temperature (C), -270C to 0C
+const EMF_BELOW_ZERO: [f32; 11] = [
+    0.0,
+    0.394_501_280_250e-1,
+    0.236_223_735_980e-4,
+    -0.328_589_067_840e-6,
+    -0.499_048_287_770e-8,
+    -0.675_090_591_730e-10,
+    -0.574_103_274_280e-12,
+    -0.310_888_728_940e-14,
+    -0.104_516_093_650e-16,
+    -0.198_892_668_780e-19,
+    -0.163_226_974_860e-22,
+];
+/// NIST type K EMF (mV) from temperature (C), 0C to 1372C
+const EMF_ABOVE_ZERO: [f32; 10] = [
+    -0.176_004_136_860e-1,
+    0.389_212_049_750e-1,
+    0.185_587_700_320e-4,
+    -0.994_575_928_740e-7,
+    0.318_409_457_190e-9,
+    -0.560_728_448_890e-12,
+    0.560_750_590_590e-15,
+    -0.320_207_200_030e-18,
+    0.971_511_471_520e-22,
+    -0.121_047_212_750e-25,
+];
+/// Exponential term of the 0C to 1372C EMF polynomial: `a0 * exp(a1 * (t - a2)^2)`
+const EMF_EXPONENTIAL: [f32; 3] = [
+    0.118_597_600_000,
+    -0.118_343_200_000e-3,
+    0.126_968_600_000e3,
+];
+
+/// NIST type K inverse polynomial, temperature (C) from EMF (mV), -200C to 0C
+const INVERSE_BELOW_ZERO: [f32; 9] = [
+    0.0,
+    2.517_346_2e1,
+    -1.166_287_8,
+    -1.083_363_8,
+    -8.977_354_0e-1,
+    -3.734_237_7e-1,
+    -8.663_264_3e-2,
+    -1.045_059_8e-2,
+    -5.192_057_7e-4,
+];
+/// NIST type K inverse polynomial, temperature (C) from EMF (mV), 0C to 500C
+const INVERSE_LOW: [f32; 10] = [
+    0.0,
+    2.508_355e1,
+    7.860_106e-2,
+    -2.503_131e-1,
+    8.315_270e-2,
+    -1.228_034e-2,
+    9.804_036e-4,
+    -4.413_030e-5,
+    1.057_734e-6,
+    -1.052_755e-8,
+];
+/// NIST type K inverse polynomial, temperature (C) from EMF (mV), 500C to 1372C
+const INVERSE_HIGH: [f32; 7] = [
+    -1.318_058e2,
+    4.830_222e1,
+    -1.646_031,
+    5.464_731e-2,
+    -9.650_715e-4,
+    8.802_193e-6,
+    -3.110_810e-8,
+];
+
+/// EMF (mV) at the boundaries of the inverse polynomials' ranges
+const EMF_MIN: f32 = -5.891;
+const EMF_500C: f32 = 20.644;
+const EMF_MAX: f32 = 54.886;
+
+/// Evaluates a polynomial with the given coefficients (lowest order first)
+fn polynomial(coefficients: &[f32], x: f32) -> f32 {
+    coefficients.iter().rev().fold(0., |acc, c| acc * x + c)
+}
+
+/// The EMF (mV) of a type K thermocouple with its reference junction at 0C
+#[must_use]
+pub fn emf_from_temperature(temp: Temperature) -> f32 {
+    let t = temp.celsius();
+    if t < 0. {
+        polynomial(&EMF_BELOW_ZERO, t)
+    } else {
+        let [a0, a1, a2] = EMF_EXPONENTIAL;
+        polynomial(&EMF_ABOVE_ZERO, t) + a0 * libm::expf(a1 * (t - a2) * (t - a2))
+    }
+}
+
+/// The temperature of a type K thermocouple's measuring junction given its EMF (mV), with its
+/// reference junction at 0C
+///
+/// # Returns
+/// the temperature, or `None` if the EMF is outside of the -200C to 1372C range NIST covers
+#[must_use]
+pub fn temperature_from_emf(emf: f32) -> Option<Temperature> {
+    let coefficients: &[f32] = if !(EMF_MIN..=EMF_MAX).contains(&emf) {
+        return None;
+    } else if emf < 0. {
+        &INVERSE_BELOW_ZERO
+    } else if emf < EMF_500C {
+        &INVERSE_LOW
+    } else {
+        &INVERSE_HIGH
+    };
+    Some(Temperature::from_celsius(polynomial(coefficients, emf)))
+}
+
+/// The AD8495 datasheet's linear conversion from output voltage to temperature
+#[must_use]
+pub fn ad8495_linear(voltage: f32) -> Temperature {
+    Temperature::from_celsius((voltage - AD8495_REFERENCE_VOLTAGE) / AD8495_VOLTS_PER_DEGREE)
+}
+
+/// Converts the AD8495's output voltage to a temperature, correcting for the thermocouple's
+/// nonlinearity
+///
+/// # Arguments
+/// * `voltage`: The AD8495's output voltage
+/// * `cold_junction`: The temperature of the AD8495 itself
+///
+/// # Returns
+/// the corrected temperature, or `None` if it's outside of the range NIST covers
+#[must_use]
+pub fn ad8495_corrected(voltage: f32, cold_junction: Temperature) -> Option<Temperature> {
+    // The AD8495 amplifies the thermocouple's voltage plus its own linear approximation of the
+    // cold junction's EMF. Swap that approximation out for the real thing.
+    let amplified_mv = (voltage - AD8495_REFERENCE_VOLTAGE) * 1000. / AD8495_GAIN;
+    let thermocouple_mv =
+        amplified_mv - AD8495_COLD_JUNCTION_MV_PER_DEGREE * cold_junction.celsius();
+    temperature_from_emf(thermocouple_mv + emf_from_temperature(cold_junction))
+}
+
+/// Converts the AD8495's output voltage to a temperature. Uses the nonlinearity correction with the
+/// cold junction at room temperature, falling back to the linear conversion outside of the
+/// thermocouple's range (a disconnected or shorted probe, generally).
+#[must_use]
+pub fn ad8495_temperature(voltage: f32) -> Temperature {
+    ad8495_corrected(voltage, DEFAULT_COLD_JUNCTION).unwrap_or_else(|| ad8495_linear(voltage))
+}
+
+/// The AD8495's output voltage for the given measuring and cold junction temperatures. The inverse
+/// of [`ad8495_corrected`], for simulating the amplifier.
+#[must_use]
+pub fn ad8495_output(temp: Temperature, cold_junction: Temperature) -> f32 {
+    let thermocouple_mv = emf_from_temperature(temp) - emf_from_temperature(cold_junction);
+    let amplified_mv =
+        thermocouple_mv + AD8495_COLD_JUNCTION_MV_PER_DEGREE * cold_junction.celsius();
+    AD8495_REFERENCE_VOLTAGE + amplified_mv * AD8495_GAIN / 1000.
+}
+
+/// Output at or above this is the AD8495 pinned to its positive rail, which it does when the
+/// thermocouple is disconnected
+const OPEN_CIRCUIT_VOLTAGE: f32 = 3.2;
+/// Output at or below this is pinned to ground, which takes a thermocouple shorted to ground
+const SHORTED_VOLTAGE: f32 = 0.1;
+/// Below this no kitchen oven can be, so the probe is broken
+const MIN_VALID_TEMP: Temperature = Temperature::from_celsius(-12.2); // 10F
+/// At or above this no kitchen oven can be, so the probe is broken
+const MAX_VALID_TEMP: Temperature = Temperature::from_celsius(315.6); // 600F
+/// A real oven can't change this much (C) between samples a second apart, but a loose connection
+/// can. Opening the door drops the air temperature a few degrees a second at most.
+const MAX_STEP_PER_SAMPLE: f32 = 30.;
+/// Consecutive bad samples before we declare a fault
+const FAULT_DEBOUNCE_SAMPLES: u8 = 3;
+/// Consecutive good samples before we trust the thermocouple again
+const CLEAR_DEBOUNCE_SAMPLES: u8 = 3;
+
+/// Ways the thermocouple reading can't be trusted
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ThermocoupleFault {
+    /// The thermocouple is disconnected
+    Open,
+    /// The thermocouple is shorted to ground
+    Shorted,
+    /// Reading is colder than any kitchen
+    Low,
+    /// Reading is hotter than any oven
+    High,
+    /// Readings are jumping around faster than an oven can change
+    Erratic,
+}
+
+impl ThermocoupleFault {
+    /// The four character code shown on the display and reported over serial
+    #[must_use]
+    pub const fn code(self) -> &'static str {
+        match self {
+            ThermocoupleFault::Open => "OPEN",
+            ThermocoupleFault::Shorted => "SHRT",
+            ThermocoupleFault::Low => "LO  ",
+            ThermocoupleFault::High => "HI  ",
+            ThermocoupleFault::Erratic => "ERR!",
+        }
+    }
+
+    /// Classifies a single AD8495 output sample
+    ///
+    /// # Arguments
+    /// * `voltage`: The AD8495's output voltage
+    /// * `previous`: The previous sample's voltage, if there is one
+    ///
+    /// # Returns
+    /// the fault the sample shows, if any
+    #[must_use]
+    pub fn classify(voltage: f32, previous: Option<f32>) -> Option<Self> {
+        let temp = ad8495_temperature(voltage);
+        if voltage >= OPEN_CIRCUIT_VOLTAGE {
+            Some(ThermocoupleFault::Open)
+        } else if voltage <= SHORTED_VOLTAGE {
+            Some(ThermocoupleFault::Shorted)
+        } else if temp < MIN_VALID_TEMP {
+            Some(ThermocoupleFault::Low)
+        } else if temp >= MAX_VALID_TEMP {
+            Some(ThermocoupleFault::High)
+        } else if previous.is_some_and(|previous| {
+            (temp.celsius() - ad8495_temperature(previous).celsius()).abs() > MAX_STEP_PER_SAMPLE
+        }) {
+            Some(ThermocoupleFault::Erratic)
+        } else {
+            None
+        }
+    }
+}
+
+/// Whether the thermocouple's readings can be trusted, according to a [`FaultDetector`]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ProbeStatus {
+    /// The reading is good
+    Good,
+    /// The reading is bad, but not for long enough to call it a fault. Skip it.
+    Suspect,
+    /// The thermocouple is faulted
+    Faulted(ThermocoupleFault),
+}
+
+/// Debounces [`ThermocoupleFault`]s so one glitchy sample doesn't flap the display
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct FaultDetector {
+    previous: Option<f32>,
+    fault: Option<ThermocoupleFault>,
+    /// Consecutive bad samples while good, or good samples while faulted
+    count: u8,
+}
+
+impl FaultDetector {
+    /// Creates a detector that hasn't seen any samples
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            previous: None,
+            fault: None,
+            count: 0,
+        }
+    }
+
+    /// The currently declared fault, if any
+    #[must_use]
+    pub fn fault(&self) -> Option<ThermocoupleFault> {
+        self.fault
+    }
+
+    /// Checks the next AD8495 output sample
+    pub fn check(&mut self, voltage: f32) -> ProbeStatus {
+        let sample_fault = ThermocoupleFault::classify(voltage, self.previous);
+        // Only judge the rate of change between in-range samples, so coming back from a fault
+        // isn't itself a jump
+        self.previous = match sample_fault {
+            None | Some(ThermocoupleFault::Erratic) => Some(voltage),
+            Some(_) => None,
+        };
+
+        match (self.fault, sample_fault) {
+            (None, None) => {
+                self.count = 0;
+                ProbeStatus::Good
+            }
+            (None, Some(fault)) => {
+                self.count += 1;
+                if self.count >= FAULT_DEBOUNCE_SAMPLES {
+                    self.count = 0;
+                    self.fault = Some(fault);
+                    ProbeStatus::Faulted(fault)
+                } else {
+                    ProbeStatus::Suspect
+                }
+            }
+            (Some(_), Some(fault)) => {
+                // Still faulted, but report what it looks like now
+                self.count = 0;
+                self.fault = Some(fault);
+                ProbeStatus::Faulted(fault)
+            }
+            (Some(fault), None) => {
+                self.count += 1;
+                if self.count >= CLEAR_DEBOUNCE_SAMPLES {
+                    self.count = 0;
+                    self.fault = None;
+                    ProbeStatus::Good
+                } else {
+                    ProbeStatus::Faulted(fault)
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn c(celsius: f32) -> Temperature {
+        Temperature::from_celsius(celsius)
+    }
+
+    /// Excerpts of the NIST ITS-90 type K reference table, (C, mV)
+    const NIST_TABLE: [(f32, f32); 14] = [
+        (-200., -5.891),
+        (-100., -3.554),
+        (0., 0.000),
+        (25., 1.000),
+        (100., 4.096),
+        (150., 6.138),
+        (200., 8.138),
+        (250., 10.153),
+        (300., 12.209),
+        (400., 16.397),
+        (500., 20.644),
+        (600., 24.905),
+        (1000., 41.276),
+        (1370., 54.819),
+    ];
+
+    #[test]
+    fn emf_matches_nist_table() {
+        for (celsius, mv) in NIST_TABLE {
+            let emf = emf_from_temperature(c(celsius));
+            assert!((emf - mv).abs() < 0.002, "{}C: {} != {}", celsius, emf, mv);
+        }
+    }
+
+    #[test]
+    fn inverse_matches_nist_table() {
+        for (celsius, mv) in NIST_TABLE {
+            let temp = temperature_from_emf(mv).unwrap().celsius();
+            // The inverse polynomials are good to 0.06C, and the table is rounded to 1 uV
+            assert!(
+                (temp - celsius).abs() < 0.1,
+                "{}mV: {} != {}",
+                mv,
+                temp,
+                celsius
+            );
+        }
+        assert!(temperature_from_emf(-6.).is_none());
+        assert!(temperature_from_emf(55.).is_none());
+    }
+
+    #[test]
+    fn amplifier_round_trip() {
+        for celsius in (-150..1200).step_by(25) {
+            for cold_junction in [c(15.), c(25.), c(40.)] {
+                let voltage = ad8495_output(c(celsius as f32), cold_junction);
+                let temp = ad8495_corrected(voltage, cold_junction).unwrap().celsius();
+                assert!(
+                    (temp - celsius as f32).abs() < 0.1,
+                    "{} != {}",
+                    temp,
+                    celsius
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn corrects_linear_drift() {
+        // The linear formula is close at room temperature, but drifts at oven temperatures
+        let room = ad8495_output(c(25.), DEFAULT_COLD_JUNCTION);
+        assert!((ad8495_linear(room).celsius() - 25.).abs() < 0.5);
+        for (celsius, drift) in [(400., 1.5), (600., 10.)] {
+            let voltage = ad8495_output(c(celsius), DEFAULT_COLD_JUNCTION);
+            let linear_error = ad8495_linear(voltage).celsius() - celsius;
+            assert!(linear_error > drift, "{}C: {}", celsius, linear_error);
+            assert!((ad8495_temperature(voltage).celsius() - celsius).abs() < 0.1);
+        }
+
+        // AD8495 datasheet: 100C reads 0.504v above the reference at a 25C ambient
+        assert!((ad8495_temperature(1.25 + 0.504).celsius() - 100.).abs() < 0.3);
+    }
+
+    #[test]
+    fn falls_back_to_linear_out_of_range() {
+        // Output pinned at ground is below the thermocouple's range
+        assert_eq!(ad8495_temperature(0.), ad8495_linear(0.));
+        assert!(ad8495_corrected(0., DEFAULT_COLD_JUNCTION).is_none());
+    }
+
+    /// AD8495 output at the given temperature, with the board at room temperature
+    fn volts(celsius: f32) -> f32 {
+        ad8495_output(c(celsius), DEFAULT_COLD_JUNCTION)
+    }
+
+    #[test]
+    fn classifies_faults() {
+        use ThermocoupleFault::*;
+        let classify = ThermocoupleFault::classify;
+        assert_eq!(classify(volts(20.), None), None);
+        assert_eq!(classify(volts(290.), Some(volts(289.))), None);
+        assert_eq!(classify(3.3, None), Some(Open));
+        assert_eq!(classify(0.0, None), Some(Shorted));
+        assert_eq!(classify(volts(-30.), None), Some(Low));
+        assert_eq!(classify(volts(350.), None), Some(High));
+        assert_eq!(classify(volts(200.), Some(volts(20.))), Some(Erratic));
+        assert_eq!(classify(volts(20.), Some(volts(200.))), Some(Erratic));
+        // A cold kitchen is fine
+        assert_eq!(classify(volts(2.), None), None);
+    }
+
+    #[test]
+    fn fault_codes_fit_the_display() {
+        use ThermocoupleFault::*;
+        for fault in [Open, Shorted, Low, High, Erratic] {
+            assert_eq!(fault.code().len(), 4);
+        }
+    }
+
+    #[test]
+    fn debounces_faults() {
+        let mut detector = FaultDetector::new();
+        assert_eq!(detector.check(volts(200.)), ProbeStatus::Good);
+
+        // A single glitch is skipped, not a fault
+        assert_eq!(detector.check(3.3), ProbeStatus::Suspect);
+        assert_eq!(detector.check(volts(200.)), ProbeStatus::Good);
+        assert_eq!(detector.check(volts(20.)), ProbeStatus::Suspect);
+        assert_eq!(detector.check(volts(200.)), ProbeStatus::Suspect);
+        assert_eq!(detector.check(volts(199.)), ProbeStatus::Good);
+        assert_eq!(detector.fault(), None);
+
+        // A loose connection
+        for voltage in [volts(20.), volts(200.)] {
+            assert_eq!(detector.check(voltage), ProbeStatus::Suspect);
+        }
+        assert_eq!(
+            detector.check(volts(20.)),
+            ProbeStatus::Faulted(ThermocoupleFault::Erratic)
+        );
+        for _ in 0..3 {
+            detector.check(volts(20.));
+        }
+        assert_eq!(detector.fault(), None);
+
+        // Unplugged
+        assert_eq!(detector.check(3.3), ProbeStatus::Suspect);
+        assert_eq!(detector.check(3.3), ProbeStatus::Suspect);
+        let open = ProbeStatus::Faulted(ThermocoupleFault::Open);
+        assert_eq!(detector.check(3.3), open);
+        assert_eq!(detector.fault(), Some(ThermocoupleFault::Open));
+
+        // Plugged back in: the fault holds until we've seen a few good samples
+        assert_eq!(detector.check(volts(150.)), open);
+        assert_eq!(detector.check(volts(150.)), open);
+        assert_eq!(detector.check(volts(150.)), ProbeStatus::Good);
+        assert_eq!(detector.fault(), None);
+    }
+
+    #[test]
+    fn fault_follows_what_the_probe_shows() {
+        let mut detector = FaultDetector::new();
+        for _ in 0..3 {
+            detector.check(0.);
+        }
+        assert_eq!(detector.fault(), Some(ThermocoupleFault::Shorted));
+        assert_eq!(
+            detector.check(3.3),
+            ProbeStatus::Faulted(ThermocoupleFault::Open)
+        );
+        // A good sample between bad ones doesn't count towards clearing
+        detector.check(volts(100.));
+        detector.check(volts(100.));
+        detector.check(3.3);
+        detector.check(volts(100.));
+        detector.check(volts(100.));
+        assert!(detector.fault().is_some());
+    }
+}
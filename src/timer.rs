@@ -0,0 +1,122 @@
+//! A kitchen countdown timer.
+//!
+//! The timer doesn't keep time itself: it's told the time whenever it's asked about, from the same
+//! monotonic clock the rest of the app uses.
+
+/// How long we alert the user for once the timer runs out, before giving up on them
+pub const TIMER_ALERT_MS: u64 = 60_000;
+
+/// What the timer is doing
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum TimerState {
+    /// Not counting down
+    Idle,
+    /// Counting down, with this long (ms) to go
+    Running { remaining_ms: u64 },
+    /// Ran out, and the user should be told
+    Expired,
+}
+
+/// A countdown timer
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct Timer {
+    /// When the countdown ends, if one's been started
+    ends_ms: Option<u64>,
+}
+
+impl Timer {
+    /// Creates a timer that isn't counting down
+    #[must_use]
+    pub const fn new() -> Self {
+        Self { ends_ms: None }
+    }
+
+    /// Starts counting down, replacing any countdown in progress
+    ///
+    /// # Arguments
+    /// * `duration_ms`: How long to count down for
+    /// * `now_ms`: The current time
+    pub fn start(&mut self, duration_ms: u64, now_ms: u64) {
+        self.ends_ms = Some(now_ms.saturating_add(duration_ms));
+    }
+
+    /// Stops counting down, or alerting if the timer has already run out
+    pub fn cancel(&mut self) {
+        self.ends_ms = None;
+    }
+
+    /// What the timer is doing at the given time
+    #[must_use]
+    pub fn state(&self, now_ms: u64) -> TimerState {
+        match self.ends_ms {
+            Some(ends_ms) if now_ms < ends_ms => TimerState::Running {
+                remaining_ms: ends_ms - now_ms,
+            },
+            Some(ends_ms) if now_ms - ends_ms < TIMER_ALERT_MS => TimerState::Expired,
+            _ => TimerState::Idle,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn counts_down() {
+        let mut timer = Timer::new();
+        assert_eq!(timer.state(0), TimerState::Idle);
+        timer.start(5 * 60_000, 10_000);
+        assert_eq!(
+            timer.state(10_000),
+            TimerState::Running {
+                remaining_ms: 300_000
+            }
+        );
+        assert_eq!(
+            timer.state(309_999),
+            TimerState::Running { remaining_ms: 1 }
+        );
+        assert_eq!(timer.state(310_000), TimerState::Expired);
+        assert_eq!(
+            timer.state(310_000 + TIMER_ALERT_MS - 1),
+            TimerState::Expired
+        );
+        // Nobody came, so stop alerting
+        assert_eq!(timer.state(310_000 + TIMER_ALERT_MS), TimerState::Idle);
+    }
+
+    #[test]
+    fn cancels() {
+        let mut timer = Timer::new();
+        timer.start(1_000, 0);
+        timer.cancel();
+        assert_eq!(timer.state(500), TimerState::Idle);
+
+        // Including once it's alerting
+        timer.start(1_000, 0);
+        assert_eq!(timer.state(2_000), TimerState::Expired);
+        timer.cancel();
+        assert_eq!(timer.state(2_000), TimerState::Idle);
+    }
+
+    #[test]
+    fn restarts() {
+        let mut timer = Timer::new();
+        timer.start(60_000, 0);
+        timer.start(10_000, 30_000);
+        assert_eq!(
+            timer.state(30_000),
+            TimerState::Running {
+                remaining_ms: 10_000
+            }
+        );
+        timer.start(u64::MAX, 30_000);
+        assert_eq!(
+            timer.state(30_000),
+            TimerState::Running {
+                remaining_ms: u64::MAX - 30_000
+            }
+        );
+    }
+}
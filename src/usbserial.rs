@@ -2,7 +2,9 @@
 
 extern crate feather_m0 as bsp;
 
+use crate::command::{Line, LineBuffer};
 use bsp::hal;
+use core::ptr::addr_of_mut;
 use cortex_m::peripheral::NVIC;
 use hal::clock::GenericClockController;
 use hal::pac::{interrupt, PM, USB};
@@ -11,6 +13,9 @@ use usb_device::bus::UsbBusAllocator;
 use usb_device::prelude::*;
 use usbd_serial::{SerialPort, USB_CLASS_CDC};
 
+/// Longest command line we accept
+pub const MAX_LINE_LEN: usize = 64;
+
 pub struct USBSerial {
     usb_bus: UsbDevice<'static, UsbBus>,
     usb_serial: SerialPort<'static, UsbBus>,
@@ -18,6 +23,8 @@ pub struct USBSerial {
 
 static mut USB_SERIAL: Option<USBSerial> = None;
 static mut BUS_ALLOCATOR: Option<UsbBusAllocator<UsbBus>> = None;
+/// Bytes received from the host, filled in by the USB interrupt
+static mut LINE_BUFFER: LineBuffer<MAX_LINE_LEN> = LineBuffer::new();
 
 impl USBSerial {
     /// Initializes the `USBSerial` singleton.
@@ -80,6 +87,12 @@ impl USBSerial {
         }
     }
 
+    /// Takes the last complete line received over USB serial, if there is one
+    pub fn read_line() -> Option<Line<MAX_LINE_LEN>> {
+        // Keep the USB interrupt from adding to the buffer while we take from it
+        cortex_m::interrupt::free(|_| unsafe { (*addr_of_mut!(LINE_BUFFER)).take_line() })
+    }
+
     /// Polls the USB peripheral, reading out whatever bytes are available
     ///
     /// # Arguments
@@ -111,7 +124,38 @@ impl USBSerial {
 #[interrupt]
 fn USB() {
     let mut read_buf: [u8; 64] = [0u8; 64];
-    USBSerial::poll_usb(&mut read_buf);
+    let bytes_read = USBSerial::poll_usb(&mut read_buf);
+    unsafe { (*addr_of_mut!(LINE_BUFFER)).push(&read_buf[..bytes_read]) };
+}
+
+/// Formats a number with a fixed number of decimal places, as `ufmt` doesn't do floats
+pub struct Decimal(pub f32, pub u32);
+
+impl ufmt::uDisplay for Decimal {
+    fn fmt<W>(&self, f: &mut ufmt::Formatter<'_, W>) -> Result<(), W::Error>
+    where
+        W: ufmt::uWrite + ?Sized,
+    {
+        let Decimal(value, places) = *self;
+        let scale = 10_u32.pow(places);
+        let scaled = (value.abs() * scale as f32 + 0.5) as u32;
+        if value < 0. && scaled != 0 {
+            f.write_str("-")?;
+        }
+        ufmt::uwrite!(f, "{}", scaled / scale)?;
+        if places > 0 {
+            f.write_str(".")?;
+            let fraction = scaled % scale;
+            // Pad with leading zeros
+            let mut digit = scale / 10;
+            while digit > 1 && fraction < digit {
+                f.write_str("0")?;
+                digit /= 10;
+            }
+            ufmt::uwrite!(f, "{}", fraction)?;
+        }
+        Ok(())
+    }
 }
 
 /// Writes the given message out over USB serial.